@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::Bundle;
+
+/// Splits `files` into bundles (primary first, then its companions) and the
+/// files that belong to none.
+///
+/// Files in the same folder form a bundle when their extensions are in the
+/// same group and their stems match: `IMG_001.xmp`, `IMG_001.CR2.xmp` and
+/// `movie.en.srt` are companions of `IMG_001.jpg` and `movie.mkv`.
+pub fn group(files: Vec<PathBuf>, groups: &[Bundle]) -> (Vec<Vec<PathBuf>>, Vec<PathBuf>) {
+    if groups.is_empty() {
+        return (Vec::new(), files);
+    }
+
+    let mut by_dir: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        by_dir.entry(file.parent().unwrap_or(Path::new("")).to_path_buf()).or_default().push(file);
+    }
+
+    let mut bundles = Vec::new();
+    let mut loose = Vec::new();
+    for (_, mut files) in by_dir {
+        for group in groups {
+            let (members, rest): (Vec<_>, Vec<_>) = files.into_iter().partition(|file| rank(group, file).is_some());
+            files = rest;
+
+            for bundle in bundle_stems(group, members) {
+                if bundle.len() > 1 {
+                    bundles.push(bundle);
+                } else {
+                    loose.extend(bundle);
+                }
+            }
+        }
+        loose.extend(files);
+    }
+    (bundles, loose)
+}
+
+/// Groups the files of one folder and one group by stem, primary first.
+fn bundle_stems(group: &Bundle, mut members: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+    // Shorter stems first, so that `IMG_001` is the base `IMG_001.CR2.xmp` attaches to.
+    members.sort_by_key(|file| (stem(file).len(), rank(group, file)));
+
+    let mut bundles: Vec<(String, Vec<PathBuf>)> = Vec::new();
+    for file in members {
+        let file_stem = stem(&file);
+        let base = bundles.iter_mut().find(|(base, _)| {
+            file_stem == *base || file_stem.strip_prefix(base.as_str()).is_some_and(|rest| rest.starts_with('.'))
+        });
+        match base {
+            Some((_, bundle)) => bundle.push(file),
+            None => bundles.push((file_stem, vec![file])),
+        }
+    }
+
+    bundles
+        .into_iter()
+        .map(|(_, mut bundle)| {
+            bundle.sort_by_key(|file| (rank(group, file), file.clone()));
+            bundle
+        })
+        .collect()
+}
+
+/// The position of the file's extension in the group; lower is more primary.
+fn rank(group: &Bundle, file: &Path) -> Option<usize> {
+    let extension = file.extension()?.to_str()?.to_ascii_lowercase();
+    group.extensions.iter().position(|ext| *ext == extension)
+}
+
+fn stem(file: &Path) -> String {
+    file.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn photos() -> Bundle {
+        Bundle { name: "photos".to_string(), extensions: vec!["jpg".to_string(), "cr2".to_string(), "xmp".to_string()] }
+    }
+
+    #[test]
+    fn groups_companions_with_their_primary() {
+        let files = paths(&["a/IMG_1.xmp", "a/IMG_1.CR2.xmp", "a/IMG_1.CR2", "a/IMG_1.jpg", "a/IMG_2.jpg", "a/notes.txt"]);
+        let (bundles, loose) = group(files, &[photos()]);
+        assert_eq!(bundles, [paths(&["a/IMG_1.jpg", "a/IMG_1.CR2", "a/IMG_1.CR2.xmp", "a/IMG_1.xmp"])]);
+        assert_eq!(loose, paths(&["a/IMG_2.jpg", "a/notes.txt"]));
+    }
+
+    #[test]
+    fn only_groups_files_in_the_same_folder() {
+        let (bundles, mut loose) = group(paths(&["a/IMG_1.jpg", "b/IMG_1.xmp"]), &[photos()]);
+        loose.sort();
+        assert!(bundles.is_empty());
+        assert_eq!(loose, paths(&["a/IMG_1.jpg", "b/IMG_1.xmp"]));
+    }
+
+    #[test]
+    fn without_groups_every_file_is_loose() {
+        let files = paths(&["IMG_1.jpg", "IMG_1.xmp"]);
+        assert_eq!(group(files.clone(), &[]), (Vec::new(), files));
+    }
+}
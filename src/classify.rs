@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Category, Config, Detect};
+use crate::plan::SkipReason;
+use crate::sniff::{self, Detected};
+use crate::template::{Template, Vars};
+
+pub struct Classifier<'a> {
+    root: PathBuf,
+    categories: &'a [Category],
+    by_extension: HashMap<&'a str, usize>,
+    sniff: bool,
+    fix_extensions: bool,
+    /// Replaces every category's folder.
+    folder: Option<Template>,
+}
+
+#[derive(Debug)]
+pub struct Classification<'a> {
+    pub category: &'a Category,
+    /// The extension the file was classified by: its own, or the detected one.
+    pub kind: String,
+    pub destination: PathBuf,
+}
+
+impl<'a> Classifier<'a> {
+    pub fn new(config: &'a Config, root: &Path, fix_extensions: bool) -> Classifier<'a> {
+        let mut by_extension = HashMap::new();
+        for (index, category) in config.categories.iter().enumerate() {
+            for extension in &category.extensions {
+                by_extension.insert(extension.as_str(), index);
+            }
+        }
+        Classifier {
+            root: root.to_path_buf(),
+            categories: &config.categories,
+            by_extension,
+            sniff: fix_extensions || config.uses_content(),
+            fix_extensions,
+            folder: None,
+        }
+    }
+
+    /// Puts the files of every category into `folder` instead of the category's own.
+    pub fn with_folder(mut self, folder: Option<Template>) -> Classifier<'a> {
+        self.folder = folder;
+        self
+    }
+
+    pub fn folder<'b>(&'b self, category: &'b Category) -> &'b Template {
+        self.folder.as_ref().unwrap_or(&category.folder)
+    }
+
+    /// The fixed part of every category folder, e.g. `images` for `images/{mtime:%Y}`.
+    pub fn category_dirs(&self) -> Vec<PathBuf> {
+        let folders: Vec<&Template> = match &self.folder {
+            Some(folder) => vec![folder],
+            None => self.categories.iter().map(|category| &category.folder).collect(),
+        };
+        folders
+            .into_iter()
+            .map(Template::prefix)
+            .filter(|prefix| !prefix.as_os_str().is_empty())
+            .map(|prefix| self.root.join(prefix))
+            .collect()
+    }
+
+    pub fn classify(&self, path: &Path) -> Result<Classification<'a>, SkipReason> {
+        let extension = path.extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let detected = if self.sniff { sniff::sniff(path).ok().flatten() } else { None };
+
+        let by_extension = self.lookup(&extension);
+        let by_content = detected.and_then(|d| self.lookup(d.ext));
+        // A name about to be fixed is classified by what it will become.
+        let misnamed = detected.is_some_and(|d| self.is_misnamed(&extension, d));
+
+        let (category, kind) = match (by_extension, by_content) {
+            (_, Some(content)) if content.detect == Detect::Content || misnamed => (content, detected.unwrap().ext.to_string()),
+            (Some(ext), _) if ext.detect == Detect::Content && detected.is_some_and(|d| !d.matches_extension(&extension)) => {
+                return Err(SkipReason::ContentMismatch { extension, detected: detected.unwrap().ext.to_string() });
+            }
+            (Some(ext), _) => (ext, extension.clone()),
+            (None, Some(content)) if content.detect == Detect::Fallback => (content, detected.unwrap().ext.to_string()),
+            _ if extension.is_empty() => return Err(SkipReason::NoExtension),
+            _ => return Err(SkipReason::Unmapped(extension)),
+        };
+
+        let file_name = match detected {
+            Some(detected) if misnamed => fixed_name(path, &extension, detected),
+            _ => path.file_name().unwrap_or_default().to_os_string(),
+        };
+        let vars = Vars { path, extension: &kind, category: &category.name, captures: HashMap::new() };
+        let folder = self.folder.as_ref().unwrap_or(&category.folder).render(&vars).map_err(SkipReason::Destination)?;
+        let destination = self.root.join(folder).join(file_name);
+        Ok(Classification { category, kind, destination })
+    }
+
+    /// The name `path` is renamed to in place when `--fix-extensions` finds
+    /// its content disagrees with its extension but no category takes it.
+    pub fn fixed_name(&self, path: &Path) -> Option<(&'static str, PathBuf)> {
+        if !self.fix_extensions {
+            return None;
+        }
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+        let detected = sniff::sniff(path).ok().flatten().filter(|&d| self.is_misnamed(&extension, d))?;
+        Some((detected.ext, path.with_file_name(fixed_name(path, &extension, detected))))
+    }
+
+    fn is_misnamed(&self, extension: &str, detected: Detected) -> bool {
+        self.fix_extensions && detected.fixable && !detected.matches_extension(extension)
+    }
+
+    fn lookup(&self, extension: &str) -> Option<&'a Category> {
+        self.by_extension.get(extension).map(|&index| &self.categories[index])
+    }
+}
+
+/// The file name of `path` with its extension replaced by the detected one.
+fn fixed_name(path: &Path, extension: &str, detected: Detected) -> OsString {
+    let file_name = path.file_name().unwrap_or_default().to_os_string();
+    let stem = if extension.is_empty() { file_name } else { path.file_stem().unwrap_or_default().to_os_string() };
+    let mut fixed = stem;
+    fixed.push(".");
+    fixed.push(detected.ext);
+    fixed
+}
@@ -0,0 +1,774 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::Shell;
+
+use tidyup::config::normalize_extension;
+use tidyup::conflict::OnConflict;
+use tidyup::dedup::OnDuplicate;
+use tidyup::template::Template;
+use tidyup::PlanOptions;
+
+use crate::output::{EventFormat, Format, Sink, Verbosity};
+
+const ABOUT: &str = "Groups the files in a directory into folders by type.";
+
+const LONG_ABOUT: &str = "\
+Groups and displays desktop items based on their type.
+
+The program groups the items of an input folder, or a list of items read with --from-file \
+or --stdin, by their type (e.g., images, extensions, shortcuts), and displays the groups \
+(`tidyup group`) or moves the items into a folder per group.
+
+Each item in the input file should be in the format NAME.EXTENSION, where NAME is the name \
+of the item and EXTENSION one of the extensions of a configured category.
+
+Without a subcommand, tidyup behaves like `tidyup run`.";
+
+const AFTER_LONG_HELP: &str = "\
+Undo:
+  Every run records its moves in a journal under $XDG_STATE_HOME/tidyup/
+  (default ~/.local/state/tidyup/). `tidyup undo` moves the files of the most
+  recent run back, or of RUN_ID if given, and removes the category directories
+  that run created once they are empty. Files that changed since they were
+  moved are left in place and reported. Files replaced by --on-conflict overwrite,
+  keep-newer or keep-larger cannot be brought back.
+
+Configuration:
+  Categories are read from the first tidyup.toml found in $XDG_CONFIG_HOME/tidyup/
+  (default ~/.config/tidyup/) or in DIRECTORY, unless --config is given. Each
+  category names a destination folder and the extensions it collects:
+
+    [[category]]
+    name = \"images\"
+    folder = \"images\"
+    extensions = [\"png\", \"jpg\", \"jpeg\"]
+
+  Without a config file the built-in mapping above plus python (py) and c++ (cpp)
+  is used; `tidyup config default` prints it.
+
+  A category may also set `detect` to decide membership by file content (magic bytes)
+  for PNG, JPEG, GIF, WebP, PDF, ZIP/OOXML, gzip, ELF, MP3, MP4, shebang scripts and more:
+    detect = \"extension\"   the extension alone (default)
+    detect = \"content\"     the detected type wins over the extension
+    detect = \"fallback\"    the detected type is used when the extension is missing or unmapped
+
+Rules:
+  [[rule]] tables match files by more than their extension. A file matched by a rule
+  is handled by it instead of by its category:
+
+    [[rule]]
+    name = \"old screenshots\"
+    priority = 10                     # higher runs first (default 0)
+    destination = \"archive/screens\"  # action = \"move\" (default) needs one
+    when = { name = \"Screenshot*\", mtime = \">30d\", not = { hidden = true } }
+
+  Conditions: name (glob), regex, extension (list), size (\">10M\", \"1K..1G\"),
+  mtime / ctime age (\">7d\", \"<2h\"), uid, gid, mode (exact octal bits), mode_all,
+  mode_any, hidden, the EXIF conditions make / model (globs, any case), taken (age of
+  the capture date, else of the mtime), orientation and gps (true if the photo has a
+  position), and the combinators all = [...], any = [...], not = {...}; the
+  keys of one table must all match.
+
+  Besides \"move\", action = \"copy\", \"symlink\" or \"hardlink\" leave the original in
+  place and put a copy or link at the destination; symlinks are relative unless the rule
+  sets symlink = \"absolute\", hard links fall back to a copy across filesystems, and
+  copies keep permissions, owner, times and extended attributes. Files already copied or
+  linked there are left alone. action = \"skip\" leaves matching files alone and
+  action = \"trash\" puts them in the trash, e.g. installers older than a month:
+    when = { extension = [\"deb\", \"dmg\", \"msi\"], mtime = \">30d\" }, action = \"trash\"
+  The top-level `match = \"first\"` (default) lets the first matching rule decide;
+  `match = \"all\"` applies every matching rule until one moves, skips or trashes the
+  file, so a file can be copied or linked to several places.
+
+Destination templates:
+  Category folders and rule destinations may contain placeholders, e.g.
+  folder = \"images/{mtime:%Y}/{mtime:%m}\" or destination = \"invoices/{regex.1}\":
+    {category}  category or rule name     {ext}    extension (\"noext\" if none)
+    {stem}      file name without extension
+    {mtime:FMT} / {ctime:FMT}  strftime date (default %Y-%m-%d)
+    {size}      empty, tiny (<100K), small (<10M), medium (<1G) or large
+    {owner} / {group}  owning user and group
+    {regex.N} / {regex.NAME}  capture group of the rule's regex condition
+    {taken:FMT} EXIF capture date in the camera's offset, else the mtime
+    {make} / {model} / {camera} / {orientation} / {gps}  EXIF fields (\"unknown\" if absent)
+  EXIF is read from JPEG, HEIC/AVIF and TIFF-based RAW files (DNG, CR2, NEF, ARW, ...).
+  Expanded values never contain `/`, `.` or `..`, so files stay below DIRECTORY.
+  Write {{ and }} for literal braces.
+
+Bundles:
+  [[bundle]] tables keep sidecar files with the file they describe. Files in one folder
+  whose extensions are in the same bundle and whose names share a stem move together:
+
+    [[bundle]]
+    name = \"raw + sidecars\"
+    extensions = [\"cr2\", \"jpg\", \"xmp\"]   # the first one present is the primary
+
+  IMG_1.cr2, IMG_1.jpg, IMG_1.xmp and IMG_1.cr2.xmp go wherever IMG_1.cr2 is classified,
+  keep a shared name when the primary is renamed on conflict, and are all put back if
+  one of them cannot be moved. A lone file is handled like any other. The built-in
+  configuration has no bundles, so every file is classified on its own.
+
+Duplicates:
+  --dedup compares each file with the files already in its destination folder and with
+  the other files being tidied: by size, then a hash of the first 64 KiB, then a BLAKE3
+  hash of the whole file. The first copy found is kept; for each other copy
+    --dedup skip      leaves it where it is
+    --dedup trash     moves it to the trash
+    --dedup hardlink  replaces it with a hard link to the kept copy
+    --dedup report    moves it as usual and lists it as a duplicate
+  `tidyup undo` turns a hard link made this way back into a separate copy.
+
+Trash:
+  Nothing is deleted outright. Trashed files go to the freedesktop.org trash with a
+  .trashinfo file recording where they came from and when, so the desktop can restore
+  them: ~/.local/share/Trash for files on that filesystem, .Trash/UID or .Trash-UID at
+  the top of other mounts. `tidyup undo` puts them back as well.
+
+Views:
+  `tidyup view --into DIR` classifies DIRECTORY as a run would but leaves every file in
+  place and puts a symlink to it into DIR instead, relative unless --absolute is given.
+  --folder replaces the category folders, so several views of the same files can sit
+  side by side:
+    tidyup view -d ~/Photos --into ~/Views/by-type
+    tidyup view -d ~/Photos --into ~/Views/by-year --folder '{mtime:%Y}'
+  Running it again only adds the missing links; the links it made itself, which it lists
+  in DIR/.tidyup-view, are removed once their file is gone or now belongs elsewhere.
+  Other links and files in DIR are left alone. Rules that trash files only skip them here.
+
+Input lists:
+  --from-file LIST and --stdin replace the scan of DIRECTORY with a list of files, one per
+  line or separated by NUL bytes as `find -print0` prints them. Relative entries are
+  taken to be below DIRECTORY, folders are not descended into, and --exclude and the
+  other filters still apply. `tidyup group` prints the grouping without moving anything;
+  items of a list that do not exist are grouped by their name alone.
+
+Watch:
+  `tidyup watch` keeps running and tidies DIRECTORY whenever inotify reports a change
+  (polling where inotify is unavailable, or with --poll). A file is only moved once its
+  size and mtime have been stable for --settle and no NAME.part / NAME.crdownload
+  sibling exists, so downloads in progress are left alone.
+
+Reviewing:
+  --interactive (-I) asks before each action: y does it, n leaves the file alone, a does
+  it and the rest of its category without asking, s leaves the rest of the category
+  alone, q leaves everything that is left alone. --review shows the whole plan in a
+  full-screen list grouped by destination folder: up/down (or j/k) move, space switches
+  an entry off or on, e (or enter) changes its destination, a applies what is selected
+  and q quits without changing anything. Both also work with `tidyup plan`, -n and -o.
+
+Plan files:
+  `tidyup plan -o FILE` writes the plan instead of printing it: a header line and then one
+  JSON object per action, with absolute paths and the size, mtime and inode of each source.
+  Delete the lines of actions that should not happen or change their destination, then
+  `tidyup apply FILE` carries out what is left. Actions whose source changed since the plan
+  was made are refused and reported; the rest are journaled like a run and can be undone.
+  Lines starting with # are ignored.
+
+Machine-readable output:
+  --format json prints one JSON document instead of text: for `tidyup plan` (or -n) the
+  planned actions with \"type\": \"plan\", otherwise the result with \"type\": \"result\",
+  the run id, the counts and every event. --events ndjson instead writes one JSON object
+  per line as things happen: started, scanned, classified (one per entry), created_dir,
+  moved, copied, trashed, linked, unlinked, kept, skipped, failed, removed_dir and a
+  final summary; `tidyup watch --events ndjson` writes a summary after every pass.
+  Skip reasons have a stable \"reason\" code next to the \"message\" meant for people.
+  Documents and the started event carry \"version\": 1, which only changes when a field
+  is renamed, removed or changes meaning.
+
+Explaining:
+  `tidyup explain PATH...` traces the decision for each PATH without changing anything:
+  whether a scan of DIRECTORY reaches it (--exclude, category folders, --max-depth), its
+  extension and detected content type, every rule in evaluation order with each condition
+  marked [x] or [ ] next to the file's actual value, the category when no rule decides,
+  the --extensions / --ignore filters, the destination template and the path it renders
+  to, a file with the same content in that folder (with --dedup), and what --on-conflict
+  (default rename) would do if that path is taken. It takes the same scan options as a
+  run, so pass the ones the run would use.
+
+Exit status:
+  0  everything was tidied (or there was nothing to do)
+  1  the run finished, but some files could not be read, moved or restored
+  2  invalid command line or plan file
+  3  the configuration file is invalid
+  4  fatal error, e.g. DIRECTORY cannot be read or the undo journal cannot be written
+
+Examples:
+  tidyup -d DESKTOP
+    Reads items from 'DESKTOP', groups them, and writes the results to appropriate folders.
+
+  tidyup plan -d DESKTOP
+    Shows what would be moved where, without touching DESKTOP.
+
+  tidyup -d ~/Downloads -r --max-depth 3 -x '*.part' -x node_modules
+    Tidies up to three levels of subfolders, leaving partial downloads and node_modules alone.
+
+  tidyup --prune-empty
+    Tidies the current directory and removes category folders that ended up empty.
+
+  tidyup --dedup trash -d ~/Downloads
+    Tidies Downloads and trashes files that are already in their category folder.
+
+  tidyup view -r -d ~/Projects --into ~/Views/by-type
+    Links every file below Projects into ~/Views/by-type/<category>/ without moving it.
+
+  find ~/Desktop -newer ~/.last-tidy -print0 | tidyup --stdin -d ~/Desktop
+    Only tidies the files changed since ~/.last-tidy.
+
+  tidyup group --from-file items.txt
+    Prints the NAME.EXTENSION items listed in items.txt grouped by category.
+
+  tidyup --review -d ~/Desktop
+    Shows where everything on the Desktop would go and applies it once confirmed.
+
+  tidyup plan -d /srv/shared -o plan.json && $EDITOR plan.json && tidyup apply plan.json
+    Reviews and edits the moves before anything happens on a shared drive.
+
+  tidyup explain -d ~/Downloads ~/Downloads/report.pdf --on-conflict skip
+    Shows which rules report.pdf matches, where it would go and whether it would be skipped.
+
+  tidyup --events ndjson -d ~/Downloads | jq -c 'select(.event == \"moved\")'
+    Tidies Downloads and passes the moves on to another program as they happen.
+
+  tidyup -v -e png,jpg -e gif
+    Only moves images, listing every file that is moved.";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "tidyup",
+    version,
+    about = ABOUT,
+    long_about = LONG_ABOUT,
+    after_long_help = AFTER_LONG_HELP
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+impl Cli {
+    /// Parses the command line like [`Parser::parse`]. The global options go
+    /// before or after a subcommand, while the options of the default run are
+    /// only accepted without one.
+    pub fn parse_args() -> Cli {
+        let mut command = Cli::command();
+        let matches = command.get_matches_mut();
+        if let Some((name, _)) = matches.subcommand() {
+            let given = command.get_arguments().find(|arg| {
+                !arg.is_global_set() && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            });
+            if let Some(arg) = given {
+                let flag = match (arg.get_long(), arg.get_short()) {
+                    (Some(long), _) => format!("--{}", long),
+                    (None, Some(short)) => format!("-{}", short),
+                    (None, None) => arg.get_id().to_string(),
+                };
+                let message = format!("the subcommand '{}' cannot be used with '{}'", name, flag);
+                command.error(ErrorKind::ArgumentConflict, message).exit();
+            }
+        }
+        Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut command).exit())
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct GlobalArgs {
+    /// Read categories from FILE instead of searching for tidyup.toml.
+    #[arg(short, long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Show more details; repeat (-vv) for even more.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only print errors.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+}
+
+impl GlobalArgs {
+    pub fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, 0) => Verbosity::Normal,
+            (false, 1) => Verbosity::Verbose,
+            (false, _) => Verbosity::Debug,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Move files into their category folders (the default).
+    Run(RunArgs),
+    /// Print the moves a run would make, without changing anything.
+    Plan(RunArgs),
+    /// Carry out a plan written by `tidyup plan -o`, except what changed since.
+    Apply(ApplyArgs),
+    /// Keep running and tidy DIRECTORY whenever files arrive.
+    Watch(WatchArgs),
+    /// Build or refresh a tree of symlinks to the files of DIRECTORY, leaving them in place.
+    View(ViewArgs),
+    /// Move the files of a previous run back.
+    Undo {
+        /// The run to revert; defaults to the most recent run not yet undone.
+        run_id: Option<String>,
+    },
+    /// Count the files and bytes each category would receive.
+    Stats(ScanArgs),
+    /// Print the files grouped by category, without moving anything.
+    Group(GroupArgs),
+    /// Trace why each PATH would or would not be moved: how it is reached,
+    /// its type, every rule and condition, the destination and any conflict.
+    Explain {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+
+        /// The conflict policy to predict the outcome with.
+        #[arg(
+            long,
+            value_name = "POLICY",
+            default_value = "rename",
+            value_parser = PossibleValuesParser::new(OnConflict::NAMES).map(|s| s.parse::<OnConflict>().unwrap())
+        )]
+        on_conflict: OnConflict,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print a shell completion script.
+    Completions {
+        shell: Shell,
+    },
+    /// Print the man page (roff).
+    Man,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration that applies to DIRECTORY and list its categories.
+    Check {
+        #[arg(short, long, value_name = "DIRECTORY", default_value = ".")]
+        directory: PathBuf,
+    },
+    /// Print the built-in configuration.
+    Default,
+}
+
+/// Which files are considered and how they are classified.
+#[derive(Debug, Clone, Args)]
+pub struct SelectArgs {
+    /// The directory to tidy.
+    #[arg(short, long, value_name = "DIRECTORY", default_value = ".")]
+    pub directory: PathBuf,
+
+    /// Only consider these extensions (comma-separated or repeated).
+    #[arg(short, long, value_name = "EXT", value_delimiter = ',', value_parser = parse_extension)]
+    pub extensions: Vec<String>,
+
+    /// Ignore these extensions (comma-separated or repeated).
+    #[arg(short, long, value_name = "EXT", value_delimiter = ',', value_parser = parse_extension)]
+    pub ignore: Vec<String>,
+
+    /// Rename files whose content does not match their extension (e.g. a PNG
+    /// saved as download.bin) to the detected extension, and classify them by
+    /// it; files no category takes are renamed where they are.
+    #[arg(long)]
+    pub fix_extensions: bool,
+}
+
+impl SelectArgs {
+    pub fn plan_options(&self) -> PlanOptions {
+        PlanOptions {
+            extensions: self.extensions.clone(),
+            ignore: self.ignore.clone(),
+            fix_extensions: self.fix_extensions,
+            ..PlanOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ScanArgs {
+    #[command(flatten)]
+    pub select: SelectArgs,
+
+    /// Also tidy files in subdirectories (moved into the top-level category folders).
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Descend at most N levels below DIRECTORY (implies --recursive).
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(usize))]
+    pub max_depth: Option<usize>,
+
+    /// Skip files and folders matching GLOB, relative to DIRECTORY or by name.
+    #[arg(short = 'x', long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Also scan the category folders themselves when recursing.
+    #[arg(long)]
+    pub include_tidied: bool,
+
+    /// Descend into symlinked directories (each directory is visited once).
+    #[arg(short = 'L', long)]
+    pub follow_symlinks: bool,
+
+    /// Look for files whose content is already at their destination or elsewhere in
+    /// the scan, and skip, trash, hard-link or only report them.
+    #[arg(
+        long,
+        value_name = "POLICY",
+        value_parser = PossibleValuesParser::new(OnDuplicate::NAMES).map(|s| s.parse::<OnDuplicate>().unwrap())
+    )]
+    pub dedup: Option<OnDuplicate>,
+}
+
+impl ScanArgs {
+    pub fn max_depth(&self) -> usize {
+        match self.max_depth {
+            Some(depth) => depth.max(1),
+            None if self.recursive => usize::MAX,
+            None => 1,
+        }
+    }
+
+    pub fn plan_options(&self) -> PlanOptions {
+        PlanOptions {
+            max_depth: self.max_depth(),
+            exclude: self.exclude.clone(),
+            follow_symlinks: self.follow_symlinks,
+            include_tidied: self.include_tidied,
+            dedup: self.dedup,
+            ..self.select.plan_options()
+        }
+    }
+}
+
+/// An explicit list of files to use instead of scanning DIRECTORY.
+#[derive(Debug, Clone, Args)]
+pub struct InputArgs {
+    /// Only consider the files listed in LIST, one per line or NUL-separated
+    /// (as printed by `find -print0`); relative paths are below DIRECTORY.
+    #[arg(long, value_name = "LIST", conflicts_with = "stdin")]
+    pub from_file: Option<PathBuf>,
+
+    /// Read the list of files from standard input, like --from-file.
+    #[arg(long)]
+    pub stdin: bool,
+}
+
+impl InputArgs {
+    /// The listed files, or `None` if DIRECTORY is to be scanned.
+    pub fn files(&self) -> io::Result<Option<Vec<PathBuf>>> {
+        let list = match (&self.from_file, self.stdin) {
+            (Some(path), _) => {
+                fs::read(path).map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?
+            }
+            (None, true) => {
+                let mut list = Vec::new();
+                io::stdin().read_to_end(&mut list)?;
+                list
+            }
+            (None, false) => return Ok(None),
+        };
+        Ok(Some(parse_list(&list)))
+    }
+}
+
+/// Splits a file list at NULs if it contains any, at line ends otherwise.
+fn parse_list(list: &[u8]) -> Vec<PathBuf> {
+    let separator = if list.contains(&0) { 0 } else { b'\n' };
+    list.split(|&byte| byte == separator)
+        .map(|entry| entry.strip_suffix(b"\r").unwrap_or(entry))
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| PathBuf::from(OsStr::from_bytes(entry)))
+        .collect()
+}
+
+/// How plans, results and events are written.
+#[derive(Debug, Clone, Args)]
+pub struct OutputArgs {
+    /// Write the plan or the result as text or as one JSON document.
+    #[arg(long, value_name = "FORMAT", value_enum, default_value = "text")]
+    pub format: Format,
+
+    /// Stream every classification, move, skip and error as it happens, one JSON
+    /// object per line, instead of the text or JSON output.
+    #[arg(long, value_name = "FORMAT", value_enum, conflicts_with = "format")]
+    pub events: Option<EventFormat>,
+}
+
+impl OutputArgs {
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text && self.events.is_none()
+    }
+
+    pub fn sink(&self, verbosity: Verbosity) -> Sink {
+        Sink::new(self.format, self.events, verbosity)
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct MoveArgs {
+    /// What to do when the destination already exists.
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "rename",
+        value_parser = PossibleValuesParser::new(OnConflict::NAMES).map(|s| s.parse::<OnConflict>().unwrap())
+    )]
+    pub on_conflict: OnConflict,
+
+    /// When a move has to copy across filesystems, compare a BLAKE3 checksum
+    /// of the copy before deleting the original.
+    #[arg(long)]
+    pub verify_checksum: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[command(flatten)]
+    pub moves: MoveArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Print the planned moves, created directories and skipped files without changing anything.
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Ask before each move: yes, no, always or never for its category, or quit.
+    #[arg(short = 'I', long, conflicts_with_all = ["stdin", "review"])]
+    pub interactive: bool,
+
+    /// Review the plan in a full-screen list first: switch entries off, change
+    /// destinations, then apply.
+    #[arg(long, conflicts_with = "stdin")]
+    pub review: bool,
+
+    /// Write the plan to FILE for review and `tidyup apply` instead of carrying it out.
+    #[arg(short = 'o', long = "output", value_name = "FILE")]
+    pub plan_file: Option<PathBuf>,
+
+    /// Afterwards, remove category folders (and folders inside them) that are empty,
+    /// e.g. left behind by earlier runs or undos.
+    #[arg(long)]
+    pub prune_empty: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ApplyArgs {
+    /// The plan file written by `tidyup plan -o`.
+    #[arg(value_name = "PLAN")]
+    pub plan: PathBuf,
+
+    #[command(flatten)]
+    pub moves: MoveArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    /// Print what would still be carried out and what changed, without changing anything.
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct GroupArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    #[command(flatten)]
+    pub input: InputArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct WatchArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    #[command(flatten)]
+    pub moves: MoveArgs,
+
+    /// How long a file's size and mtime must stay unchanged before it is moved.
+    #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = parse_duration)]
+    pub settle: Duration,
+
+    /// How long to wait after the last event before looking at the directory.
+    #[arg(long, value_name = "DURATION", default_value = "500ms", value_parser = parse_duration)]
+    pub debounce: Duration,
+
+    /// Stream every classification, move, skip and error as it happens, one JSON
+    /// object per line, instead of text.
+    #[arg(long, value_name = "FORMAT", value_enum)]
+    pub events: Option<EventFormat>,
+
+    /// Poll instead of using inotify (done automatically where inotify is unavailable).
+    #[arg(long)]
+    pub poll: bool,
+
+    /// How often to look at the directory when polling.
+    #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_duration)]
+    pub poll_interval: Duration,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ViewArgs {
+    #[command(flatten)]
+    pub scan: ScanArgs,
+
+    /// The folder to build the view in, outside or inside DIRECTORY.
+    #[arg(long, value_name = "DIR", required = true)]
+    pub into: PathBuf,
+
+    /// Put the links into FOLDER instead of their category folder, e.g. `{mtime:%Y}`.
+    #[arg(long, value_name = "TEMPLATE", value_parser = Template::parse)]
+    pub folder: Option<Template>,
+
+    /// Point the links at absolute paths instead of relative ones.
+    #[arg(long)]
+    pub absolute: bool,
+
+    /// Print the links that would be added and removed without changing anything.
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// Parses `500ms`, `2s`, `1.5m` or a bare number of seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.trim().parse().map_err(|_| format!("invalid duration `{}`", value))?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("invalid duration unit `{}` (expected ms, s, m or h)", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("invalid duration `{}`: {}", value, e))
+}
+
+fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = normalize_extension(value);
+    if extension.is_empty() {
+        Err("extension must not be empty".to_string())
+    } else {
+        Ok(extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use std::path::Path;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("tidyup").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn is_a_valid_command() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_extension_lists() {
+        let cli = parse(&["-e", "PNG,.jpg", "-e", "gif", "-i", " .Tmp "]).unwrap();
+        assert_eq!(cli.run.scan.select.extensions, ["png", "jpg", "gif"]);
+        assert_eq!(cli.run.scan.select.ignore, ["tmp"]);
+        assert!(parse(&["-e", "png,."]).is_err());
+        assert!(parse(&["-i", ""]).is_err());
+    }
+
+    #[test]
+    fn explains_with_the_scan_options() {
+        let cli = parse(&["explain", "-r", "--max-depth", "3", "-x", "*.tmp", "-L", "--include-tidied", "--dedup", "skip", "a.jpg"]).unwrap();
+        let Some(Command::Explain { paths, scan, .. }) = cli.command else { panic!("not explain") };
+        assert_eq!(paths, [Path::new("a.jpg")]);
+        let options = scan.plan_options();
+        assert_eq!(options.max_depth, 3);
+        assert_eq!(options.exclude, ["*.tmp"]);
+        assert!(options.follow_symlinks && options.include_tidied);
+        assert_eq!(options.dedup, Some(OnDuplicate::Skip));
+    }
+
+    #[test]
+    fn runs_without_a_subcommand() {
+        let cli = parse(&["-d", "/tmp", "-n", "-vv"]).unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.run.dry_run);
+        assert_eq!(cli.run.scan.select.directory, Path::new("/tmp"));
+        assert_eq!(cli.global.verbosity(), Verbosity::Debug);
+
+        let cli = parse(&["plan", "--on-conflict", "skip", "-q"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Plan(RunArgs { moves: MoveArgs { on_conflict: OnConflict::Skip, .. }, .. }))));
+        assert_eq!(cli.global.verbosity(), Verbosity::Quiet);
+        let cli = parse(&["-v", "-c", "tidyup.toml", "stats"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Stats(_))));
+        assert_eq!(cli.global.verbosity(), Verbosity::Verbose);
+        assert_eq!(cli.global.config.as_deref(), Some(Path::new("tidyup.toml")));
+        assert!(parse(&["--on-conflict", "replace"]).is_err());
+        assert!(parse(&["-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn limits_depth_only_when_asked() {
+        let depth = |args: &[&str]| parse(args).unwrap().run.scan.max_depth();
+        assert_eq!(depth(&[]), 1);
+        assert_eq!(depth(&["-r"]), usize::MAX);
+        assert_eq!(depth(&["--max-depth", "3"]), 3);
+        assert_eq!(depth(&["--max-depth", "0"]), 1);
+        assert!(parse(&["--max-depth", "-1"]).is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration(" 1h "), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("3d").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn splits_file_lists_at_nuls_or_lines() {
+        assert_eq!(parse_list(b"a.txt\nsub/b c.jpg\r\n\n"), [Path::new("a.txt"), Path::new("sub/b c.jpg")]);
+        assert_eq!(parse_list(b"line\nbreak.txt\0/abs.png\0"), [Path::new("line\nbreak.txt"), Path::new("/abs.png")]);
+        assert_eq!(parse_list(b"\xff.bin\n").len(), 1);
+        assert!(parse_list(b"").is_empty());
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobBuilder};
+use regex::Regex;
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::fsops::Transfer;
+use crate::rules::{self, Condition, MatchMode, Range, Rule, RuleAction};
+use crate::template::{self, Placeholder, Template};
+use crate::xdg;
+
+pub const CONFIG_FILE_NAME: &str = "tidyup.toml";
+
+pub const DEFAULT_CONFIG: &str = r#"
+[[category]]
+name = "images"
+folder = "images"
+extensions = ["png", "jpg", "jpeg"]
+
+[[category]]
+name = "python"
+folder = "python"
+extensions = ["py"]
+
+[[category]]
+name = "c++"
+folder = "c++"
+extensions = ["cpp"]
+"#;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub source: Option<PathBuf>,
+    pub categories: Vec<Category>,
+    /// Sorted by priority, highest first. Files no rule matches fall back to the categories.
+    pub rules: Vec<Rule>,
+    pub match_mode: MatchMode,
+    pub bundles: Vec<Bundle>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Category {
+    pub name: String,
+    pub folder: Template,
+    pub extensions: Vec<String>,
+    pub detect: Detect,
+}
+
+/// Extensions whose files travel together when they share a stem.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub name: String,
+    /// The first extension present decides where the bundle goes.
+    pub extensions: Vec<String>,
+}
+
+/// How a category decides whether a file belongs to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Detect {
+    /// The file name extension alone.
+    #[default]
+    Extension,
+    /// The content type, when it can be recognised, over the extension.
+    Content,
+    /// The extension, or the content type for files whose extension is
+    /// missing or unmapped.
+    Fallback,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = match &self.path {
+            Some(path) => path.display().to_string(),
+            None => "<built-in config>".to_string(),
+        };
+        if self.line == 0 {
+            write!(f, "{}: {}", path, self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}", path, self.line, self.column, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    #[serde(default, rename = "match")]
+    match_mode: MatchMode,
+    #[serde(default)]
+    category: Vec<RawCategory>,
+    #[serde(default)]
+    rule: Vec<RawRule>,
+    #[serde(default)]
+    bundle: Vec<RawBundle>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCategory {
+    name: Spanned<String>,
+    folder: Option<Spanned<String>>,
+    extensions: Spanned<Vec<Spanned<String>>>,
+    #[serde(default)]
+    detect: Detect,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBundle {
+    name: Spanned<String>,
+    extensions: Spanned<Vec<Spanned<String>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    name: Spanned<String>,
+    #[serde(default)]
+    priority: i64,
+    #[serde(default)]
+    when: RawCondition,
+    #[serde(default)]
+    action: RuleAction,
+    destination: Option<Spanned<String>>,
+    /// For `symlink`: whether links point at `relative` (default) or `absolute` paths.
+    symlink: Option<Spanned<SymlinkStyle>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SymlinkStyle {
+    Relative,
+    Absolute,
+}
+
+/// The keys of one table are combined with `all`.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCondition {
+    all: Option<Vec<RawCondition>>,
+    any: Option<Vec<RawCondition>>,
+    not: Option<Box<RawCondition>>,
+    name: Option<Spanned<String>>,
+    regex: Option<Spanned<String>>,
+    extension: Option<Vec<String>>,
+    size: Option<Spanned<String>>,
+    mtime: Option<Spanned<String>>,
+    ctime: Option<Spanned<String>>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mode: Option<Spanned<String>>,
+    mode_all: Option<Spanned<String>>,
+    mode_any: Option<Spanned<String>>,
+    hidden: Option<bool>,
+    make: Option<Spanned<String>>,
+    model: Option<Spanned<String>>,
+    taken: Option<Spanned<String>>,
+    orientation: Option<u16>,
+    gps: Option<bool>,
+}
+
+impl Config {
+    /// Loads the config from `explicit` if given, otherwise from the first
+    /// `tidyup.toml` found in the user config dir or `target_dir`, falling
+    /// back to the built-in defaults.
+    pub fn load(explicit: Option<&Path>, target_dir: &Path) -> Result<Config, ConfigError> {
+        let path = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => search_paths(target_dir).into_iter().find(|p| p.is_file()),
+        };
+        match path {
+            Some(path) => {
+                let text = fs::read_to_string(&path).map_err(|e| ConfigError {
+                    path: Some(path.clone()),
+                    line: 0,
+                    column: 0,
+                    message: e.to_string(),
+                })?;
+                Config::parse(&text, Some(path))
+            }
+            None => Config::parse(DEFAULT_CONFIG, None),
+        }
+    }
+
+    pub fn parse(text: &str, source: Option<PathBuf>) -> Result<Config, ConfigError> {
+        let error = |offset: usize, message: String| {
+            let (line, column) = line_col(text, offset);
+            ConfigError { path: source.clone(), line, column, message }
+        };
+
+        let raw: RawConfig = toml::from_str(text).map_err(|e| {
+            let offset = e.span().map(|s| s.start).unwrap_or(0);
+            error(offset, e.message().to_string())
+        })?;
+
+        let mut categories = Vec::new();
+        let mut names = HashSet::new();
+        let mut owners: HashMap<String, String> = HashMap::new();
+        for raw_category in raw.category {
+            let name = raw_category.name.get_ref().trim().to_string();
+            if name.is_empty() {
+                return Err(error(raw_category.name.span().start, "category name must not be empty".to_string()));
+            }
+            if !names.insert(name.clone()) {
+                return Err(error(raw_category.name.span().start, format!("duplicate category `{}`", name)));
+            }
+
+            let folder = match &raw_category.folder {
+                Some(folder) => {
+                    let template = Template::parse(folder.get_ref()).map_err(|message| error(folder.span().start, message))?;
+                    if template.placeholders().any(|placeholder| matches!(placeholder, Placeholder::Regex(_))) {
+                        return Err(error(folder.span().start, "`{regex.N}` is only available in rule destinations".to_string()));
+                    }
+                    template
+                }
+                None if !template::is_relative_inside(Path::new(&name)) => {
+                    return Err(error(
+                        raw_category.name.span().start,
+                        format!("category `{}` has no `folder`, and its name is not a relative path inside the target directory", name),
+                    ));
+                }
+                None => Template::literal(&name),
+            };
+
+            if raw_category.extensions.get_ref().is_empty() {
+                return Err(error(
+                    raw_category.extensions.span().start,
+                    format!("category `{}` has no extensions", name),
+                ));
+            }
+            let mut extensions = Vec::new();
+            for extension in raw_category.extensions.get_ref() {
+                let normalized = normalize_extension(extension.get_ref());
+                if normalized.is_empty() {
+                    return Err(error(extension.span().start, "extension must not be empty".to_string()));
+                }
+                if let Some(owner) = owners.insert(normalized.clone(), name.clone()) {
+                    return Err(error(
+                        extension.span().start,
+                        format!("extension `{}` is already mapped to category `{}`", normalized, owner),
+                    ));
+                }
+                extensions.push(normalized);
+            }
+
+            categories.push(Category { name, folder, extensions, detect: raw_category.detect });
+        }
+
+        let mut rules = Vec::new();
+        let mut rule_names = HashSet::new();
+        for raw_rule in raw.rule {
+            let name = raw_rule.name.get_ref().trim().to_string();
+            let name_offset = raw_rule.name.span().start;
+            if name.is_empty() {
+                return Err(error(name_offset, "rule name must not be empty".to_string()));
+            }
+            if !rule_names.insert(name.clone()) {
+                return Err(error(name_offset, format!("duplicate rule `{}`", name)));
+            }
+
+            let places = raw_rule.action.places();
+            let destination = match &raw_rule.destination {
+                Some(destination) if places => {
+                    Some(Template::parse(destination.get_ref()).map_err(|message| error(destination.span().start, message))?)
+                }
+                None if places => {
+                    return Err(error(name_offset, format!("rule `{}` places files but has no destination", name)));
+                }
+                Some(destination) => {
+                    return Err(error(destination.span().start, format!("rule `{}` does not move files; remove its destination", name)));
+                }
+                None => None,
+            };
+            let transfer = match (raw_rule.action, &raw_rule.symlink) {
+                (RuleAction::Symlink, style) => {
+                    Transfer::Symlink { absolute: style.as_ref().is_some_and(|style| *style.get_ref() == SymlinkStyle::Absolute) }
+                }
+                (_, Some(style)) => {
+                    return Err(error(style.span().start, format!("rule `{}` does not create symlinks; remove `symlink`", name)));
+                }
+                (RuleAction::Copy, None) => Transfer::Copy,
+                (RuleAction::Hardlink, None) => Transfer::Hardlink,
+                (_, None) => Transfer::Move,
+            };
+
+            let when = condition(raw_rule.when).map_err(|(offset, message)| error(offset, message))?;
+            if let (Some(destination), Some(raw_destination)) = (&destination, &raw_rule.destination) {
+                let uses_regex = destination.placeholders().any(|placeholder| matches!(placeholder, Placeholder::Regex(_)));
+                if uses_regex && !when.has_regex() {
+                    return Err(error(
+                        raw_destination.span().start,
+                        format!("rule `{}` uses `{{regex.N}}` but has no `regex` condition", name),
+                    ));
+                }
+            }
+            rules.push(Rule { name, priority: raw_rule.priority, when, action: raw_rule.action, destination, transfer });
+        }
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+
+        let mut bundles = Vec::new();
+        let mut bundle_names = HashSet::new();
+        for raw_bundle in raw.bundle {
+            let name = raw_bundle.name.get_ref().trim().to_string();
+            if name.is_empty() {
+                return Err(error(raw_bundle.name.span().start, "bundle name must not be empty".to_string()));
+            }
+            if !bundle_names.insert(name.clone()) {
+                return Err(error(raw_bundle.name.span().start, format!("duplicate bundle `{}`", name)));
+            }
+            if raw_bundle.extensions.get_ref().len() < 2 {
+                return Err(error(
+                    raw_bundle.extensions.span().start,
+                    format!("bundle `{}` needs at least two extensions", name),
+                ));
+            }
+            let mut extensions: Vec<String> = Vec::new();
+            for extension in raw_bundle.extensions.get_ref() {
+                let normalized = normalize_extension(extension.get_ref());
+                if normalized.is_empty() {
+                    return Err(error(extension.span().start, "extension must not be empty".to_string()));
+                }
+                if extensions.contains(&normalized) {
+                    return Err(error(extension.span().start, format!("extension `{}` is listed twice", normalized)));
+                }
+                extensions.push(normalized);
+            }
+            bundles.push(Bundle { name, extensions });
+        }
+
+        Ok(Config { source, categories, rules, match_mode: raw.match_mode, bundles })
+    }
+
+    pub fn uses_content(&self) -> bool {
+        self.categories.iter().any(|category| category.detect != Detect::Extension)
+    }
+}
+
+/// Builds a condition, or the offset and message of the first invalid value.
+fn condition(raw: RawCondition) -> Result<Condition, (usize, String)> {
+    fn parsed<T>(value: &Spanned<String>, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, (usize, String)> {
+        parse(value.get_ref()).map_err(|message| (value.span().start, message))
+    }
+
+    let mut all = Vec::new();
+    if let Some(name) = &raw.name {
+        let glob = parsed(name, |pattern| Glob::new(pattern).map_err(|e| e.to_string()))?;
+        all.push(Condition::Name(glob.compile_matcher()));
+    }
+    if let Some(regex) = &raw.regex {
+        all.push(Condition::Regex(parsed(regex, |pattern| Regex::new(pattern).map_err(|e| e.to_string()))?));
+    }
+    if let Some(extensions) = raw.extension {
+        all.push(Condition::Extension(extensions.iter().map(|ext| normalize_extension(ext)).collect()));
+    }
+    if let Some(size) = &raw.size {
+        all.push(Condition::Size(parsed(size, |text| Range::parse(text, rules::parse_size))?));
+    }
+    if let Some(mtime) = &raw.mtime {
+        all.push(Condition::Modified(parsed(mtime, |text| Range::parse(text, rules::parse_age))?));
+    }
+    if let Some(ctime) = &raw.ctime {
+        all.push(Condition::Changed(parsed(ctime, |text| Range::parse(text, rules::parse_age))?));
+    }
+    if let Some(uid) = raw.uid {
+        all.push(Condition::Uid(uid));
+    }
+    if let Some(gid) = raw.gid {
+        all.push(Condition::Gid(gid));
+    }
+    if let Some(mode) = &raw.mode {
+        all.push(Condition::Mode(parsed(mode, rules::parse_mode)?));
+    }
+    if let Some(mode) = &raw.mode_all {
+        all.push(Condition::ModeAll(parsed(mode, rules::parse_mode)?));
+    }
+    if let Some(mode) = &raw.mode_any {
+        all.push(Condition::ModeAny(parsed(mode, rules::parse_mode)?));
+    }
+    if let Some(hidden) = raw.hidden {
+        all.push(Condition::Hidden(hidden));
+    }
+    let camera_glob = |pattern: &str| {
+        let glob = GlobBuilder::new(pattern).case_insensitive(true).build().map_err(|e| e.to_string())?;
+        Ok(glob.compile_matcher())
+    };
+    if let Some(make) = &raw.make {
+        all.push(Condition::Make(parsed(make, camera_glob)?));
+    }
+    if let Some(model) = &raw.model {
+        all.push(Condition::Model(parsed(model, camera_glob)?));
+    }
+    if let Some(taken) = &raw.taken {
+        all.push(Condition::Taken(parsed(taken, |text| Range::parse(text, rules::parse_age))?));
+    }
+    if let Some(orientation) = raw.orientation {
+        all.push(Condition::Orientation(orientation));
+    }
+    if let Some(gps) = raw.gps {
+        all.push(Condition::Gps(gps));
+    }
+    if let Some(conditions) = raw.all {
+        all.push(Condition::All(conditions.into_iter().map(condition).collect::<Result<_, _>>()?));
+    }
+    if let Some(conditions) = raw.any {
+        all.push(Condition::Any(conditions.into_iter().map(condition).collect::<Result<_, _>>()?));
+    }
+    if let Some(negated) = raw.not {
+        all.push(Condition::Not(Box::new(condition(*negated)?)));
+    }
+
+    Ok(if all.len() == 1 { all.remove(0) } else { Condition::All(all) })
+}
+
+pub fn normalize_extension(extension: &str) -> String {
+    extension.trim().trim_start_matches('.').to_ascii_lowercase()
+}
+
+fn search_paths(target_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(config_home) = xdg::config_home() {
+        paths.push(config_home.join("tidyup").join(CONFIG_FILE_NAME));
+    }
+    paths.push(target_dir.join(CONFIG_FILE_NAME));
+    paths
+}
+
+fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(text.len());
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rfind('\n').map(|nl| offset - nl).unwrap_or(offset + 1);
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> ConfigError {
+        Config::parse(text, Some(PathBuf::from("tidyup.toml"))).unwrap_err()
+    }
+
+    #[test]
+    fn parses_the_built_in_config() {
+        let config = Config::parse(DEFAULT_CONFIG, None).unwrap();
+        let names: Vec<_> = config.categories.iter().map(|category| category.name.as_str()).collect();
+        assert_eq!(names, ["images", "python", "c++"]);
+        assert_eq!(config.categories[0].extensions, ["png", "jpg", "jpeg"]);
+        assert_eq!(config.source, None);
+        assert!(config.bundles.is_empty());
+    }
+
+    #[test]
+    fn normalizes_names_folders_and_extensions() {
+        let config = Config::parse(
+            r#"
+            [[category]]
+            name = " docs "
+            extensions = [".PDF", "Txt"]
+
+            [[category]]
+            name = "photos"
+            folder = "media/photos"
+            extensions = ["jpg"]
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(config.categories[0].name, "docs");
+        assert_eq!(config.categories[0].folder.to_string(), "docs");
+        assert_eq!(config.categories[0].extensions, ["pdf", "txt"]);
+        assert_eq!(config.categories[1].folder.to_string(), "media/photos");
+    }
+
+    #[test]
+    fn reports_errors_with_line_and_column() {
+        let e = error("[[category]]\nname = \"a\"\nextensions = [\"png\"]\n\n[[category]]\nname = \"a\"\nextensions = [\"jpg\"]\n");
+        assert_eq!((e.line, e.column), (6, 8));
+        assert_eq!(e.to_string(), "tidyup.toml:6:8: duplicate category `a`");
+
+        let e = error("[[category]]\nname = \"a\"\nextensions = [\"png\"]\n[[category]]\nname = \"b\"\nextensions = [\"jpg\", \".PNG\"]\n");
+        assert_eq!((e.line, e.column), (6, 22));
+        assert!(e.message.contains("already mapped to category `a`"), "{}", e.message);
+
+        let e = error("[[category]]\nname = \"a\"\nfolder = \"../a\"\nextensions = [\"png\"]\n");
+        assert_eq!((e.line, e.column), (3, 10));
+
+        let e = error("[[category]]\nname = \"../a\"\nextensions = [\"png\"]\n");
+        assert_eq!((e.line, e.column), (2, 8));
+        assert!(e.message.contains("its name is not a relative path"), "{}", e.message);
+
+        let e = error("[[category]]\nname = \"a\"\nextensions = []\n");
+        assert_eq!(e.message, "category `a` has no extensions");
+
+        let e = error("[[category]]\nname = \"a\"\nextension = [\"png\"]\n");
+        assert_eq!(e.line, 3);
+    }
+
+    #[test]
+    fn places_errors_in_the_text() {
+        assert_eq!(line_col("ab\ncd", 0), (1, 1));
+        assert_eq!(line_col("ab\ncd", 4), (2, 2));
+        assert_eq!(line_col("ab", 99), (1, 3));
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+
+use crate::fsops::{Mover, Transfer};
+
+const MAX_SUFFIX: u32 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    Skip,
+    #[default]
+    Rename,
+    Timestamp,
+    KeepNewer,
+    KeepLarger,
+    Overwrite,
+    Ask,
+}
+
+impl OnConflict {
+    pub const NAMES: &'static [&'static str] =
+        &["skip", "rename", "timestamp", "keep-newer", "keep-larger", "overwrite", "ask"];
+}
+
+impl FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OnConflict, String> {
+        match s {
+            "skip" => Ok(OnConflict::Skip),
+            "rename" => Ok(OnConflict::Rename),
+            "timestamp" => Ok(OnConflict::Timestamp),
+            "keep-newer" => Ok(OnConflict::KeepNewer),
+            "keep-larger" => Ok(OnConflict::KeepLarger),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "ask" => Ok(OnConflict::Ask),
+            _ => Err(format!("unknown conflict policy `{}` (expected one of: {})", s, OnConflict::NAMES.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for OnConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OnConflict::Skip => "skip",
+            OnConflict::Rename => "rename",
+            OnConflict::Timestamp => "timestamp",
+            OnConflict::KeepNewer => "keep-newer",
+            OnConflict::KeepLarger => "keep-larger",
+            OnConflict::Overwrite => "overwrite",
+            OnConflict::Ask => "ask",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Moved(PathBuf),
+    Replaced(PathBuf),
+    Skipped(String),
+}
+
+/// Moves (or copies or links, as `transfer` says) `source` to `destination`,
+/// resolving a name clash with `policy`. A file appearing at the destination
+/// concurrently is never replaced unless the policy asks for replacement.
+pub fn place(mover: &Mover, transfer: Transfer, source: &Path, destination: &Path, policy: OnConflict) -> io::Result<Outcome> {
+    let placer = Placer { mover, transfer };
+    match placer.noreplace(source, destination) {
+        Ok(()) => return Ok(Outcome::Moved(destination.to_path_buf())),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+
+    let policy = match policy {
+        OnConflict::Ask => ask(source, destination)?,
+        policy => policy,
+    };
+
+    match policy {
+        OnConflict::Skip | OnConflict::Ask => Ok(Outcome::Skipped(format!("{} already exists", destination.display()))),
+        OnConflict::Rename => place_numbered(&placer, source, destination, None),
+        OnConflict::Timestamp => {
+            let mtime: DateTime<Local> = fs::metadata(source)?.modified()?.into();
+            let stamp = mtime.format("%Y%m%d-%H%M%S").to_string();
+            let candidate = suffixed(destination, &stamp);
+            match placer.noreplace(source, &candidate) {
+                Ok(()) => Ok(Outcome::Moved(candidate)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => place_numbered(&placer, source, destination, Some(&stamp)),
+                Err(e) => Err(e),
+            }
+        }
+        OnConflict::KeepNewer => {
+            let incoming = fs::metadata(source)?.modified()?;
+            let existing = fs::metadata(destination)?.modified()?;
+            if incoming > existing {
+                replace(&placer, source, destination)
+            } else {
+                Ok(Outcome::Skipped(format!("{} is not older", destination.display())))
+            }
+        }
+        OnConflict::KeepLarger => {
+            let incoming = fs::metadata(source)?.len();
+            let existing = fs::metadata(destination)?.len();
+            if incoming > existing {
+                replace(&placer, source, destination)
+            } else {
+                Ok(Outcome::Skipped(format!("{} is not smaller", destination.display())))
+            }
+        }
+        OnConflict::Overwrite => replace(&placer, source, destination),
+    }
+}
+
+/// What [`place`] would do with `source` now, without doing it. `ask`
+/// cannot be answered in advance and comes back as a skip saying so.
+pub fn predict(source: &Path, destination: &Path, policy: OnConflict) -> io::Result<Outcome> {
+    let taken = |path: &Path| fs::symlink_metadata(path).is_ok();
+    if !taken(destination) {
+        return Ok(Outcome::Moved(destination.to_path_buf()));
+    }
+    let numbered = |prefix: Option<&str>| {
+        (1..=MAX_SUFFIX)
+            .map(|n| match prefix {
+                Some(prefix) => suffixed(destination, &format!("{} ({})", prefix, n)),
+                None => suffixed(destination, &format!("({})", n)),
+            })
+            .find(|candidate| !taken(candidate))
+            .map_or_else(
+                || Outcome::Skipped(format!("no free name for {} after {} attempts", destination.display(), MAX_SUFFIX)),
+                Outcome::Moved,
+            )
+    };
+
+    match policy {
+        OnConflict::Skip => Ok(Outcome::Skipped(format!("{} already exists", destination.display()))),
+        OnConflict::Ask => Ok(Outcome::Skipped(format!("{} already exists, the user is asked", destination.display()))),
+        OnConflict::Rename => Ok(numbered(None)),
+        OnConflict::Timestamp => {
+            let mtime: DateTime<Local> = fs::metadata(source)?.modified()?.into();
+            let stamp = mtime.format("%Y%m%d-%H%M%S").to_string();
+            let candidate = suffixed(destination, &stamp);
+            Ok(if taken(&candidate) { numbered(Some(&stamp)) } else { Outcome::Moved(candidate) })
+        }
+        OnConflict::KeepNewer => {
+            if fs::metadata(source)?.modified()? > fs::metadata(destination)?.modified()? {
+                Ok(Outcome::Replaced(destination.to_path_buf()))
+            } else {
+                Ok(Outcome::Skipped(format!("{} is not older", destination.display())))
+            }
+        }
+        OnConflict::KeepLarger => {
+            if fs::metadata(source)?.len() > fs::metadata(destination)?.len() {
+                Ok(Outcome::Replaced(destination.to_path_buf()))
+            } else {
+                Ok(Outcome::Skipped(format!("{} is not smaller", destination.display())))
+            }
+        }
+        OnConflict::Overwrite => Ok(Outcome::Replaced(destination.to_path_buf())),
+    }
+}
+
+/// A [`Mover`] bound to one kind of transfer.
+struct Placer<'a> {
+    mover: &'a Mover,
+    transfer: Transfer,
+}
+
+impl Placer<'_> {
+    fn noreplace(&self, source: &Path, destination: &Path) -> io::Result<()> {
+        self.mover.transfer_noreplace(self.transfer, source, destination)
+    }
+}
+
+fn replace(placer: &Placer, source: &Path, destination: &Path) -> io::Result<Outcome> {
+    placer.mover.transfer_replace(placer.transfer, source, destination)?;
+    Ok(Outcome::Replaced(destination.to_path_buf()))
+}
+
+fn place_numbered(placer: &Placer, source: &Path, destination: &Path, prefix: Option<&str>) -> io::Result<Outcome> {
+    for n in 1..=MAX_SUFFIX {
+        let suffix = match prefix {
+            Some(prefix) => format!("{} ({})", prefix, n),
+            None => format!("({})", n),
+        };
+        let candidate = suffixed(destination, &suffix);
+        match placer.noreplace(source, &candidate) {
+            Ok(()) => return Ok(Outcome::Moved(candidate)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Outcome::Skipped(format!("no free name for {} after {} attempts", destination.display(), MAX_SUFFIX)))
+}
+
+/// `images/name.png` + `(1)` -> `images/name (1).png`
+pub(crate) fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{} {}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{} {}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+fn ask(source: &Path, destination: &Path) -> io::Result<OnConflict> {
+    let stdin = io::stdin();
+    loop {
+        eprint!(
+            "{} already exists (moving {}). [s]kip, [r]ename, [t]imestamp, keep [n]ewer, keep [l]arger, [o]verwrite? ",
+            destination.display(),
+            source.display()
+        );
+        io::stderr().flush()?;
+
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            return Ok(OnConflict::Skip);
+        }
+        match answer.trim() {
+            "s" | "skip" => return Ok(OnConflict::Skip),
+            "r" | "rename" => return Ok(OnConflict::Rename),
+            "t" | "timestamp" => return Ok(OnConflict::Timestamp),
+            "n" | "newer" => return Ok(OnConflict::KeepNewer),
+            "l" | "larger" => return Ok(OnConflict::KeepLarger),
+            "o" | "overwrite" => return Ok(OnConflict::Overwrite),
+            _ => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn place_file(source: &Path, destination: &Path, policy: OnConflict) -> Outcome {
+        place(&Mover::default(), Transfer::Move, source, destination, policy).unwrap()
+    }
+
+    #[test]
+    fn suffixes_before_the_extension() {
+        assert_eq!(suffixed(Path::new("images/name.png"), "(1)"), Path::new("images/name (1).png"));
+        assert_eq!(suffixed(Path::new("archive.tar.gz"), "(2)"), Path::new("archive.tar (2).gz"));
+        assert_eq!(suffixed(Path::new("docs/README"), "(1)"), Path::new("docs/README (1)"));
+        assert_eq!(suffixed(Path::new(".bashrc"), "(1)"), Path::new(".bashrc (1)"));
+    }
+
+    #[test]
+    fn moves_to_a_free_destination() {
+        let dir = TestDir::new();
+        let source = dir.file("a.png", b"new");
+        let destination = dir.path().join("images/a.png");
+        fs::create_dir(dir.path().join("images")).unwrap();
+
+        assert!(matches!(place_file(&source, &destination, OnConflict::Skip), Outcome::Moved(to) if to == destination));
+        assert!(!source.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+    }
+
+    #[test]
+    fn never_replaces_unless_asked_to() {
+        let dir = TestDir::new();
+        let source = dir.file("a.png", b"new");
+        let destination = dir.file("images/a.png", b"old");
+
+        assert!(matches!(place_file(&source, &destination, OnConflict::Skip), Outcome::Skipped(_)));
+        assert_eq!(fs::read(&source).unwrap(), b"new");
+        assert_eq!(fs::read(&destination).unwrap(), b"old");
+
+        assert!(matches!(place_file(&source, &destination, OnConflict::KeepLarger), Outcome::Skipped(_)));
+        assert!(source.exists());
+
+        assert!(matches!(place_file(&source, &destination, OnConflict::Overwrite), Outcome::Replaced(to) if to == destination));
+        assert!(!source.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+    }
+
+    #[test]
+    fn renames_to_the_next_free_number() {
+        let dir = TestDir::new();
+        let destination = dir.file("images/a.png", b"old");
+        dir.file("images/a (1).png", b"old");
+
+        let source = dir.file("a.png", b"new");
+        let outcome = place_file(&source, &destination, OnConflict::Rename);
+        assert!(matches!(outcome, Outcome::Moved(to) if to == dir.path().join("images/a (2).png")));
+        assert_eq!(fs::read(&destination).unwrap(), b"old");
+
+        let source = dir.file("a.png", b"newer");
+        let outcome = place_file(&source, &destination, OnConflict::Timestamp);
+        let Outcome::Moved(to) = outcome else { panic!("expected a move, got {:?}", outcome) };
+        let name = to.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("a ") && name.ends_with(".png") && name.len() == "a 20240101-000000.png".len(), "{}", name);
+    }
+}
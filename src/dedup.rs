@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::report::Failure;
+
+/// How much of a file is hashed before deciding whether to read all of it.
+const PARTIAL_SIZE: u64 = 64 * 1024;
+
+/// What happens to a file whose content is already at its destination or
+/// elsewhere in the scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDuplicate {
+    /// Leave the incoming copy where it is.
+    Skip,
+    /// Move the incoming copy to the trash.
+    Trash,
+    /// Replace the incoming copy with a hard link to the original.
+    Hardlink,
+    /// Move it like any other file and list it as a duplicate.
+    Report,
+}
+
+impl OnDuplicate {
+    pub const NAMES: &'static [&'static str] = &["skip", "trash", "hardlink", "report"];
+}
+
+impl FromStr for OnDuplicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OnDuplicate, String> {
+        match s {
+            "skip" => Ok(OnDuplicate::Skip),
+            "trash" => Ok(OnDuplicate::Trash),
+            "hardlink" => Ok(OnDuplicate::Hardlink),
+            "report" => Ok(OnDuplicate::Report),
+            _ => Err(format!("unknown duplicate policy `{}` (expected one of: {})", s, OnDuplicate::NAMES.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for OnDuplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OnDuplicate::Skip => "skip",
+            OnDuplicate::Trash => "trash",
+            OnDuplicate::Hardlink => "hardlink",
+            OnDuplicate::Report => "report",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A file whose bytes are the same as those of `original`.
+#[derive(Debug)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    pub original: PathBuf,
+}
+
+/// A file taking part in the search, with what is known about it so far.
+struct Candidate {
+    path: PathBuf,
+    incoming: bool,
+    inode: (u64, u64),
+}
+
+/// Finds the `incoming` files whose content is already in one of the
+/// `existing` files or in an incoming file listed before them.
+///
+/// Files are compared by size first, then by a hash of their first 64 KiB,
+/// and only then by a BLAKE3 hash of their whole content. Empty files and
+/// files that are hard links to their original are not duplicates. Incoming
+/// files that cannot be read end up in `failures`.
+pub fn find(incoming: &[PathBuf], existing: &[PathBuf], failures: &mut Vec<Failure>) -> Vec<Duplicate> {
+    let mut by_size: HashMap<u64, Vec<Candidate>> = HashMap::new();
+    let mut sizes = Vec::new();
+    let files = existing.iter().map(|path| (path, false)).chain(incoming.iter().map(|path| (path, true)));
+    for (path, incoming) in files {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => metadata,
+            Ok(_) => continue,
+            Err(error) => {
+                if incoming {
+                    failures.push(Failure { path: path.clone(), error });
+                }
+                continue;
+            }
+        };
+        let group = by_size.entry(metadata.len()).or_default();
+        if group.is_empty() {
+            sizes.push(metadata.len());
+        }
+        group.push(Candidate { path: path.clone(), incoming, inode: (metadata.dev(), metadata.ino()) });
+    }
+
+    let mut duplicates = Vec::new();
+    for size in sizes {
+        let group = by_size.remove(&size).unwrap_or_default();
+        // Files of a size that no incoming file has, or that only one file has, cannot be duplicates.
+        if group.len() < 2 || !group.iter().any(|candidate| candidate.incoming) {
+            continue;
+        }
+        for same_start in split_by(group, |path| hash_file(path, Some(PARTIAL_SIZE)), failures) {
+            let rest = if size <= PARTIAL_SIZE {
+                vec![same_start]
+            } else {
+                split_by(same_start, |path| hash_file(path, None), failures)
+            };
+            for mut same in rest {
+                let original = same.remove(0);
+                duplicates.extend(
+                    same.into_iter()
+                        .filter(|candidate| candidate.incoming && candidate.inode != original.inode)
+                        .map(|candidate| Duplicate { path: candidate.path, original: original.path.clone() }),
+                );
+            }
+        }
+    }
+    duplicates
+}
+
+/// Whether `a` and `b` still hold the same bytes.
+pub fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    Ok(hash_file(a, None)? == hash_file(b, None)?)
+}
+
+/// Splits `group` into the runs of candidates with the same key, keeping
+/// their order and dropping keys only one candidate has.
+fn split_by(
+    group: Vec<Candidate>,
+    key: impl Fn(&Path) -> io::Result<blake3::Hash>,
+    failures: &mut Vec<Failure>,
+) -> Vec<Vec<Candidate>> {
+    let mut split: Vec<(blake3::Hash, Vec<Candidate>)> = Vec::new();
+    for candidate in group {
+        let hash = match key(&candidate.path) {
+            Ok(hash) => hash,
+            Err(error) => {
+                if candidate.incoming {
+                    failures.push(Failure { path: candidate.path, error });
+                }
+                continue;
+            }
+        };
+        match split.iter_mut().find(|(key, _)| *key == hash) {
+            Some((_, same)) => same.push(candidate),
+            None => split.push((hash, vec![candidate])),
+        }
+    }
+    split
+        .into_iter()
+        .map(|(_, same)| same)
+        .filter(|same| same.len() > 1 && same.iter().any(|candidate| candidate.incoming))
+        .collect()
+}
+
+/// Hashes the first `limit` bytes of the file, or all of it.
+fn hash_file(path: &Path, limit: Option<u64>) -> io::Result<blake3::Hash> {
+    let file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    match limit {
+        Some(limit) => hasher.update_reader(file.take(limit))?,
+        None => hasher.update_reader(file)?,
+    };
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn pairs(duplicates: &[Duplicate]) -> Vec<(&Path, &Path)> {
+        duplicates.iter().map(|duplicate| (duplicate.path.as_path(), duplicate.original.as_path())).collect()
+    }
+
+    #[test]
+    fn finds_incoming_copies_of_existing_files() {
+        let dir = TestDir::new();
+        let original = dir.file("images/a.jpg", b"same");
+        let copy = dir.file("a (1).jpg", b"same");
+        let other = dir.file("b.jpg", b"diff");
+        let mut failures = Vec::new();
+
+        let (incoming, existing) = ([copy, other], [original]);
+        let duplicates = find(&incoming, &existing, &mut failures);
+        assert_eq!(pairs(&duplicates), [(incoming[0].as_path(), existing[0].as_path())]);
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn the_first_incoming_copy_is_the_original() {
+        let dir = TestDir::new();
+        let first = dir.file("a.txt", b"content");
+        let second = dir.file("b.txt", b"content");
+        let mut failures = Vec::new();
+
+        let duplicates = find(&[first.clone(), second.clone()], &[], &mut failures);
+        assert_eq!(pairs(&duplicates), [(second.as_path(), first.as_path())]);
+    }
+
+    #[test]
+    fn compares_whole_content_past_the_first_block() {
+        let dir = TestDir::new();
+        let mut content = vec![7u8; PARTIAL_SIZE as usize + 10];
+        let original = dir.file("a.bin", &content);
+        *content.last_mut().unwrap() = 8;
+        let differs = dir.file("b.bin", &content);
+        let mut failures = Vec::new();
+
+        assert!(find(&[differs], &[original], &mut failures).is_empty());
+    }
+
+    #[test]
+    fn skips_empty_files_hard_links_and_missing_files() {
+        let dir = TestDir::new();
+        let empty = dir.file("empty", b"");
+        let also_empty = dir.file("also-empty", b"");
+        let original = dir.file("a.txt", b"linked");
+        let link = dir.path().join("b.txt");
+        fs::hard_link(&original, &link).unwrap();
+        let missing = dir.path().join("missing.txt");
+        let mut failures = Vec::new();
+
+        let duplicates = find(&[also_empty, link, missing.clone()], &[empty, original], &mut failures);
+        assert!(duplicates.is_empty());
+        assert_eq!(failures.iter().map(|failure| &failure.path).collect::<Vec<_>>(), [&missing]);
+    }
+}
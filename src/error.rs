@@ -0,0 +1,39 @@
+use std::fmt;
+use std::io;
+
+use crate::config::ConfigError;
+
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    /// An exclude glob that does not parse.
+    InvalidPattern(String),
+    /// A plan file that cannot be read back.
+    InvalidPlan(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(e) => write!(f, "{}", e),
+            Error::InvalidPattern(message) => write!(f, "invalid exclude pattern: {}", message),
+            Error::InvalidPlan(message) => write!(f, "invalid plan file: {}", message),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
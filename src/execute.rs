@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::conflict::{self, OnConflict, Outcome};
+use crate::dedup;
+use crate::error::Error;
+use crate::fsops::{self, Mover, Transfer};
+use crate::journal::Journal;
+use crate::plan::{self, Action, Plan, TrashReason};
+use crate::report::{Event, Failure, Reporter, Summary};
+use crate::trash;
+
+/// Applies a [`Plan`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Executor {
+    pub mover: Mover,
+    pub on_conflict: OnConflict,
+}
+
+impl Executor {
+    /// Applies `plan` with a new undo journal for its root, unless there is
+    /// nothing to do. Returns the summary and the id of the run, if one was recorded.
+    pub fn run(&self, plan: Plan, reporter: &mut dyn Reporter) -> Result<(Summary, Option<String>), Error> {
+        if plan.is_empty() {
+            let summary = Summary { skipped: plan.skips().count(), failures: plan.failures, ..Summary::default() };
+            return Ok((summary, None));
+        }
+        let mut journal = Journal::create(&plan.root)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot create the undo journal: {}", e)))?;
+        let summary = self.execute(plan, Some(&mut journal), reporter)?;
+        Ok((summary, Some(journal.run_id)))
+    }
+
+    /// Applies `plan`, recording every change in `journal` if given. A file
+    /// that cannot be moved is recorded in the summary and the run goes on;
+    /// only losing the journal is an error.
+    pub fn execute(&self, plan: Plan, mut journal: Option<&mut Journal>, reporter: &mut dyn Reporter) -> io::Result<Summary> {
+        let mut summary = Summary { failures: plan.failures, ..Summary::default() };
+        // Where the files moved so far ended up, for links and checks against originals moved earlier.
+        let mut moved_to: HashMap<PathBuf, PathBuf> = HashMap::new();
+
+        for action in &plan.actions {
+            match action {
+                Action::Move { source, destination, transfer, .. } => {
+                    let created = match destination.parent() {
+                        Some(parent) => create_dirs(&plan.root, parent, journal.as_deref_mut(), reporter)?,
+                        None => Ok(()),
+                    };
+                    if let Err(error) = created {
+                        reporter.report(Event::Failed { path: source, error: &error });
+                        summary.failures.push(Failure { path: source.clone(), error });
+                        continue;
+                    }
+
+                    let placed = fs::symlink_metadata(source)
+                        .and_then(|metadata| Ok((conflict::place(&self.mover, *transfer, source, destination, self.on_conflict)?, metadata)));
+                    match placed {
+                        Ok((Outcome::Moved(destination) | Outcome::Replaced(destination), metadata)) => {
+                            let arrived = Arrived { source: source.clone(), destination, transfer: *transfer, metadata };
+                            arrived.record(journal.as_deref_mut(), reporter, &mut summary, &mut moved_to)?;
+                        }
+                        Ok((Outcome::Skipped(reason), _)) => {
+                            summary.skipped += 1;
+                            reporter.report(Event::Kept { path: source, reason: &reason });
+                        }
+                        Err(error) => {
+                            reporter.report(Event::Failed { path: source, error: &error });
+                            summary.failures.push(Failure { path: source.clone(), error });
+                        }
+                    }
+                }
+                Action::Bundle { members, transfer, .. } => {
+                    let (primary, destination) = &members[0];
+                    let created = match destination.parent() {
+                        Some(parent) => create_dirs(&plan.root, parent, journal.as_deref_mut(), reporter)?,
+                        None => Ok(()),
+                    };
+                    match created {
+                        Ok(()) => self.execute_bundle(members, *transfer, journal.as_deref_mut(), reporter, &mut summary, &mut moved_to)?,
+                        Err(error) => fail_bundle(members, primary, error, reporter, &mut summary),
+                    }
+                }
+                Action::Trash { path, reason } => {
+                    let original = match reason {
+                        TrashReason::Duplicate(original) => Some(moved_to.get(original).unwrap_or(original)),
+                        TrashReason::Rule(_) => None,
+                    };
+                    let same = original.map_or(Ok(true), |original| still_duplicate(path, original));
+                    let trashed = same.and_then(|same| match same {
+                        true => fs::symlink_metadata(path).and_then(|metadata| Ok(Some((trash::trash(&self.mover, path)?, metadata)))),
+                        false => Ok(None),
+                    });
+                    match trashed {
+                        Ok(Some((trashed, metadata))) => {
+                            if let Some(journal) = journal.as_deref_mut() {
+                                journal.record_trash(path, &trashed, &metadata)?;
+                            }
+                            summary.trashed += 1;
+                            reporter.report(Event::Trashed { path, trashed: &trashed.file });
+                        }
+                        Ok(None) => {
+                            summary.skipped += 1;
+                            let reason = format!("no longer the same as {}", original.unwrap_or(path).display());
+                            reporter.report(Event::Kept { path, reason: &reason });
+                        }
+                        Err(error) => {
+                            reporter.report(Event::Failed { path, error: &error });
+                            summary.failures.push(Failure { path: path.clone(), error });
+                        }
+                    }
+                }
+                Action::Link { path, original } => {
+                    let original = moved_to.get(original).unwrap_or(original);
+                    let linked = still_duplicate(path, original).and_then(|same| match same {
+                        true => fs::symlink_metadata(path).and_then(|metadata| fsops::replace_with_link(original, path).map(|()| Some(metadata))),
+                        false => Ok(None),
+                    });
+                    match linked {
+                        Ok(Some(metadata)) => {
+                            if let Some(journal) = journal.as_deref_mut() {
+                                journal.record_link(path, original, &metadata)?;
+                            }
+                            summary.linked += 1;
+                            reporter.report(Event::Linked { path, original });
+                        }
+                        Ok(None) => {
+                            summary.skipped += 1;
+                            let reason = format!("no longer the same as {}", original.display());
+                            reporter.report(Event::Kept { path, reason: &reason });
+                        }
+                        Err(error) => {
+                            reporter.report(Event::Failed { path, error: &error });
+                            summary.failures.push(Failure { path: path.clone(), error });
+                        }
+                    }
+                }
+                Action::Skip { path, reason } => {
+                    summary.skipped += 1;
+                    reporter.report(Event::Skipped { path, reason });
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Moves the members of a bundle one after the other. The primary settles
+    /// any conflict and the companions take on its final name; if a member
+    /// cannot follow, the ones already moved are put back.
+    fn execute_bundle(
+        &self,
+        members: &[(PathBuf, PathBuf)],
+        transfer: Transfer,
+        mut journal: Option<&mut Journal>,
+        reporter: &mut dyn Reporter,
+        summary: &mut Summary,
+        moved_to: &mut HashMap<PathBuf, PathBuf>,
+    ) -> io::Result<()> {
+        let (primary, destination) = &members[0];
+        let metadata = members.iter().map(|(source, _)| fs::symlink_metadata(source)).collect::<io::Result<Vec<_>>>();
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                fail_bundle(members, primary, error, reporter, summary);
+                return Ok(());
+            }
+        };
+
+        let (placed, replace) = match conflict::place(&self.mover, transfer, primary, destination, self.on_conflict) {
+            Ok(Outcome::Moved(placed)) => (placed, false),
+            Ok(Outcome::Replaced(placed)) => (placed, true),
+            Ok(Outcome::Skipped(reason)) => {
+                for (source, _) in members {
+                    summary.skipped += 1;
+                    reporter.report(Event::Kept { path: source, reason: &reason });
+                }
+                return Ok(());
+            }
+            Err(error) => {
+                fail_bundle(members, primary, error, reporter, summary);
+                return Ok(());
+            }
+        };
+
+        let mut moved = vec![(primary.clone(), placed.clone())];
+        for (source, companion) in &members[1..] {
+            let companion = follow_rename(companion, destination, &placed);
+            let result = if replace {
+                self.mover.transfer_replace(transfer, source, &companion)
+            } else {
+                self.mover.transfer_noreplace(transfer, source, &companion)
+            };
+            if let Err(e) = result {
+                let error = io::Error::new(e.kind(), format!("cannot {} to {}: {}", transfer, companion.display(), e));
+                // Put the members that already moved back where they were, or drop the copies made so far.
+                for (source, destination) in moved.iter().rev() {
+                    let undone = match transfer {
+                        Transfer::Move => self.mover.move_noreplace(destination, source),
+                        _ => fs::remove_file(destination),
+                    };
+                    if let Err(e) = undone {
+                        let error = io::Error::new(e.kind(), format!("cannot undo {}: {}", destination.display(), e));
+                        reporter.report(Event::Failed { path: source, error: &error });
+                        summary.failures.push(Failure { path: source.clone(), error });
+                    }
+                }
+                fail_bundle(members, source, error, reporter, summary);
+                return Ok(());
+            }
+            moved.push((source.clone(), companion));
+        }
+
+        for ((source, destination), metadata) in moved.into_iter().zip(metadata) {
+            let arrived = Arrived { source, destination, transfer, metadata };
+            arrived.record(journal.as_deref_mut(), reporter, summary, moved_to)?;
+        }
+        Ok(())
+    }
+}
+
+/// A file that was moved, copied or linked to its destination.
+struct Arrived {
+    source: PathBuf,
+    destination: PathBuf,
+    transfer: Transfer,
+    /// Taken from the source before it was moved.
+    metadata: fs::Metadata,
+}
+
+impl Arrived {
+    fn record(
+        self,
+        journal: Option<&mut Journal>,
+        reporter: &mut dyn Reporter,
+        summary: &mut Summary,
+        moved_to: &mut HashMap<PathBuf, PathBuf>,
+    ) -> io::Result<()> {
+        let Arrived { source, destination, transfer, metadata } = self;
+        if transfer == Transfer::Move {
+            if let Some(journal) = journal {
+                journal.record_move(&source, &destination, &metadata)?;
+            }
+            summary.moved += 1;
+            reporter.report(Event::Moved { source: &source, destination: &destination });
+            moved_to.insert(source, destination);
+            return Ok(());
+        }
+
+        if let Some(journal) = journal {
+            journal.record_copy(&source, &destination)?;
+        }
+        match transfer {
+            Transfer::Copy => summary.copied += 1,
+            _ => summary.linked += 1,
+        }
+        reporter.report(Event::Copied { source: &source, destination: &destination, transfer });
+        Ok(())
+    }
+}
+
+/// Checks right before a duplicate is thrown away or linked that it still
+/// matches its original, which may have changed since the plan was made.
+fn still_duplicate(path: &Path, original: &Path) -> io::Result<bool> {
+    match dedup::same_content(path, original) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && path.exists() => Ok(false),
+        result => result,
+    }
+}
+
+/// Records `error` for the member that failed and a note for every other member of the bundle.
+fn fail_bundle(members: &[(PathBuf, PathBuf)], failed: &Path, error: io::Error, reporter: &mut dyn Reporter, summary: &mut Summary) {
+    for (source, _) in members {
+        let error = if source == failed {
+            io::Error::new(error.kind(), error.to_string())
+        } else {
+            io::Error::other(format!("not moved because {} in the same bundle failed", failed.display()))
+        };
+        reporter.report(Event::Failed { path: source, error: &error });
+        summary.failures.push(Failure { path: source.clone(), error });
+    }
+}
+
+/// Gives a companion the name the primary ended up with: when `IMG_1.jpg`
+/// became `IMG_1 (1).jpg`, `IMG_1.xmp` becomes `IMG_1 (1).xmp`.
+fn follow_rename(companion: &Path, planned: &Path, placed: &Path) -> PathBuf {
+    let (Some(name), Some(planned_stem), Some(placed_stem)) = (
+        companion.file_name().and_then(|name| name.to_str()),
+        planned.file_stem().and_then(|stem| stem.to_str()),
+        placed.file_stem().and_then(|stem| stem.to_str()),
+    ) else {
+        return companion.to_path_buf();
+    };
+    match name.strip_prefix(planned_stem) {
+        Some(rest) if planned_stem != placed_stem => placed.with_file_name(format!("{}{}", placed_stem, rest)),
+        _ => companion.to_path_buf(),
+    }
+}
+
+/// Creates `dir` and its missing parents below `root`, recording each one
+/// so that undo can remove it again. The outer error is the journal's, the
+/// inner one a folder that could not be created.
+fn create_dirs(
+    root: &Path,
+    dir: &Path,
+    mut journal: Option<&mut Journal>,
+    reporter: &mut dyn Reporter,
+) -> io::Result<Result<(), io::Error>> {
+    for missing in plan::missing_dirs(root, dir) {
+        match fs::create_dir(&missing) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && missing.is_dir() => continue,
+            Err(e) => return Ok(Err(io::Error::new(e.kind(), format!("cannot create {}: {}", missing.display(), e)))),
+        }
+        if let Some(journal) = journal.as_deref_mut() {
+            journal.record_dir(&missing)?;
+        }
+        reporter.report(Event::CreatedDir(&missing));
+    }
+    Ok(Ok(()))
+}
+
+/// Removes the empty folders among `dirs`, deepest first, and returns the
+/// ones that could not be removed. Folders that are no longer empty are left alone.
+pub fn prune(dirs: &[PathBuf], reporter: &mut dyn Reporter) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for dir in dirs {
+        match fs::remove_dir(dir) {
+            Ok(()) => reporter.report(Event::RemovedDir(dir)),
+            Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty || e.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                reporter.report(Event::Failed { path: dir, error: &error });
+                failures.push(Failure { path: dir.clone(), error });
+            }
+        }
+    }
+    failures
+}
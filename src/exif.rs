@@ -0,0 +1,503 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone};
+
+/// How much of a file is read to find the EXIF block of a JPEG or the `meta` box of a HEIF.
+const HEAD_SIZE: u64 = 256 * 1024;
+/// How much of a TIFF-based RAW file is read; its IFDs sit near the start.
+const TIFF_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_EXIF_SIZE: u64 = 1024 * 1024;
+
+const TAG_MAKE: u16 = 0x010f;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME: u16 = 0x9010;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const TAG_GPS_LATITUDE_REF: u16 = 1;
+const TAG_GPS_LATITUDE: u16 = 2;
+const TAG_GPS_LONGITUDE_REF: u16 = 3;
+const TAG_GPS_LONGITUDE: u16 = 4;
+
+/// The EXIF fields tidyup cares about.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Exif {
+    /// `DateTimeOriginal`, or `DateTime` when the camera wrote no original date,
+    /// as shown on the camera's clock.
+    pub taken: Option<NaiveDateTime>,
+    /// From `OffsetTimeOriginal` / `OffsetTime`, when the camera recorded one.
+    pub offset: Option<FixedOffset>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub orientation: Option<u16>,
+    /// Latitude and longitude in degrees, south and west negative.
+    pub gps: Option<(f64, f64)>,
+}
+
+impl Exif {
+    /// The capture time, in the recorded offset or else in the local timezone.
+    pub fn taken_at(&self) -> Option<DateTime<FixedOffset>> {
+        let taken = self.taken?;
+        match self.offset {
+            Some(offset) => offset.from_local_datetime(&taken).single(),
+            None => Local.from_local_datetime(&taken).earliest().map(|time| time.fixed_offset()),
+        }
+    }
+
+    /// Make and model, without repeating the make when the model already starts with it.
+    pub fn camera(&self) -> Option<String> {
+        match (self.make.as_deref(), self.model.as_deref()) {
+            (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model.to_string()),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (Some(make), None) => Some(make.to_string()),
+            (None, Some(model)) => Some(model.to_string()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Reads the EXIF block of a JPEG, HEIF/AVIF or TIFF-based (DNG, CR2, NEF,
+/// ARW, ORF, RW2, ...) file. `None` if the file has no readable EXIF data.
+pub fn read(path: &Path) -> io::Result<Option<Exif>> {
+    let mut file = File::open(path)?;
+    let mut head = Vec::new();
+    (&mut file).take(HEAD_SIZE).read_to_end(&mut head)?;
+
+    let tiff = if head.starts_with(b"\xff\xd8") {
+        jpeg_exif(&head).map(<[u8]>::to_vec)
+    } else if is_tiff(&head) {
+        let mut data = head;
+        (&mut file).take(TIFF_SIZE.saturating_sub(HEAD_SIZE)).read_to_end(&mut data)?;
+        Some(data)
+    } else if head.get(4..8) == Some(b"ftyp") {
+        match heif_exif_location(&head) {
+            Some((offset, length)) => read_heif_exif(&mut file, offset, length)?,
+            None => None,
+        }
+    } else {
+        None
+    };
+    Ok(tiff.and_then(|tiff| parse_tiff(&tiff)))
+}
+
+fn is_tiff(b: &[u8]) -> bool {
+    // Olympus ORF and Panasonic RW2 use their own magic numbers in place of 42.
+    matches!(b.get(0..4), Some(b"II*\0" | b"MM\0*" | b"IIRO" | b"IIRS" | b"IIU\0"))
+}
+
+/// The TIFF data of the `Exif` APP1 segment.
+fn jpeg_exif(b: &[u8]) -> Option<&[u8]> {
+    let mut at = 2;
+    while at + 4 <= b.len() {
+        if b[at] != 0xff {
+            return None;
+        }
+        let marker = b[at + 1];
+        if marker == 0xff {
+            at += 1;
+            continue;
+        }
+        // Start of scan: the metadata segments are over.
+        if marker == 0xda || marker == 0xd9 {
+            return None;
+        }
+        let length = u16::from_be_bytes([b[at + 2], b[at + 3]]) as usize;
+        let segment = b.get(at + 4..at + 2 + length)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        at += 2 + length;
+    }
+    None
+}
+
+/// Finds the file offset and length of the `Exif` item of a HEIF/AVIF file
+/// from its `meta` box (`iinf` for the item id, `iloc` for its extent).
+fn heif_exif_location(b: &[u8]) -> Option<(u64, u64)> {
+    let meta = boxes(b).find(|(kind, _)| kind == b"meta")?.1;
+    // `meta` is a full box: skip version and flags.
+    let children = meta.get(4..)?;
+
+    let iinf = boxes(children).find(|(kind, _)| kind == b"iinf")?.1;
+    let version = *iinf.first()?;
+    let entries = if version == 0 { iinf.get(6..)? } else { iinf.get(8..)? };
+    let item_id = boxes(entries).filter(|(kind, _)| kind == b"infe").find_map(|(_, infe)| {
+        let version = *infe.first()?;
+        let (id, item_type) = match version {
+            2 => (u16::from_be_bytes(infe.get(4..6)?.try_into().ok()?) as u32, infe.get(8..12)?),
+            3 => (u32::from_be_bytes(infe.get(4..8)?.try_into().ok()?), infe.get(10..14)?),
+            _ => return None,
+        };
+        (item_type == b"Exif").then_some(id)
+    })?;
+
+    let iloc = boxes(children).find(|(kind, _)| kind == b"iloc")?.1;
+    let mut reader = Cursor { b: iloc, at: 0 };
+    let version = reader.uint(1)?;
+    reader.at = 4;
+    let sizes = reader.uint(1)?;
+    let (offset_size, length_size) = ((sizes >> 4) as usize, (sizes & 0xf) as usize);
+    let sizes = reader.uint(1)?;
+    let (base_offset_size, index_size) = ((sizes >> 4) as usize, if version == 0 { 0 } else { (sizes & 0xf) as usize });
+    let item_count = if version < 2 { reader.uint(2)? } else { reader.uint(4)? };
+    for _ in 0..item_count {
+        let id = if version < 2 { reader.uint(2)? } else { reader.uint(4)? };
+        let construction_method = if version == 0 { 0 } else { reader.uint(2)? & 0xf };
+        reader.uint(2)?; // data_reference_index
+        let base_offset = reader.uint(base_offset_size)?;
+        let extent_count = reader.uint(2)?;
+        let mut first = None;
+        for _ in 0..extent_count {
+            reader.uint(index_size)?;
+            let offset = reader.uint(offset_size)?;
+            let length = reader.uint(length_size)?;
+            first.get_or_insert((base_offset.checked_add(offset)?, length));
+        }
+        if id == item_id as u64 {
+            // Only items stored in the file itself (not in `idat` or elsewhere) are supported.
+            return if construction_method == 0 { first } else { None };
+        }
+    }
+    None
+}
+
+/// Reads an `Exif` item: a 4-byte offset to the TIFF header, then the TIFF data.
+fn read_heif_exif(file: &mut File, offset: u64, length: u64) -> io::Result<Option<Vec<u8>>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut item = Vec::new();
+    file.take(length.min(MAX_EXIF_SIZE)).read_to_end(&mut item)?;
+    let Some(header) = item.get(0..4) else {
+        return Ok(None);
+    };
+    let start = 4 + u32::from_be_bytes(header.try_into().unwrap()) as usize;
+    Ok(item.get(start..).map(<[u8]>::to_vec))
+}
+
+/// Iterates over the ISO base media boxes in `b` as (type, payload).
+fn boxes(b: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let header = b.get(at..at + 8)?;
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let kind = &header[4..8];
+        let mut payload_start = at + 8;
+        if size == 1 {
+            size = u64::from_be_bytes(b.get(at + 8..at + 16)?.try_into().unwrap()) as usize;
+            payload_start += 8;
+        } else if size == 0 {
+            size = b.len() - at;
+        }
+        let end = at.checked_add(size)?.min(b.len());
+        if end < payload_start {
+            return None;
+        }
+        let payload = &b[payload_start..end];
+        at = end;
+        Some((kind, payload))
+    })
+}
+
+/// Big-endian reader over a box payload.
+struct Cursor<'a> {
+    b: &'a [u8],
+    at: usize,
+}
+
+impl Cursor<'_> {
+    fn uint(&mut self, size: usize) -> Option<u64> {
+        let bytes = self.b.get(self.at..self.at + size)?;
+        self.at += size;
+        Some(bytes.iter().fold(0, |value, &byte| value << 8 | byte as u64))
+    }
+}
+
+struct Tiff<'a> {
+    b: &'a [u8],
+    little_endian: bool,
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// The value itself when it fits in four bytes, otherwise its offset.
+    value_at: usize,
+}
+
+fn parse_tiff(b: &[u8]) -> Option<Exif> {
+    let little_endian = match b.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let tiff = Tiff { b, little_endian };
+    let ifd0 = tiff.ifd(tiff.u32(4)? as usize)?;
+
+    let mut exif = Exif::default();
+    let mut date_time = None;
+    let mut exif_ifd = None;
+    let mut gps_ifd = None;
+    for entry in &ifd0 {
+        match entry.tag {
+            TAG_MAKE => exif.make = tiff.string(entry),
+            TAG_MODEL => exif.model = tiff.string(entry),
+            TAG_ORIENTATION => exif.orientation = tiff.short(entry),
+            TAG_DATE_TIME => date_time = tiff.string(entry),
+            TAG_EXIF_IFD => exif_ifd = tiff.long(entry),
+            TAG_GPS_IFD => gps_ifd = tiff.long(entry),
+            _ => {}
+        }
+    }
+
+    let mut original = None;
+    let mut offset = None;
+    let mut offset_original = None;
+    if let Some(entries) = exif_ifd.and_then(|at| tiff.ifd(at as usize)) {
+        for entry in &entries {
+            match entry.tag {
+                TAG_DATE_TIME_ORIGINAL => original = tiff.string(entry),
+                TAG_OFFSET_TIME => offset = tiff.string(entry),
+                TAG_OFFSET_TIME_ORIGINAL => offset_original = tiff.string(entry),
+                _ => {}
+            }
+        }
+    }
+    let (taken, taken_offset) = match original {
+        Some(original) => (original, offset_original.or(offset)),
+        None => (date_time.unwrap_or_default(), offset),
+    };
+    exif.taken = NaiveDateTime::parse_from_str(taken.trim(), "%Y:%m:%d %H:%M:%S").ok();
+    exif.offset = taken_offset.and_then(|offset| parse_offset(&offset));
+
+    if let Some(entries) = gps_ifd.and_then(|at| tiff.ifd(at as usize)) {
+        let find = |tag| entries.iter().find(|entry| entry.tag == tag);
+        let coordinate = |value_tag, ref_tag, negative: &str| {
+            let degrees = tiff.degrees(find(value_tag)?)?;
+            let reference = find(ref_tag).and_then(|entry| tiff.string(entry)).unwrap_or_default();
+            Some(if reference.eq_ignore_ascii_case(negative) { -degrees } else { degrees })
+        };
+        let latitude = coordinate(TAG_GPS_LATITUDE, TAG_GPS_LATITUDE_REF, "S");
+        let longitude = coordinate(TAG_GPS_LONGITUDE, TAG_GPS_LONGITUDE_REF, "W");
+        exif.gps = latitude.zip(longitude);
+    }
+
+    Some(exif)
+}
+
+/// Parses `+02:00`, `-0530` or `Z`.
+fn parse_offset(text: &str) -> Option<FixedOffset> {
+    let text = text.trim().trim_end_matches('\0');
+    if text == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let (sign, rest) = match text.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let digits: String = rest.chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.b.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.b.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    fn ifd(&self, at: usize) -> Option<Vec<Entry>> {
+        let count = self.u16(at)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for index in 0..count {
+            let entry = at + 2 + index * 12;
+            let (tag, kind, count) = (self.u16(entry)?, self.u16(entry + 2)?, self.u32(entry + 4)?);
+            let size = type_size(kind) * count as usize;
+            let value_at = if size <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+            entries.push(Entry { tag, kind, count, value_at });
+        }
+        Some(entries)
+    }
+
+    fn string(&self, entry: &Entry) -> Option<String> {
+        if entry.kind != 2 {
+            return None;
+        }
+        let bytes = self.b.get(entry.value_at..entry.value_at + entry.count as usize)?;
+        let bytes = &bytes[..bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len())];
+        let text = String::from_utf8_lossy(bytes).trim().to_string();
+        (!text.is_empty()).then_some(text)
+    }
+
+    fn short(&self, entry: &Entry) -> Option<u16> {
+        match entry.kind {
+            3 => self.u16(entry.value_at),
+            4 => self.u32(entry.value_at).map(|value| value as u16),
+            _ => None,
+        }
+    }
+
+    fn long(&self, entry: &Entry) -> Option<u32> {
+        match entry.kind {
+            3 => self.u16(entry.value_at).map(u32::from),
+            4 | 13 => self.u32(entry.value_at),
+            _ => None,
+        }
+    }
+
+    /// Degrees, minutes and seconds as three rationals.
+    fn degrees(&self, entry: &Entry) -> Option<f64> {
+        if entry.kind != 5 || entry.count < 3 {
+            return None;
+        }
+        let rational = |index: usize| {
+            let at = entry.value_at + index * 8;
+            let (numerator, denominator) = (self.u32(at)?, self.u32(at + 4)?);
+            (denominator != 0).then(|| numerator as f64 / denominator as f64)
+        };
+        Some(rational(0)? + rational(1)? / 60.0 + rational(2)? / 3600.0)
+    }
+}
+
+fn type_size(kind: u16) -> usize {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    enum Value<'a> {
+        Ascii(&'a str),
+        Short(u16),
+        Long(u32),
+    }
+
+    /// Writes one IFD of a little-endian TIFF, with the values that do not
+    /// fit in an entry appended to `data`, which starts at `data_at`.
+    fn write_ifd(b: &mut Vec<u8>, entries: &[(u16, Value)], data: &mut Vec<u8>, data_at: usize) {
+        b.extend((entries.len() as u16).to_le_bytes());
+        for (tag, value) in entries {
+            b.extend(tag.to_le_bytes());
+            match value {
+                Value::Short(value) => {
+                    b.extend(3u16.to_le_bytes());
+                    b.extend(1u32.to_le_bytes());
+                    b.extend(value.to_le_bytes());
+                    b.extend([0, 0]);
+                }
+                Value::Long(value) => {
+                    b.extend(4u16.to_le_bytes());
+                    b.extend(1u32.to_le_bytes());
+                    b.extend(value.to_le_bytes());
+                }
+                Value::Ascii(text) => {
+                    let mut bytes = text.as_bytes().to_vec();
+                    bytes.push(0);
+                    b.extend(2u16.to_le_bytes());
+                    b.extend((bytes.len() as u32).to_le_bytes());
+                    if bytes.len() <= 4 {
+                        bytes.resize(4, 0);
+                        b.extend(bytes);
+                    } else {
+                        b.extend(((data_at + data.len()) as u32).to_le_bytes());
+                        data.extend(bytes);
+                    }
+                }
+            }
+        }
+        b.extend(0u32.to_le_bytes());
+    }
+
+    /// A TIFF with `ifd0` and an Exif IFD holding `exif`.
+    fn tiff(ifd0: Vec<(u16, Value)>, exif: &[(u16, Value)]) -> Vec<u8> {
+        let exif_at = 8 + 2 + 12 * (ifd0.len() + 1) + 4;
+        let data_at = exif_at + 2 + 12 * exif.len() + 4;
+        let mut ifd0 = ifd0;
+        ifd0.push((TAG_EXIF_IFD, Value::Long(exif_at as u32)));
+
+        let mut b = b"II*\0".to_vec();
+        b.extend(8u32.to_le_bytes());
+        let mut data = Vec::new();
+        write_ifd(&mut b, &ifd0, &mut data, data_at);
+        write_ifd(&mut b, exif, &mut data, data_at);
+        b.extend(data);
+        b
+    }
+
+    fn camera_tiff() -> Vec<u8> {
+        tiff(
+            vec![
+                (TAG_MAKE, Value::Ascii("Canon")),
+                (TAG_MODEL, Value::Ascii("Canon EOS R6")),
+                (TAG_ORIENTATION, Value::Short(6)),
+                (TAG_DATE_TIME, Value::Ascii("2020:01:01 00:00:00")),
+            ],
+            &[(TAG_DATE_TIME_ORIGINAL, Value::Ascii("2024:05:06 07:08:09")), (TAG_OFFSET_TIME_ORIGINAL, Value::Ascii("+02:00"))],
+        )
+    }
+
+    #[test]
+    fn reads_camera_and_capture_time() {
+        let exif = parse_tiff(&camera_tiff()).unwrap();
+        assert_eq!(exif.make.as_deref(), Some("Canon"));
+        assert_eq!(exif.orientation, Some(6));
+        assert_eq!(exif.camera().as_deref(), Some("Canon EOS R6"));
+        assert_eq!(exif.taken, NaiveDate::from_ymd_opt(2024, 5, 6).and_then(|date| date.and_hms_opt(7, 8, 9)));
+        assert_eq!(exif.taken_at().unwrap().to_rfc3339(), "2024-05-06T07:08:09+02:00");
+    }
+
+    #[test]
+    fn falls_back_to_the_modification_date() {
+        let exif = parse_tiff(&tiff(vec![(TAG_DATE_TIME, Value::Ascii("2020:01:02 03:04:05"))], &[])).unwrap();
+        assert_eq!(exif.taken, NaiveDate::from_ymd_opt(2020, 1, 2).and_then(|date| date.and_hms_opt(3, 4, 5)));
+        assert_eq!(exif.offset, None);
+        assert_eq!(exif.make, None);
+    }
+
+    #[test]
+    fn survives_truncated_and_corrupt_data() {
+        let b = camera_tiff();
+        for length in 0..b.len() {
+            parse_tiff(&b[..length]);
+        }
+        assert_eq!(parse_tiff(b"II*\0\xff\xff\xff\xff"), None);
+        assert_eq!(parse_tiff(b"XX*\0\x08\0\0\0"), None);
+        let mut corrupt = b.clone();
+        // Point the make at the end of the data.
+        corrupt[18..22].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(parse_tiff(&corrupt).unwrap().make, None);
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+02:00"), FixedOffset::east_opt(2 * 3600));
+        assert_eq!(parse_offset("-0530"), FixedOffset::west_opt(5 * 3600 + 30 * 60));
+        assert_eq!(parse_offset("Z"), FixedOffset::east_opt(0));
+        assert_eq!(parse_offset(" +01:00\0"), FixedOffset::east_opt(3600));
+        for invalid in ["", "+", "02:00", "+2:00", "+02:000", "+1é1", "é", "+ab:cd", "+99:00"] {
+            assert_eq!(parse_offset(invalid), None, "{:?}", invalid);
+        }
+    }
+}
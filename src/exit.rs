@@ -0,0 +1,41 @@
+use std::process::ExitCode;
+
+use tidyup::report::Failure;
+use tidyup::Error;
+
+/// Process exit codes, so scripts can tell a partial run from a broken one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Success = 0,
+    /// The run finished but some files could not be processed.
+    Partial = 1,
+    /// Invalid command line (also what clap exits with).
+    Usage = 2,
+    Config = 3,
+    /// Nothing or only part of the work was attempted.
+    Fatal = 4,
+}
+
+impl Exit {
+    pub fn from_failures(failures: &[Failure]) -> Exit {
+        if failures.is_empty() {
+            Exit::Success
+        } else {
+            Exit::Partial
+        }
+    }
+
+    pub fn from_error(error: &Error) -> Exit {
+        match error {
+            Error::Config(_) => Exit::Config,
+            Error::InvalidPattern(_) | Error::InvalidPlan(_) => Exit::Usage,
+            Error::Io(_) => Exit::Fatal,
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> ExitCode {
+        ExitCode::from(exit as u8)
+    }
+}
@@ -0,0 +1,108 @@
+//! The decision for one file, step by step, as [`Planner::explain`](crate::Planner::explain)
+//! records it while planning the file the way a run would.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::config::Category;
+use crate::conflict::Outcome;
+use crate::fsops::Transfer;
+use crate::plan::{Action, SkipReason};
+use crate::rules::{MatchMode, Rule, Trace};
+
+#[derive(Debug)]
+pub struct Decision<'a> {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Seconds since the last modification.
+    pub age: u64,
+    pub reach: Reach,
+    /// The primary of the bundle the file is a companion of; the rules and
+    /// category below are then the primary's.
+    pub companion_of: Option<PathBuf>,
+    pub companions: Vec<PathBuf>,
+    pub extension: String,
+    /// The type recognised from the content, or why it could not be read.
+    pub content: Result<Option<&'static str>, String>,
+    pub match_mode: MatchMode,
+    /// Every rule in the order it is evaluated.
+    pub rules: Vec<RuleStep<'a>>,
+    /// The category, looked up when no rule decides, or why there is none.
+    pub category: Option<Result<CategoryStep<'a>, SkipReason>>,
+    /// How `--extensions` and `--ignore` judged the file's type, once it
+    /// was known: `None` if it passed.
+    pub filter: Option<Option<SkipReason>>,
+    /// The file in the destination folder with the same content, when
+    /// `--dedup` is given.
+    pub duplicate_of: Option<PathBuf>,
+    pub placements: Vec<Placement>,
+    /// What a run would do, as [`Planner::plan_file`](crate::Planner::plan_file) plans it.
+    pub actions: Vec<Action>,
+}
+
+/// Whether a scan of the root gets to the file.
+#[derive(Debug)]
+pub enum Reach {
+    Reached,
+    /// Not below the root: only planned when listed.
+    Outside,
+    PassedOver(SkipReason),
+}
+
+#[derive(Debug)]
+pub struct RuleStep<'a> {
+    pub rule: &'a Rule,
+    pub trace: Trace,
+    /// Whether the rule is one of those that decide, which in `first` mode
+    /// is only the first that matches.
+    pub decides: bool,
+}
+
+#[derive(Debug)]
+pub struct CategoryStep<'a> {
+    pub category: &'a Category,
+    /// The extension the file was classified by.
+    pub kind: String,
+    /// Whether `kind` came from the content rather than the name.
+    pub by_content: bool,
+    /// The folder template, as written.
+    pub folder: String,
+}
+
+/// A file put at a destination, and what the conflict policy makes of it.
+#[derive(Debug)]
+pub struct Placement {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub transfer: Transfer,
+    /// Whether something is at the destination already.
+    pub taken: bool,
+    pub outcome: io::Result<Outcome>,
+}
+
+impl<'a> Decision<'a> {
+    pub(crate) fn new(path: PathBuf, match_mode: MatchMode) -> Decision<'a> {
+        Decision {
+            path,
+            size: 0,
+            age: 0,
+            reach: Reach::Reached,
+            companion_of: None,
+            companions: Vec::new(),
+            extension: String::new(),
+            content: Ok(None),
+            match_mode,
+            rules: Vec::new(),
+            category: None,
+            filter: None,
+            duplicate_of: None,
+            placements: Vec::new(),
+            actions: Vec::new(),
+        }
+    }
+
+    /// The rules that decide, in order.
+    pub fn deciding(&self) -> impl Iterator<Item = &'a Rule> + '_ {
+        self.rules.iter().filter(|step| step.decides).map(|step| step.rule)
+    }
+}
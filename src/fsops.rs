@@ -0,0 +1,535 @@
+use std::ffi::{CString, OsStr};
+use std::fmt;
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{fchown, symlink, MetadataExt, OpenOptionsExt};
+use std::path::{self as stdpath, Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// How a file gets to its destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transfer {
+    #[default]
+    Move,
+    /// A copy with the original's permissions, owner, times and extended attributes.
+    Copy,
+    /// A symbolic link to the original, by absolute path or relative to the link.
+    Symlink { absolute: bool },
+    /// A hard link to the original, or a copy where that is not possible.
+    Hardlink,
+}
+
+impl Transfer {
+    /// The name used in plan files and JSON output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Transfer::Move => "move",
+            Transfer::Copy => "copy",
+            Transfer::Symlink { absolute: false } => "symlink",
+            Transfer::Symlink { absolute: true } => "absolute_symlink",
+            Transfer::Hardlink => "hardlink",
+        }
+    }
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Transfer, String> {
+        match name {
+            "move" => Ok(Transfer::Move),
+            "copy" => Ok(Transfer::Copy),
+            "symlink" => Ok(Transfer::Symlink { absolute: false }),
+            "absolute_symlink" => Ok(Transfer::Symlink { absolute: true }),
+            "hardlink" => Ok(Transfer::Hardlink),
+            _ => Err(format!("unknown transfer `{}`", name)),
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Transfer::Move => "move",
+            Transfer::Copy => "copy",
+            Transfer::Symlink { absolute: false } => "symlink",
+            Transfer::Symlink { absolute: true } => "absolute symlink",
+            Transfer::Hardlink => "hardlink",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Moves files, falling back to copy + verify + delete when the destination
+/// is on another filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mover {
+    pub verify_checksum: bool,
+}
+
+impl Mover {
+    /// Moves `from` to `to`, failing with `AlreadyExists` if `to` exists.
+    pub fn move_noreplace(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match rename_noreplace(from, to) {
+            Err(e) if is_cross_device(&e) => self.copy_then_unlink(from, to, false),
+            result => result,
+        }
+    }
+
+    /// Moves `from` to `to`, replacing whatever is at `to`.
+    pub fn move_replace(&self, from: &Path, to: &Path) -> io::Result<()> {
+        match fs::rename(from, to) {
+            Err(e) if is_cross_device(&e) => self.copy_then_unlink(from, to, true),
+            result => result,
+        }
+    }
+
+    /// Puts `from` at `to` the way `transfer` says, failing with
+    /// `AlreadyExists` if `to` exists.
+    pub fn transfer_noreplace(&self, transfer: Transfer, from: &Path, to: &Path) -> io::Result<()> {
+        match transfer {
+            Transfer::Move => self.move_noreplace(from, to),
+            Transfer::Copy => self.copy_into_place(from, to, false),
+            Transfer::Symlink { absolute } => symlink(link_target(from, to, absolute)?, to),
+            Transfer::Hardlink => match fs::hard_link(from, to) {
+                Err(e) if is_cross_device(&e) => self.copy_into_place(from, to, false),
+                result => result,
+            },
+        }
+    }
+
+    /// Puts `from` at `to` the way `transfer` says, replacing whatever is at `to`.
+    pub fn transfer_replace(&self, transfer: Transfer, from: &Path, to: &Path) -> io::Result<()> {
+        let temp = temp_path(to);
+        let created = match transfer {
+            Transfer::Move => return self.move_replace(from, to),
+            Transfer::Copy => return self.copy_into_place(from, to, true),
+            Transfer::Symlink { absolute } => link_target(from, to, absolute).and_then(|target| symlink(target, &temp)),
+            Transfer::Hardlink => match fs::hard_link(from, &temp) {
+                Err(e) if is_cross_device(&e) => return self.copy_into_place(from, to, true),
+                result => result,
+            },
+        };
+        created?;
+        if let Err(e) = fs::rename(&temp, to) {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn copy_into_place(&self, from: &Path, to: &Path, replace: bool) -> io::Result<()> {
+        let temp = temp_path(to);
+        if let Err(e) = self.copy_verified(from, &temp) {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        let placed = if replace { fs::rename(&temp, to) } else { rename_noreplace(&temp, to) };
+        if let Err(e) = placed {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn copy_then_unlink(&self, from: &Path, to: &Path, replace: bool) -> io::Result<()> {
+        self.copy_into_place(from, to, replace)?;
+        if let Some(parent) = to.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+
+        if let Err(e) = fs::remove_file(from) {
+            // Leave things as they were rather than with two copies.
+            let _ = fs::remove_file(to);
+            return Err(io::Error::new(e.kind(), format!("copied but cannot remove {}: {}", from.display(), e)));
+        }
+        Ok(())
+    }
+
+    fn copy_verified(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut input = File::open(from)?;
+        let metadata = input.metadata()?;
+        let mut output = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            // Private and writable until the attributes are copied; the
+            // source's mode is set last.
+            .mode(0o600)
+            .open(to)?;
+
+        let mut hasher = self.verify_checksum.then(blake3::Hasher::new);
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut copied = 0u64;
+        loop {
+            let n = match input.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            output.write_all(&buffer[..n])?;
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&buffer[..n]);
+            }
+            copied += n as u64;
+        }
+
+        // Ownership can only be kept when running with enough privileges.
+        let _ = fchown(&output, Some(metadata.uid()), Some(metadata.gid()));
+        // A read-only mode would refuse the xattrs, so they go first.
+        copy_xattrs(from, to)?;
+        output.set_permissions(metadata.permissions())?;
+        output.set_times(FileTimes::new().set_accessed(metadata.accessed()?).set_modified(metadata.modified()?))?;
+        output.sync_all()?;
+
+        let written = output.metadata()?.len();
+        if copied != metadata.len() || written != metadata.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("size mismatch copying {}: expected {} bytes, wrote {}", from.display(), metadata.len(), written),
+            ));
+        }
+        if let Some(hasher) = hasher {
+            let expected = hasher.finalize();
+            let mut check = blake3::Hasher::new();
+            check.update_reader(File::open(to)?)?;
+            if check.finalize() != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checksum mismatch copying {}", from.display()),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `to` already is what `transfer` would make of `from`, so that
+/// copying or linking again would only add a numbered twin.
+pub fn is_transferred(transfer: Transfer, from: &Path, to: &Path) -> bool {
+    let (Ok(source), Ok(target)) = (fs::metadata(from), fs::symlink_metadata(to)) else {
+        return false;
+    };
+    let same_file = (source.dev(), source.ino()) == (target.dev(), target.ino());
+    let same_copy = target.is_file() && target.len() == source.len() && target.mtime() == source.mtime();
+    match transfer {
+        Transfer::Move => false,
+        Transfer::Copy => same_copy,
+        Transfer::Symlink { .. } => target.is_symlink() && fs::metadata(to).is_ok_and(|linked| (linked.dev(), linked.ino()) == (source.dev(), source.ino())),
+        Transfer::Hardlink => same_file || same_copy,
+    }
+}
+
+/// `path` with `.` and `..` resolved without looking at the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// What a symlink at `to` should contain to point at `from`.
+fn link_target(from: &Path, to: &Path, absolute: bool) -> io::Result<PathBuf> {
+    let from = stdpath::absolute(from)?;
+    if absolute {
+        return Ok(from);
+    }
+    let dir = stdpath::absolute(to)?.parent().map(Path::to_path_buf).unwrap_or_default();
+    let (from_parts, dir_parts): (Vec<_>, Vec<_>) = (from.components().collect(), dir.components().collect());
+    let common = from_parts.iter().zip(&dir_parts).take_while(|(a, b)| a == b).count();
+    let mut target: PathBuf = dir_parts[common..].iter().map(|_| Component::ParentDir).collect();
+    target.extend(&from_parts[common..]);
+    Ok(target)
+}
+
+fn is_cross_device(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EXDEV)
+}
+
+fn temp_path(to: &Path) -> PathBuf {
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    to.with_file_name(format!(".{}.tidyup-{}.tmp", name, process::id()))
+}
+
+fn copy_xattrs(from: &Path, to: &Path) -> io::Result<()> {
+    let from_c = cstring(from)?;
+    let to_c = cstring(to)?;
+
+    let names = match xattr_call(|buf, len| unsafe { libc::llistxattr(from_c.as_ptr(), buf as *mut libc::c_char, len) }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(libc::ENOTSUP) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name_c = cstring(Path::new(OsStr::from_bytes(name)))?;
+        let value = match xattr_call(|buf, len| unsafe { libc::lgetxattr(from_c.as_ptr(), name_c.as_ptr(), buf, len) }) {
+            Ok(value) => value,
+            Err(e) if is_ignorable(&e, name) || e.raw_os_error() == Some(libc::ENODATA) => continue,
+            Err(e) => return Err(e),
+        };
+        let ret = unsafe {
+            libc::lsetxattr(to_c.as_ptr(), name_c.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0)
+        };
+        if ret != 0 {
+            let e = io::Error::last_os_error();
+            if !is_ignorable(&e, name) {
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs a size-query-then-fill xattr syscall, retrying if the value grows in between.
+fn xattr_call(call: impl Fn(*mut libc::c_void, usize) -> isize) -> io::Result<Vec<u8>> {
+    loop {
+        let size = call(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let n = call(buf.as_mut_ptr() as *mut libc::c_void, buf.len());
+        if n >= 0 {
+            buf.truncate(n as usize);
+            return Ok(buf);
+        }
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::ERANGE) {
+            return Err(e);
+        }
+    }
+}
+
+/// Whether copying the xattr `name` may fail with `e` without failing the copy:
+/// the target may not support its namespace at all, and security.* and
+/// trusted.* need privileges.
+fn is_ignorable(e: &io::Error, name: &[u8]) -> bool {
+    match e.raw_os_error() {
+        Some(libc::ENOTSUP) => true,
+        Some(libc::EPERM) => name.starts_with(b"security.") || name.starts_with(b"trusted."),
+        _ => false,
+    }
+}
+
+/// Replaces `path` with a hard link to `original`, in one step as far as
+/// other processes can tell.
+pub fn replace_with_link(original: &Path, path: &Path) -> io::Result<()> {
+    let temp = temp_path(path);
+    fs::hard_link(original, &temp)?;
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Replaces the hard link at `path` with a copy of `original` of its own,
+/// last modified at `modified`, in one step as far as other processes can tell.
+pub fn replace_with_copy(original: &Path, path: &Path, modified: SystemTime) -> io::Result<()> {
+    let temp = temp_path(path);
+    let copied = Mover::default()
+        .copy_verified(original, &temp)
+        .and_then(|()| File::open(&temp)?.set_modified(modified))
+        .and_then(|()| fs::rename(&temp, path));
+    if copied.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    copied
+}
+
+/// Renames `from` to `to`, failing with `AlreadyExists` instead of replacing
+/// an existing `to`.
+pub fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    match renameat2_noreplace(from, to) {
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS)) => {
+            // The filesystem cannot do it in one step; link() refuses to
+            // replace as well, so the only window left is between link and unlink.
+            fs::hard_link(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn renameat2_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    let from = cstring(from)?;
+    let to = cstring(to)?;
+    let ret = unsafe {
+        libc::renameat2(libc::AT_FDCWD, from.as_ptr(), libc::AT_FDCWD, to.as_ptr(), libc::RENAME_NOREPLACE)
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn renameat2_noreplace(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+pub fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    /// Sets the xattr `name` on `path`; `false` where the filesystem has no user xattrs.
+    fn set_xattr(path: &Path, name: &str, value: &[u8]) -> bool {
+        let (path, name) = (cstring(path).unwrap(), CString::new(name).unwrap());
+        let ret = unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0) };
+        ret == 0
+    }
+
+    fn get_xattr(path: &Path, name: &str) -> Option<Vec<u8>> {
+        let (path, name) = (cstring(path).unwrap(), CString::new(name).unwrap());
+        xattr_call(|buf, len| unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf, len) }).ok()
+    }
+
+    #[test]
+    fn copies_content_permissions_and_times() {
+        let dir = TestDir::new();
+        let from = dir.file("a.bin", &vec![42; BUFFER_SIZE + 7]);
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o640)).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(&from).unwrap().set_modified(mtime).unwrap();
+        let to = dir.path().join("b.bin");
+
+        Mover { verify_checksum: true }.copy_verified(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), fs::read(&from).unwrap());
+        let metadata = fs::metadata(&to).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+        assert!(from.exists());
+    }
+
+    #[test]
+    fn copies_user_xattrs() {
+        let dir = TestDir::new();
+        let from = dir.file("a.txt", b"x");
+        if !set_xattr(&from, "user.tidyup.test", b"value") {
+            return;
+        }
+        let to = dir.path().join("b.txt");
+        Mover::default().copy_verified(&from, &to).unwrap();
+        assert_eq!(get_xattr(&to, "user.tidyup.test").as_deref(), Some(&b"value"[..]));
+    }
+
+    #[test]
+    fn copies_xattrs_of_read_only_files() {
+        let dir = TestDir::new();
+        let from = dir.file("a.txt", b"x");
+        if !set_xattr(&from, "user.tidyup.test", b"value") {
+            return;
+        }
+        fs::set_permissions(&from, fs::Permissions::from_mode(0o444)).unwrap();
+        let to = dir.path().join("b.txt");
+        Mover::default().copy_verified(&from, &to).unwrap();
+        assert_eq!(get_xattr(&to, "user.tidyup.test").as_deref(), Some(&b"value"[..]));
+        assert_eq!(fs::metadata(&to).unwrap().permissions().mode() & 0o7777, 0o444);
+    }
+
+    #[test]
+    fn ignores_only_unsupported_or_privileged_xattrs() {
+        let error = |code| io::Error::from_raw_os_error(code);
+        assert!(is_ignorable(&error(libc::ENOTSUP), b"user.a"));
+        assert!(is_ignorable(&error(libc::EPERM), b"security.selinux"));
+        assert!(is_ignorable(&error(libc::EPERM), b"trusted.a"));
+        assert!(!is_ignorable(&error(libc::EPERM), b"user.a"));
+        assert!(!is_ignorable(&error(libc::EACCES), b"user.a"));
+        assert!(!is_ignorable(&error(libc::ENOSPC), b"security.selinux"));
+    }
+
+    #[test]
+    fn copy_never_replaces() {
+        let dir = TestDir::new();
+        let from = dir.file("a.txt", b"new");
+        let to = dir.file("b.txt", b"old");
+        let e = Mover::default().copy_verified(&from, &to).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&to).unwrap(), b"old");
+    }
+
+    #[test]
+    fn copy_then_unlink_leaves_one_file() {
+        let dir = TestDir::new();
+        let from = dir.file("a.txt", b"content");
+        let to = dir.file("sub/a.txt", b"old");
+
+        let e = Mover::default().copy_then_unlink(&from, &to, false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert!(from.exists());
+
+        Mover::default().copy_then_unlink(&from, &to, true).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).unwrap(), b"content");
+        assert_eq!(fs::read_dir(dir.path().join("sub")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn copies_and_links_without_touching_the_source() {
+        let dir = TestDir::new();
+        let source = dir.file("a/photo.jpg", b"x");
+        fs::create_dir_all(dir.path().join("b/c")).unwrap();
+        let mover = Mover::default();
+
+        let copy = dir.path().join("b/copy.jpg");
+        mover.transfer_noreplace(Transfer::Copy, &source, &copy).unwrap();
+        let hardlink = dir.path().join("b/hardlink.jpg");
+        mover.transfer_noreplace(Transfer::Hardlink, &source, &hardlink).unwrap();
+        let relative = dir.path().join("b/c/relative.jpg");
+        mover.transfer_noreplace(Transfer::Symlink { absolute: false }, &source, &relative).unwrap();
+        let absolute = dir.path().join("b/absolute.jpg");
+        mover.transfer_noreplace(Transfer::Symlink { absolute: true }, &source, &absolute).unwrap();
+
+        assert_eq!(fs::read(&source).unwrap(), b"x");
+        assert_eq!(fs::read(&copy).unwrap(), b"x");
+        assert_eq!(fs::metadata(&hardlink).unwrap().ino(), fs::metadata(&source).unwrap().ino());
+        assert_eq!(fs::read_link(&relative).unwrap(), Path::new("../../a/photo.jpg"));
+        assert_eq!(fs::read_link(&absolute).unwrap(), source);
+        for (transfer, path) in [
+            (Transfer::Copy, &copy),
+            (Transfer::Hardlink, &hardlink),
+            (Transfer::Symlink { absolute: false }, &relative),
+            (Transfer::Symlink { absolute: true }, &absolute),
+        ] {
+            assert!(is_transferred(transfer, &source, path), "{} {}", transfer, path.display());
+            let e = mover.transfer_noreplace(transfer, &source, path).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        }
+        assert!(!is_transferred(Transfer::Move, &source, &copy));
+        assert!(!is_transferred(Transfer::Hardlink, &source, &relative));
+    }
+
+    #[test]
+    fn replaces_with_a_link() {
+        let dir = TestDir::new();
+        let source = dir.file("a.txt", b"new");
+        let destination = dir.file("b/a.txt", b"old");
+        Mover::default().transfer_replace(Transfer::Symlink { absolute: false }, &source, &destination).unwrap();
+        assert_eq!(fs::read_link(&destination).unwrap(), Path::new("../a.txt"));
+        assert_eq!(fs::read(&destination).unwrap(), b"new");
+        assert_eq!(fs::read_dir(dir.path().join("b")).unwrap().count(), 1);
+    }
+}
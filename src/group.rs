@@ -0,0 +1,16 @@
+//! Files sorted by category without moving them, as `tidyup group` lists
+//! them; see [`Planner::group`](crate::Planner::group).
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::report::Failure;
+
+#[derive(Debug, Default)]
+pub struct Groups {
+    /// The files of each category, by category name.
+    pub groups: BTreeMap<String, Vec<PathBuf>>,
+    /// The files no category takes, and why.
+    pub ungrouped: Vec<(PathBuf, String)>,
+    pub failures: Vec<Failure>,
+}
@@ -0,0 +1,171 @@
+//! `--interactive`: asks about every planned action before anything happens.
+
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+use std::mem;
+
+use tidyup::{Action, Plan};
+
+const HELP: &str = "\
+y - do it
+n - leave this file alone
+a - do it and everything else of this category without asking
+s - leave this file and everything else of this category alone
+q - leave this and all remaining files alone
+? - print this help";
+
+/// Asks on stderr about each action of `plan`, reading the answers from
+/// `input`, and turns the declined ones into skips. The end of `input` counts as quit.
+pub fn confirm(plan: &mut Plan, input: &mut dyn BufRead) -> io::Result<()> {
+    let mut always = HashSet::new();
+    let mut never = HashSet::new();
+    let mut quit = false;
+    let mut declined = Vec::new();
+
+    for action in mem::take(&mut plan.actions) {
+        if let Action::Skip { .. } = action {
+            plan.actions.push(action);
+            continue;
+        }
+        let group = group(&action).to_string();
+        let accepted = if quit || never.contains(&group) {
+            false
+        } else if always.contains(&group) {
+            true
+        } else {
+            loop {
+                eprint!("{} [y,n,a,s,q,?] ", describe(&action));
+                let mut answer = String::new();
+                if input.read_line(&mut answer)? == 0 {
+                    eprintln!();
+                    quit = true;
+                    break false;
+                }
+                match answer.trim() {
+                    "y" | "yes" => break true,
+                    "n" | "no" => break false,
+                    "a" => {
+                        always.insert(group.clone());
+                        break true;
+                    }
+                    "s" => {
+                        never.insert(group.clone());
+                        break false;
+                    }
+                    "q" => {
+                        quit = true;
+                        break false;
+                    }
+                    _ => eprintln!("{}", HELP),
+                }
+            }
+        };
+        if accepted {
+            plan.actions.push(action);
+        } else {
+            declined.extend(action.declined());
+        }
+    }
+    plan.actions.extend(declined);
+    Ok(())
+}
+
+/// What "this category" means for an action.
+fn group(action: &Action) -> &str {
+    match action {
+        Action::Move { category, .. } | Action::Bundle { category, .. } => category,
+        Action::Trash { .. } => "trash",
+        Action::Link { .. } => "duplicates",
+        Action::Skip { .. } => "",
+    }
+}
+
+fn describe(action: &Action) -> String {
+    match action {
+        Action::Move { source, destination, category, transfer } => {
+            format!("{} {} -> {} [{}]?", transfer, source.display(), destination.display(), category)
+        }
+        Action::Bundle { members, category, transfer } => format!(
+            "{} {} (and {} companions) -> {} [{}]?",
+            transfer,
+            members[0].0.display(),
+            members.len() - 1,
+            members[0].1.display(),
+            category
+        ),
+        Action::Trash { path, reason } => format!("trash {} ({})?", path.display(), reason),
+        Action::Link { path, original } => {
+            format!("replace {} with a hard link to {}?", path.display(), original.display())
+        }
+        Action::Skip { path, .. } => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+    use tidyup::fsops::Transfer;
+    use tidyup::plan::SkipReason;
+
+    fn moved(name: &str, category: &str) -> Action {
+        Action::Move {
+            source: PathBuf::from(name),
+            destination: Path::new(category).join(name),
+            category: category.to_string(),
+            transfer: Transfer::Move,
+        }
+    }
+
+    /// The paths that are still moved and those that were declined, after answering `answers`.
+    fn answer(actions: Vec<Action>, answers: &str) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut plan = Plan { actions, ..Plan::default() };
+        confirm(&mut plan, &mut Cursor::new(answers)).unwrap();
+        let mut accepted = Vec::new();
+        let mut declined = Vec::new();
+        for action in &plan.actions {
+            match action {
+                Action::Skip { path, reason: SkipReason::Declined } => declined.push(path.clone()),
+                action => accepted.push(action.path().to_path_buf()),
+            }
+        }
+        (accepted, declined)
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn asks_about_each_action() {
+        let actions = vec![moved("a.jpg", "images"), moved("b.jpg", "images"), moved("c.pdf", "documents")];
+        assert_eq!(answer(actions, "y\nhuh?\nno\nyes\n"), (paths(&["a.jpg", "c.pdf"]), paths(&["b.jpg"])));
+    }
+
+    #[test]
+    fn remembers_answers_for_the_whole_category() {
+        let actions = vec![
+            moved("a.jpg", "images"),
+            moved("b.pdf", "documents"),
+            moved("c.jpg", "images"),
+            moved("d.pdf", "documents"),
+        ];
+        assert_eq!(answer(actions, "a\ns\n"), (paths(&["a.jpg", "c.jpg"]), paths(&["b.pdf", "d.pdf"])));
+    }
+
+    #[test]
+    fn quitting_or_running_out_of_input_declines_the_rest() {
+        let actions = || vec![moved("a.jpg", "images"), moved("b.jpg", "images"), moved("c.jpg", "images")];
+        assert_eq!(answer(actions(), "y\nq\n"), (paths(&["a.jpg"]), paths(&["b.jpg", "c.jpg"])));
+        assert_eq!(answer(actions(), "y\n"), (paths(&["a.jpg"]), paths(&["b.jpg", "c.jpg"])));
+    }
+
+    #[test]
+    fn keeps_skips_without_asking() {
+        let skip = Action::Skip { path: PathBuf::from("notes.txt"), reason: SkipReason::NoExtension };
+        let mut plan = Plan { actions: vec![skip], ..Plan::default() };
+        confirm(&mut plan, &mut Cursor::new("")).unwrap();
+        assert!(matches!(plan.actions.as_slice(), [Action::Skip { reason: SkipReason::NoExtension, .. }]));
+    }
+}
@@ -0,0 +1,439 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{self as stdpath, Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::fsops::{self, Mover};
+use crate::trash::Trashed;
+use crate::xdg;
+
+const MAX_SUFFIX: u32 = 10_000;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Run { run_id: String, timestamp: String, root: PathBuf },
+    CreateDir { path: PathBuf },
+    Move { source: PathBuf, destination: PathBuf, size: u64, mtime: i64, mtime_nsec: i64 },
+    /// A copy or link made at `destination`, described by its own metadata;
+    /// undo removes it and leaves `source` alone.
+    Copy { source: PathBuf, destination: PathBuf, size: u64, mtime: i64, mtime_nsec: i64 },
+    /// A move into the trash; undo also removes the `.trashinfo` file.
+    Trash { source: PathBuf, destination: PathBuf, info: PathBuf, size: u64, mtime: i64, mtime_nsec: i64 },
+    /// A file at `path` replaced with a hard link to `original`, described by
+    /// the file's metadata from before; undo gives it a copy of its own again.
+    Link { path: PathBuf, original: PathBuf, size: u64, mtime: i64, mtime_nsec: i64 },
+    Undo { timestamp: String },
+}
+
+pub struct Journal {
+    pub run_id: String,
+    file: File,
+}
+
+impl Journal {
+    pub fn create(root: &Path) -> io::Result<Journal> {
+        let dir = journal_dir()?;
+        fs::create_dir_all(&dir)?;
+
+        // Microseconds keep ids in order; the suffix separates runs that
+        // still start at the same time, such as back-to-back watch passes.
+        let now = Local::now();
+        let base = format!("{}-{}", now.format("%Y%m%dT%H%M%S%.6f"), process::id());
+        let mut n = 1;
+        let (run_id, file) = loop {
+            let run_id = if n == 1 { base.clone() } else { format!("{}-{}", base, n) };
+            match OpenOptions::new().create_new(true).append(true).open(dir.join(format!("{}.jsonl", run_id))) {
+                Ok(file) => break (run_id, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && n < MAX_SUFFIX => n += 1,
+                Err(e) => return Err(e),
+            }
+        };
+
+        let mut journal = Journal { run_id: run_id.clone(), file };
+        journal.append(&Record::Run {
+            run_id,
+            timestamp: now.to_rfc3339(),
+            root: stdpath::absolute(root)?,
+        })?;
+        Ok(journal)
+    }
+
+    pub fn record_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.append(&Record::CreateDir { path: stdpath::absolute(path)? })
+    }
+
+    /// Records a move; `metadata` is taken from the file before it is moved.
+    pub fn record_move(&mut self, source: &Path, destination: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+        self.append(&Record::Move {
+            source: stdpath::absolute(source)?,
+            destination: stdpath::absolute(destination)?,
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+
+    /// Records a copy or link, once it is in place.
+    pub fn record_copy(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(destination)?;
+        self.append(&Record::Copy {
+            source: stdpath::absolute(source)?,
+            destination: stdpath::absolute(destination)?,
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+
+    /// Records a file put in the trash; `metadata` is taken from the file before it is moved.
+    pub fn record_trash(&mut self, source: &Path, trashed: &Trashed, metadata: &fs::Metadata) -> io::Result<()> {
+        self.append(&Record::Trash {
+            source: stdpath::absolute(source)?,
+            destination: trashed.file.clone(),
+            info: trashed.info.clone(),
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+
+    /// Records a file replaced with a hard link; `metadata` is taken from the file before it is replaced.
+    pub fn record_link(&mut self, path: &Path, original: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+        self.append(&Record::Link {
+            path: stdpath::absolute(path)?,
+            original: stdpath::absolute(original)?,
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        append_record(&mut self.file, record)
+    }
+}
+
+/// What undoing one journal entry takes.
+#[derive(PartialEq, Eq)]
+enum Change {
+    Move,
+    Copy,
+    /// A move into the trash, whose info file goes as well.
+    Trash(PathBuf),
+    /// A hard link at the source to the file at the destination.
+    Link,
+}
+
+#[derive(Debug, Default)]
+pub struct UndoReport {
+    pub run_id: String,
+    pub restored: Vec<PathBuf>,
+    /// Copies and links that were removed.
+    pub removed: Vec<PathBuf>,
+    pub removed_dirs: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+pub fn journal_dir() -> io::Result<PathBuf> {
+    xdg::state_home()
+        .map(|dir| dir.join("tidyup"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cannot determine state directory; set XDG_STATE_HOME or HOME"))
+}
+
+/// Reverts the run `run_id`, or the most recent run that has not been undone yet.
+pub fn undo(run_id: Option<&str>) -> io::Result<UndoReport> {
+    let dir = journal_dir()?;
+    let path = match run_id {
+        Some(run_id) => dir.join(format!("{}.jsonl", run_id)),
+        None => latest_run(&dir)?,
+    };
+    let records = read_records(&path)?;
+
+    let mut report = UndoReport::default();
+    let mut created_dirs = Vec::new();
+    let mut moves = Vec::new();
+    for record in records {
+        match record {
+            Record::Run { run_id, .. } => report.run_id = run_id,
+            Record::CreateDir { path } => created_dirs.push(path),
+            Record::Move { source, destination, size, mtime, mtime_nsec } => {
+                moves.push((source, destination, size, (mtime, mtime_nsec), Change::Move));
+            }
+            Record::Copy { source, destination, size, mtime, mtime_nsec } => {
+                moves.push((source, destination, size, (mtime, mtime_nsec), Change::Copy));
+            }
+            Record::Trash { source, destination, info, size, mtime, mtime_nsec } => {
+                moves.push((source, destination, size, (mtime, mtime_nsec), Change::Trash(info)));
+            }
+            Record::Link { path, original, size, mtime, mtime_nsec } => {
+                moves.push((path, original, size, (mtime, mtime_nsec), Change::Link));
+            }
+            Record::Undo { .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("run {} has already been undone", report.run_id),
+                ));
+            }
+        }
+    }
+
+    for (source, destination, size, mtime, change) in moves.into_iter().rev() {
+        let matches = |path: &Path| match fs::symlink_metadata(path) {
+            Ok(metadata) => {
+                (metadata.is_file() || change == Change::Copy)
+                    && metadata.len() == size
+                    && (metadata.mtime(), metadata.mtime_nsec()) == mtime
+            }
+            Err(_) => false,
+        };
+
+        if change == Change::Link {
+            let linked = match (fs::symlink_metadata(&source), fs::metadata(&destination)) {
+                (Ok(link), Ok(original)) => (link.dev(), link.ino()) == (original.dev(), original.ino()) && link.len() == size,
+                _ => false,
+            };
+            if !linked {
+                report.failed.push((source.clone(), format!("{} is no longer a link to {}", source.display(), destination.display())));
+                continue;
+            }
+            let modified = SystemTime::UNIX_EPOCH + Duration::new(mtime.0.max(0) as u64, mtime.1 as u32);
+            match fsops::replace_with_copy(&destination, &source, modified) {
+                Ok(()) => report.restored.push(source),
+                Err(e) => report.failed.push((source, e.to_string())),
+            }
+            continue;
+        }
+
+        if change == Change::Copy {
+            if fs::symlink_metadata(&destination).is_err() {
+                continue;
+            }
+            if !matches(&destination) {
+                report.failed.push((destination.clone(), format!("{} has changed since it was made", destination.display())));
+                continue;
+            }
+            match fs::remove_file(&destination) {
+                Ok(()) => report.removed.push(destination),
+                Err(e) => report.failed.push((destination, e.to_string())),
+            }
+            continue;
+        }
+
+        if !destination.exists() {
+            if matches(&source) {
+                report.restored.push(source);
+            } else {
+                report.failed.push((source, format!("{} no longer exists", destination.display())));
+            }
+            continue;
+        }
+        if !matches(&destination) {
+            report.failed.push((source, format!("{} has changed since it was moved", destination.display())));
+            continue;
+        }
+        match Mover::default().move_noreplace(&destination, &source) {
+            Ok(()) => {
+                if let Change::Trash(info) = change {
+                    let _ = fs::remove_file(info);
+                }
+                report.restored.push(source);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                report.failed.push((source.clone(), format!("{} is occupied by another file", source.display())));
+            }
+            Err(e) => report.failed.push((source, e.to_string())),
+        }
+    }
+
+    for dir in created_dirs.into_iter().rev() {
+        if is_empty_dir(&dir) && fs::remove_dir(&dir).is_ok() {
+            report.removed_dirs.push(dir);
+        }
+    }
+
+    if report.failed.is_empty() {
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        append_record(&mut file, &Record::Undo { timestamp: Local::now().to_rfc3339() })?;
+    }
+    Ok(report)
+}
+
+fn latest_run(dir: &Path) -> io::Result<PathBuf> {
+    let mut runs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    runs.sort();
+
+    for path in runs.into_iter().rev() {
+        let records = read_records(&path)?;
+        if !records.iter().any(|r| matches!(r, Record::Undo { .. })) {
+            return Ok(path);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no run left to undo"))
+}
+
+fn read_records(path: &Path) -> io::Result<Vec<Record>> {
+    let file = File::open(path).map_err(|e| {
+        io::Error::new(e.kind(), format!("cannot open journal {}: {}", path.display(), e))
+    })?;
+    let mut records = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path.display(), number + 1, e),
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn append_record(file: &mut File, record: &Record) -> io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.sync_data()
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).map(|mut entries| entries.next().is_none()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute::Executor;
+    use crate::plan::{Action, Plan};
+    use crate::report::Event;
+    use crate::testdir::{self, TestDir};
+
+    /// Writes a journal for `run_id` by hand, so that tests need not share
+    /// the run ids [`Journal::create`] derives from the clock.
+    fn journal(run_id: &str, records: &[Record]) {
+        let dir = journal_dir().unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        let mut file = File::create(dir.join(format!("{}.jsonl", run_id))).unwrap();
+        for record in records {
+            append_record(&mut file, record).unwrap();
+        }
+    }
+
+    fn moved(source: &Path, destination: &Path) -> Record {
+        let metadata = fs::metadata(destination).unwrap();
+        Record::Move {
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+
+    #[test]
+    fn records_a_run_and_undoes_it() {
+        testdir::xdg_home();
+        let dir = TestDir::new();
+        let source = dir.file("a.png", b"x");
+        let images = dir.path().join("images");
+        let destination = images.join("a.png");
+
+        let mut journal = Journal::create(dir.path()).unwrap();
+        fs::create_dir(&images).unwrap();
+        journal.record_dir(&images).unwrap();
+        let metadata = fs::symlink_metadata(&source).unwrap();
+        fs::rename(&source, &destination).unwrap();
+        journal.record_move(&source, &destination, &metadata).unwrap();
+
+        let report = undo(Some(&journal.run_id)).unwrap();
+        assert_eq!(report.run_id, journal.run_id);
+        assert_eq!(report.restored, [source.as_path()]);
+        assert_eq!(report.removed_dirs, [images.as_path()]);
+        assert!(report.failed.is_empty());
+        assert!(source.exists() && !images.exists());
+
+        let again = undo(Some(&journal.run_id)).unwrap_err();
+        assert!(again.to_string().contains("already been undone"), "{}", again);
+    }
+
+    #[test]
+    fn leaves_changed_and_occupied_files_alone() {
+        testdir::xdg_home();
+        let dir = TestDir::new();
+        let changed = dir.file("images/changed.png", b"x");
+        let occupied = dir.file("images/occupied.png", b"x");
+        let records = [
+            Record::Run { run_id: "test-changed".to_string(), timestamp: String::new(), root: dir.path().to_path_buf() },
+            moved(&dir.path().join("changed.png"), &changed),
+            moved(&dir.path().join("occupied.png"), &occupied),
+        ];
+        journal("test-changed", &records);
+        fs::write(&changed, b"edited").unwrap();
+        dir.file("occupied.png", b"new");
+
+        let report = undo(Some("test-changed")).unwrap();
+        assert!(report.restored.is_empty());
+        let failed: Vec<_> = report.failed.iter().map(|(path, _)| path.file_name().unwrap()).collect();
+        assert_eq!(failed, ["occupied.png", "changed.png"]);
+        assert!(changed.exists() && occupied.exists());
+        // Nothing was undone, so the run can be undone again once fixed.
+        let records = read_records(&journal_dir().unwrap().join("test-changed.jsonl")).unwrap();
+        assert!(!records.iter().any(|record| matches!(record, Record::Undo { .. })));
+    }
+
+    #[test]
+    fn undoes_hard_links_made_for_duplicates() {
+        testdir::xdg_home();
+        let dir = TestDir::new();
+        let original = dir.file("images/a.png", b"same");
+        let copy = dir.file("a (1).png", b"same");
+        let mtime = fs::metadata(&copy).unwrap().modified().unwrap();
+        let plan = Plan {
+            root: dir.path().to_path_buf(),
+            actions: vec![Action::Link { path: copy.clone(), original: original.clone() }],
+            ..Plan::default()
+        };
+
+        let (summary, run_id) = Executor::default().run(plan, &mut |_: Event<'_>| {}).unwrap();
+        assert_eq!(summary.linked, 1);
+        assert_eq!(fs::metadata(&copy).unwrap().ino(), fs::metadata(&original).unwrap().ino());
+
+        let report = undo(run_id.as_deref()).unwrap();
+        assert_eq!(report.restored, [copy.as_path()]);
+        assert!(report.failed.is_empty());
+        let restored = fs::metadata(&copy).unwrap();
+        assert_ne!(restored.ino(), fs::metadata(&original).unwrap().ino());
+        assert_eq!(restored.modified().unwrap(), mtime);
+        assert_eq!(fs::read(&copy).unwrap(), b"same");
+    }
+
+    #[test]
+    fn reports_a_missing_destination() {
+        testdir::xdg_home();
+        let dir = TestDir::new();
+        let source = dir.path().join("gone.png");
+        let destination = dir.file("images/gone.png", b"x");
+        journal("test-gone", &[moved(&source, &destination)]);
+        fs::remove_file(&destination).unwrap();
+
+        let report = undo(Some("test-gone")).unwrap();
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed[0].1.ends_with("no longer exists"), "{}", report.failed[0].1);
+    }
+}
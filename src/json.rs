@@ -0,0 +1,222 @@
+//! The machine-readable forms of plans, results and events, for `--format json`
+//! and `--events ndjson`.
+//!
+//! [`VERSION`] goes up whenever a field is renamed, removed or changes meaning;
+//! new fields and event types do not change it.
+
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use tidyup::plan::{SkipReason, TrashReason};
+use tidyup::report::{Event, Failure, Summary};
+use tidyup::{Action, Plan};
+
+pub const VERSION: u32 = 1;
+
+pub fn plan(plan: &Plan) -> Value {
+    json!({
+        "version": VERSION,
+        "type": "plan",
+        "root": path(&plan.root),
+        "create_dirs": plan.create_dirs().iter().map(|dir| path(dir)).collect::<Vec<_>>(),
+        "actions": plan.actions.iter().map(action).collect::<Vec<_>>(),
+        "duplicates": plan
+            .duplicates
+            .iter()
+            .map(|duplicate| json!({ "path": path(&duplicate.path), "original": path(&duplicate.original) }))
+            .collect::<Vec<_>>(),
+        "failures": failures(&plan.failures),
+    })
+}
+
+/// What a run did: its counts, every event in order and the failures.
+pub fn result(root: &Path, run_id: Option<&str>, summary: &Summary, events: Vec<Value>) -> Value {
+    json!({
+        "version": VERSION,
+        "type": "result",
+        "root": path(root),
+        "run_id": run_id,
+        "summary": counts(summary),
+        "events": events,
+        "failures": failures(&summary.failures),
+    })
+}
+
+pub fn counts(summary: &Summary) -> Value {
+    json!({
+        "moved": summary.moved,
+        "copied": summary.copied,
+        "linked": summary.linked,
+        "trashed": summary.trashed,
+        "unlinked": summary.unlinked,
+        "skipped": summary.skipped,
+        "failed": summary.failures.len(),
+    })
+}
+
+pub fn action(action: &Action) -> Value {
+    match action {
+        Action::Move { source, destination, category, transfer } => json!({
+            "action": "move",
+            "source": path(source),
+            "destination": path(destination),
+            "category": category,
+            "transfer": transfer.name(),
+        }),
+        Action::Bundle { members, category, transfer } => json!({
+            "action": "bundle",
+            "members": members
+                .iter()
+                .map(|(source, destination)| json!({ "source": path(source), "destination": path(destination) }))
+                .collect::<Vec<_>>(),
+            "category": category,
+            "transfer": transfer.name(),
+        }),
+        Action::Trash { path: file, reason } => {
+            let mut value = json!({ "action": "trash", "path": path(file), "message": reason.to_string() });
+            match reason {
+                TrashReason::Duplicate(original) => {
+                    value["reason"] = json!("duplicate");
+                    value["original"] = json!(path(original));
+                }
+                TrashReason::Rule(rule) => {
+                    value["reason"] = json!("rule");
+                    value["rule"] = json!(rule);
+                }
+            }
+            value
+        }
+        Action::Link { path: file, original } => json!({ "action": "link", "path": path(file), "original": path(original) }),
+        Action::Skip { path: file, reason } => json!({
+            "action": "skip",
+            "path": path(file),
+            "reason": skip_code(reason),
+            "message": reason.to_string(),
+        }),
+    }
+}
+
+/// One line of `--events ndjson` for an event of the executor.
+pub fn event(event: &Event<'_>) -> Value {
+    match event {
+        Event::CreatedDir(dir) => json!({ "event": "created_dir", "path": path(dir) }),
+        Event::RemovedDir(dir) => json!({ "event": "removed_dir", "path": path(dir) }),
+        Event::Moved { source, destination } => {
+            json!({ "event": "moved", "source": path(source), "destination": path(destination) })
+        }
+        Event::Copied { source, destination, transfer } => json!({
+            "event": "copied",
+            "source": path(source),
+            "destination": path(destination),
+            "transfer": transfer.name(),
+        }),
+        Event::Trashed { path: file, trashed } => json!({ "event": "trashed", "path": path(file), "trashed": path(trashed) }),
+        Event::Linked { path: file, original } => json!({ "event": "linked", "path": path(file), "original": path(original) }),
+        Event::Unlinked(link) => json!({ "event": "unlinked", "path": path(link) }),
+        Event::Kept { path: file, reason } => json!({ "event": "kept", "path": path(file), "message": reason }),
+        Event::Skipped { path: file, reason } => json!({
+            "event": "skipped",
+            "path": path(file),
+            "reason": skip_code(reason),
+            "message": reason.to_string(),
+        }),
+        Event::Failed { path: file, error } => json!({ "event": "failed", "path": path(file), "error": error.to_string() }),
+    }
+}
+
+fn failures(failures: &[Failure]) -> Vec<Value> {
+    failures.iter().map(|failure| json!({ "path": path(&failure.path), "error": failure.error.to_string() })).collect()
+}
+
+/// A stable name for each reason; the message next to it is for people.
+fn skip_code(reason: &SkipReason) -> &'static str {
+    match reason {
+        SkipReason::NotAFile => "not_a_file",
+        SkipReason::Symlink => "symlink",
+        SkipReason::SymlinkLoop => "symlink_loop",
+        SkipReason::CategoryDir => "category_dir",
+        SkipReason::MaxDepth => "max_depth",
+        SkipReason::Excluded(_) => "excluded",
+        SkipReason::AlreadyInPlace => "already_in_place",
+        SkipReason::AlreadyPlaced(_) => "already_placed",
+        SkipReason::NoExtension => "no_extension",
+        SkipReason::ContentMismatch { .. } => "content_mismatch",
+        SkipReason::NotSelected(_) => "not_selected",
+        SkipReason::Ignored(_) => "ignored",
+        SkipReason::Unmapped(_) => "unmapped",
+        SkipReason::Rule(_) => "rule",
+        SkipReason::Companion(_) => "companion",
+        SkipReason::Duplicate(_) => "duplicate",
+        SkipReason::Destination(_) => "destination",
+        SkipReason::Declined => "declined",
+    }
+}
+
+/// Paths that are not UTF-8 are written lossily.
+fn path(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use tidyup::fsops::Transfer;
+
+    #[test]
+    fn writes_plans_with_a_version_and_codes() {
+        let root = PathBuf::from("/nonexistent/downloads");
+        let actions = vec![
+            Action::Move {
+                source: root.join("a.png"),
+                destination: root.join("images/a.png"),
+                category: "images".to_string(),
+                transfer: Transfer::Symlink { absolute: true },
+            },
+            Action::Skip { path: root.join("notes.txt"), reason: SkipReason::Unmapped("txt".to_string()) },
+        ];
+        let value = plan(&Plan { root: root.clone(), actions, ..Plan::default() });
+        assert_eq!(value["version"], VERSION);
+        assert_eq!(value["type"], "plan");
+        assert_eq!(value["root"], "/nonexistent/downloads");
+        assert_eq!(value["create_dirs"], json!(["/nonexistent/downloads/images"]));
+        assert_eq!(
+            value["actions"][0],
+            json!({
+                "action": "move",
+                "source": "/nonexistent/downloads/a.png",
+                "destination": "/nonexistent/downloads/images/a.png",
+                "category": "images",
+                "transfer": "absolute_symlink",
+            })
+        );
+        assert_eq!(value["actions"][1]["reason"], "unmapped");
+        assert_eq!(value["actions"][1]["message"], SkipReason::Unmapped("txt".to_string()).to_string());
+        assert_eq!(value["duplicates"], json!([]));
+        assert_eq!(value["failures"], json!([]));
+    }
+
+    #[test]
+    fn writes_one_object_per_event() {
+        let (source, destination) = (Path::new("/d/a.png"), Path::new("/d/images/a.png"));
+        assert_eq!(
+            event(&Event::Moved { source, destination }),
+            json!({ "event": "moved", "source": "/d/a.png", "destination": "/d/images/a.png" })
+        );
+        assert_eq!(
+            event(&Event::Skipped { path: source, reason: &SkipReason::NotAFile }),
+            json!({ "event": "skipped", "path": "/d/a.png", "reason": "not_a_file", "message": SkipReason::NotAFile.to_string() })
+        );
+        let error = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        assert_eq!(
+            event(&Event::Failed { path: source, error: &error }),
+            json!({ "event": "failed", "path": "/d/a.png", "error": "denied" })
+        );
+        let name = OsStr::from_bytes(b"/d/\xff.png");
+        assert_eq!(event(&Event::Unlinked(Path::new(name))), json!({ "event": "unlinked", "path": "/d/\u{fffd}.png" }));
+    }
+}
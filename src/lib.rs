@@ -0,0 +1,36 @@
+//! Sorts the files of a directory into category folders.
+//!
+//! A [`Planner`] scans a directory and classifies every entry into a [`Plan`]
+//! of [`Action`]s without touching the filesystem; an [`Executor`] applies it.
+
+pub mod bundle;
+pub mod classify;
+pub mod config;
+pub mod conflict;
+pub mod dedup;
+pub mod error;
+pub mod execute;
+pub mod explain;
+pub mod exif;
+pub mod fsops;
+pub mod group;
+pub mod journal;
+pub mod plan;
+pub mod planfile;
+pub mod report;
+pub mod rules;
+mod scan;
+pub mod sniff;
+pub mod stats;
+pub mod template;
+#[cfg(test)]
+mod testdir;
+pub mod trash;
+pub mod view;
+pub mod watch;
+mod xdg;
+
+pub use config::Config;
+pub use error::Error;
+pub use execute::Executor;
+pub use plan::{Action, Plan, PlanOptions, Planner};
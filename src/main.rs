@@ -1,139 +1,337 @@
-use std::fs;
-use std::env;
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
-
-fn print_usage() {
-    println!(r#"
-        Usage: tidyup -d directory
-        This program groups and displays desktop items based on their type.
+mod cli;
+mod exit;
+mod interactive;
+mod json;
+mod output;
+mod tui;
 
-        Options:
-          -h, --help                    Display this help message and exit.
-          -d, --directory   DIRECTORY   Specify the input directory containing items.
-          -e, --extensions  extensions  Specify extensions to consider
-          -i, --ignore      extensions  List of extensions to ignore
-          -v, --verbose                 Enable verbose mode to show additional details during processing.
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::SystemTime;
 
-        Description:
-          The program groups a list of directory items from an input folder, groups them by their type (e.g., images, extensions, shortcuts), and displays or saves the grouped items based on the specified options.
+use clap::CommandFactory;
 
-          Each item in the input file should be in the format:
-            NAME.EXTENSION
-          where NAME is the name of the item and TYPE is one of the following:
-            PNG, JPG, JPEG, CPP, PY
+use tidyup::config;
+use tidyup::conflict::OnConflict;
+use tidyup::dedup::OnDuplicate;
+use tidyup::execute;
+use tidyup::fsops::{Mover, Transfer};
+use tidyup::journal;
+use tidyup::report::Summary;
+use tidyup::planfile;
+use tidyup::stats::Stats;
+use tidyup::view;
+use tidyup::watch::{WatchOptions, Watcher};
+use tidyup::{Config, Error, Executor, Plan, PlanOptions, Planner};
 
-        Examples:
-          tidyup -d DESKTOP
-            Reads items from 'DESKTOP', groups them, and writes the results to appropriate folders.
+use cli::{ApplyArgs, Cli, Command, ConfigCommand, GroupArgs, RunArgs, ScanArgs, ViewArgs, WatchArgs};
+use exit::Exit;
+use output::{Format, Sink, Verbosity};
 
-          tidyup --verbose
-            Runs the program in verbose mode, providing additional processing details.
+fn load_config(cli_config: Option<&Path>, directory: &Path, verbosity: Verbosity) -> Result<Config, Error> {
+    let config = Config::load(cli_config, directory)?;
+    if verbosity >= Verbosity::Debug {
+        match &config.source {
+            Some(source) => println!("Config {}", source.display()),
+            None => println!("Config <built-in>"),
+        }
+        output::print_categories(&config);
+    }
+    Ok(config)
+}
 
-        For more information, please refer to the documentation or visit the project website.)
-    "#);
+fn build_plan(planner: &Planner) -> Result<Plan, Error> {
+    let path = planner.root();
+    planner
+        .plan()
+        .map_err(|e| Error::Io(io::Error::new(e.kind(), format!("cannot scan {}: {}", path.display(), e))))
 }
 
-fn tidyup() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let mut relevant_extensions: Vec<String> = Vec::new();
-    let mut ignore_extensions: Vec<String> = Vec::new();
-    let mut dir_name = ".".to_string();
-
-    let mut verbose = true;
-    let mut i = 1;
-    while i < args.len() {
-        match args[i].as_str() {
-            "-d" | "--directory" => {
-                if i + 1 < args.len() {
-                    dir_name = args[i + 1].clone();
-                    i += 1;
-                } else {
-                    eprintln!("Error: Missing directory after -d or --directory");
-                    print_usage();
-                    return Ok(()); // or Err(...)
-                }
+fn run(args: &RunArgs, cli_config: Option<&Path>, verbosity: Verbosity) -> Result<Exit, Error> {
+    let path = args.scan.select.directory.as_path();
+    // Machine-readable output leaves no room for text on stdout.
+    let verbosity = if args.output.is_text() { verbosity } else { Verbosity::Quiet };
+    if verbosity >= Verbosity::Verbose {
+        println!("Cleaning {}", path.display());
+    }
+    let config = load_config(cli_config, path, verbosity)?;
+    let options = PlanOptions { files: args.input.files()?, ..args.scan.plan_options() };
+    let planner = Planner::new(&config, path, options)?;
+    let mut plan = build_plan(&planner)?;
+    if args.interactive || args.review {
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+            return Err(Error::Io(io::Error::other("--interactive and --review need a terminal")));
+        }
+        if args.interactive {
+            interactive::confirm(&mut plan, &mut io::stdin().lock())?;
+        } else {
+            match tui::review(plan)? {
+                Some(reviewed) => plan = reviewed,
+                None => return Ok(Exit::Success),
             }
-            "-e" | "--extensions" => {
-                i += 1;
-                while i < args.len() && !args[i].starts_with('-') {
-                    relevant_extensions.push(args[i].clone());
-                    i += 1;
+        }
+    }
+    if let Some(plan_file) = &args.plan_file {
+        let count = planfile::save(&plan, plan_file)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot write {}: {}", plan_file.display(), e)))?;
+        if verbosity >= Verbosity::Normal {
+            println!("Wrote {} actions to {}", count, plan_file.display());
+            println!("Carry them out with `tidyup apply {}`", plan_file.display());
+        }
+        for failure in &plan.failures {
+            eprintln!("tidyup: {}: {}", failure.path.display(), failure.error);
+        }
+        return Ok(Exit::from_failures(&plan.failures));
+    }
+    let mut sink = args.output.sink(verbosity);
+    sink.started(if args.dry_run { "plan" } else { "run" }, path);
+    sink.planned(&plan);
+
+    if args.dry_run {
+        let empty = if args.prune_empty { planner.prunable(&plan) } else { Vec::new() };
+        match sink {
+            Sink::Text(_) => {
+                output::print_plan(&plan, args.moves.on_conflict);
+                if args.prune_empty {
+                    println!("Empty folders to remove ({}):", empty.len());
+                    for dir in &empty {
+                        println!("  {}", dir.display());
+                    }
                 }
-                i -= 1; // Adjust for the upcoming increment
             }
-            "-i" | "--ignore" => {
-                i += 1;
-                while i < args.len() && !args[i].starts_with('-') {
-                    ignore_extensions.push(args[i].clone());
-                    i += 1;
+            Sink::Json(_) => {
+                let mut document = json::plan(&plan);
+                if args.prune_empty {
+                    document["remove_dirs"] = empty.iter().map(|dir| dir.to_string_lossy()).collect();
                 }
-                i -= 1; // Adjust for the upcoming increment
-            }
-            "-v" | "--verbose" => {
-                verbose = true;
-            }
-            "-h" | "--help" => {
-                print_usage();
-                return Ok(());
-            }
-            _ => {
-                eprintln!("Error: Unknown argument {}", args[i]);
-                print_usage();
-                return Ok(()); // or Err(...)
+                println!("{:#}", document);
             }
+            Sink::Ndjson => {}
         }
-        i += 1;
+        return Ok(Exit::from_failures(&plan.failures));
     }
 
-    println!("Cleaning {}", dir_name);
-    println!("Verbose {}", verbose);
-    let path = Path::new(&dir_name);
+    if args.scan.dedup == Some(OnDuplicate::Report) && !plan.duplicates.is_empty() && verbosity >= Verbosity::Normal {
+        println!("Duplicates ({}):", plan.duplicates.len());
+        output::print_duplicates(&plan);
+    }
+    let (mut summary, run_id) = executor(&args.moves).run(plan, &mut sink)?;
+    if args.prune_empty {
+        summary.failures.extend(execute::prune(&planner.empty_dirs(), &mut sink));
+    }
 
-    let mut extension_mapping: HashMap<String, PathBuf> = HashMap::new();
-    extension_mapping.insert("png".to_string(), path.join("images"));
-    extension_mapping.insert("jpg".to_string(), path.join("images"));
-    extension_mapping.insert("jpeg".to_string(), path.join("images"));
-    extension_mapping.insert("py".to_string(), path.join("python"));
-    extension_mapping.insert("cpp".to_string(), path.join("c++"));
+    sink.finish(path, run_id.as_deref(), &summary);
+    print_undo_hint(run_id, &summary, verbosity);
+    Ok(Exit::from_failures(&summary.failures))
+}
 
-    for ext_dir in extension_mapping.values() {
-        if !ext_dir.exists() {
-            fs::create_dir(ext_dir)?;
+/// Tells how to revert a run that changed something.
+fn print_undo_hint(run_id: Option<String>, summary: &Summary, verbosity: Verbosity) {
+    if let (Some(run_id), true) = (run_id, verbosity >= Verbosity::Normal && summary.moved + summary.copied + summary.linked + summary.trashed > 0) {
+        println!("Revert with `tidyup undo {}`", run_id);
+    }
+}
+
+fn apply(args: &ApplyArgs, verbosity: Verbosity) -> Result<Exit, Error> {
+    let verbosity = if args.output.is_text() { verbosity } else { Verbosity::Quiet };
+    let plan = planfile::load(&args.plan)?;
+    let root = plan.root.clone();
+    let mut sink = args.output.sink(verbosity);
+    sink.started("apply", &root);
+    sink.planned(&plan);
+
+    if args.dry_run {
+        match sink {
+            Sink::Text(_) => output::print_plan(&plan, args.moves.on_conflict),
+            Sink::Json(_) => println!("{:#}", json::plan(&plan)),
+            Sink::Ndjson => {}
         }
+        return Ok(Exit::from_failures(&plan.failures));
     }
 
-    let entries = fs::read_dir(path)?;
-    for entry in entries {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
+    let (summary, run_id) = executor(&args.moves).run(plan, &mut sink)?;
+    sink.finish(&root, run_id.as_deref(), &summary);
+    print_undo_hint(run_id, &summary, verbosity);
+    Ok(Exit::from_failures(&summary.failures))
+}
 
-        if metadata.is_file() {
-            let file_name = entry.file_name();
-            println!("{}", file_name.to_string_lossy());
+fn executor(args: &cli::MoveArgs) -> Executor {
+    Executor {
+        mover: Mover { verify_checksum: args.verify_checksum },
+        on_conflict: args.on_conflict,
+    }
+}
 
-            let file_path = entry.path();
-            let extension = file_path.extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or_default()
-                .to_ascii_lowercase();
+fn watch(args: &WatchArgs, cli_config: Option<&Path>, verbosity: Verbosity) -> Result<Exit, Error> {
+    let path = args.scan.select.directory.as_path();
+    let config = load_config(cli_config, path, verbosity)?;
+    let planner = Planner::new(&config, path, args.scan.plan_options())?;
+    let verbosity = if args.events.is_none() { verbosity } else { Verbosity::Quiet };
+    if verbosity >= Verbosity::Normal {
+        println!("Watching {}", path.display());
+    }
 
-            if !extension.is_empty() && ((relevant_extensions.contains(&extension) || relevant_extensions.is_empty()) && (ignore_extensions.is_empty() || !ignore_extensions.contains(&extension))) {
-                if let Some(target_dir) = extension_mapping.get(&extension) {
-                    let new_path = target_dir.join(file_name);
-                    fs::rename(&file_path, &new_path)?;
+    let watcher = Watcher {
+        options: WatchOptions {
+            settle: args.settle,
+            debounce: args.debounce,
+            poll: args.poll,
+            poll_interval: args.poll_interval,
+        },
+        planner: &planner,
+        executor: executor(&args.moves),
+    };
+    let mut sink = Sink::new(Format::Text, args.events, verbosity);
+    sink.started("watch", path);
+    watcher.run(&mut sink)?;
+    Ok(Exit::Success)
+}
+
+fn view(args: &ViewArgs, cli_config: Option<&Path>, verbosity: Verbosity) -> Result<Exit, Error> {
+    let path = args.scan.select.directory.as_path();
+    let verbosity = if args.output.is_text() { verbosity } else { Verbosity::Quiet };
+    let config = load_config(cli_config, path, verbosity)?;
+    let options = PlanOptions {
+        target: Some(args.into.clone()),
+        folder: args.folder.clone(),
+        transfer: Some(Transfer::Symlink { absolute: args.absolute }),
+        ..args.scan.plan_options()
+    };
+    let planner = Planner::new(&config, path, options)?;
+    let plan = build_plan(&planner)?;
+    let stale = view::stale(&plan, &args.into)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", args.into.display(), e)))?;
+    let mut sink = args.output.sink(verbosity);
+    sink.started("view", path);
+    sink.planned(&plan);
+
+    if args.dry_run {
+        match sink {
+            Sink::Text(_) => {
+                output::print_plan(&plan, OnConflict::Rename);
+                println!("Stale links to remove ({}):", stale.len());
+                for link in &stale {
+                    println!("  {}", link.display());
                 }
             }
+            Sink::Json(_) => {
+                let mut document = json::plan(&plan);
+                document["stale_links"] = stale.iter().map(|link| link.to_string_lossy()).collect();
+                println!("{:#}", document);
+            }
+            Sink::Ndjson => {}
         }
+        return Ok(Exit::from_failures(&plan.failures));
+    }
+
+    let (summary, run_id) = view::update(plan, &args.into, &stale, &mut sink)?;
+    sink.finish(path, run_id.as_deref(), &summary);
+    if let (Some(run_id), true) = (run_id, verbosity >= Verbosity::Normal && summary.linked > 0) {
+        println!("Remove the new links with `tidyup undo {}`", run_id);
     }
+    Ok(Exit::from_failures(&summary.failures))
+}
 
-    Ok(())
+fn undo(run_id: Option<&str>, verbosity: Verbosity) -> Result<Exit, Error> {
+    let report = journal::undo(run_id)?;
+    output::print_undo(&report, verbosity);
+    Ok(if report.failed.is_empty() { Exit::Success } else { Exit::Partial })
 }
 
-fn main() {
-    let status = tidyup();
-    match status {
-        Ok(_) => println!("Tidyup finished."),
-        Err(e) => eprintln!("Failed to tidy directory: {}.", e)
+fn stats(args: &ScanArgs, cli_config: Option<&Path>, verbosity: Verbosity) -> Result<Exit, Error> {
+    let config = load_config(cli_config, &args.select.directory, verbosity)?;
+    let planner = Planner::new(&config, &args.select.directory, args.plan_options())?;
+    let plan = build_plan(&planner)?;
+    output::print_stats(&Stats::of(&plan));
+    for failure in &plan.failures {
+        eprintln!("tidyup: {}: {}", failure.path.display(), failure.error);
+    }
+    Ok(Exit::from_failures(&plan.failures))
+}
+
+fn group(args: &GroupArgs, cli_config: Option<&Path>, verbosity: Verbosity) -> Result<Exit, Error> {
+    let path = args.scan.select.directory.as_path();
+    let config = load_config(cli_config, path, verbosity)?;
+    let planner = Planner::new(&config, path, PlanOptions { files: args.input.files()?, ..args.scan.plan_options() })?;
+    let groups = planner.group(build_plan(&planner)?);
+    output::print_groups(&groups, path);
+    for failure in &groups.failures {
+        eprintln!("tidyup: {}: {}", failure.path.display(), failure.error);
+    }
+    Ok(Exit::from_failures(&groups.failures))
+}
+
+fn explain(
+    paths: &[PathBuf],
+    scan: &ScanArgs,
+    on_conflict: OnConflict,
+    cli_config: Option<&Path>,
+    verbosity: Verbosity,
+) -> Result<Exit, Error> {
+    let config = load_config(cli_config, &scan.select.directory, verbosity)?;
+    let planner = Planner::new(&config, &scan.select.directory, scan.plan_options())?;
+    let now = SystemTime::now();
+
+    let mut exit = Exit::Success;
+    for path in paths {
+        match planner.explain(path, now, on_conflict) {
+            Ok(decision) => output::print_decision(&decision, on_conflict),
+            Err(e) => {
+                println!("{}", path.display());
+                println!("  cannot read: {}", e);
+                exit = Exit::Partial;
+            }
+        }
+    }
+    Ok(exit)
+}
+
+fn config_command(command: &ConfigCommand, cli_config: Option<&Path>) -> Result<Exit, Error> {
+    match command {
+        ConfigCommand::Check { directory } => output::print_config(&Config::load(cli_config, directory)?),
+        ConfigCommand::Default => print!("{}", config::DEFAULT_CONFIG.trim_start()),
+    }
+    Ok(Exit::Success)
+}
+
+fn tidyup(cli: Cli) -> Result<Exit, Error> {
+    let verbosity = cli.global.verbosity();
+    let cli_config = cli.global.config.as_deref();
+
+    match &cli.command {
+        None => run(&cli.run, cli_config, verbosity),
+        Some(Command::Run(args)) => run(args, cli_config, verbosity),
+        Some(Command::Plan(args)) => {
+            let args = RunArgs { dry_run: true, ..args.clone() };
+            run(&args, cli_config, verbosity)
+        }
+        Some(Command::Apply(args)) => apply(args, verbosity),
+        Some(Command::Watch(args)) => watch(args, cli_config, verbosity),
+        Some(Command::View(args)) => view(args, cli_config, verbosity),
+        Some(Command::Undo { run_id }) => undo(run_id.as_deref(), verbosity),
+        Some(Command::Stats(args)) => stats(args, cli_config, verbosity),
+        Some(Command::Group(args)) => group(args, cli_config, verbosity),
+        Some(Command::Explain { paths, scan, on_conflict }) => explain(paths, scan, *on_conflict, cli_config, verbosity),
+        Some(Command::Config { command }) => config_command(command, cli_config),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Cli::command(), "tidyup", &mut io::stdout());
+            Ok(Exit::Success)
+        }
+        Some(Command::Man) => {
+            clap_mangen::Man::new(Cli::command()).render(&mut io::stdout())?;
+            Ok(Exit::Success)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse_args();
+    match tidyup(cli) {
+        Ok(exit) => exit.into(),
+        Err(e) => {
+            eprintln!("tidyup: {}", e);
+            Exit::from_error(&e).into()
+        }
     }
 }
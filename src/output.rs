@@ -0,0 +1,486 @@
+use std::mem;
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use tidyup::conflict::{OnConflict, Outcome};
+use tidyup::explain::{Decision, Reach};
+use tidyup::fsops::Transfer;
+use tidyup::plan::SkipReason;
+use tidyup::group::Groups;
+use tidyup::journal::UndoReport;
+use tidyup::report::{Event, Failure, Reporter, Summary};
+use tidyup::stats::Stats;
+use tidyup::watch::Observer;
+use tidyup::rules::{self, MatchMode, RuleAction, Trace};
+use tidyup::{Action, Config, Plan};
+
+use crate::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
+
+/// Prints execution events to stdout as far as `verbosity` asks for them.
+/// Failures are left to [`print_summary`].
+pub struct Printer {
+    pub verbosity: Verbosity,
+}
+
+impl Reporter for Printer {
+    fn report(&mut self, event: Event<'_>) {
+        match event {
+            Event::Moved { source, destination } if self.verbosity >= Verbosity::Verbose => {
+                println!("{} -> {}", source.display(), destination.display());
+            }
+            Event::Copied { source, destination, transfer } if self.verbosity >= Verbosity::Verbose => {
+                println!("{} -> {} ({})", source.display(), destination.display(), transfer);
+            }
+            Event::Trashed { path, .. } if self.verbosity >= Verbosity::Verbose => {
+                println!("{} -> trash", path.display());
+            }
+            Event::Linked { path, original } if self.verbosity >= Verbosity::Verbose => {
+                println!("{} => hard link to {}", path.display(), original.display());
+            }
+            Event::Unlinked(link) if self.verbosity >= Verbosity::Verbose => {
+                println!("removed stale link {}", link.display());
+            }
+            Event::Kept { path, reason } if self.verbosity >= Verbosity::Verbose => {
+                println!("{} skipped: {}", path.display(), reason);
+            }
+            Event::RemovedDir(dir) if self.verbosity >= Verbosity::Verbose => {
+                println!("removed empty folder {}", dir.display());
+            }
+            Event::Skipped { path, reason } if self.verbosity >= Verbosity::Debug => {
+                println!("{} skipped: {}", path.display(), reason);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How plans and results are written (`--format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    /// One JSON document, see [`json`].
+    Json,
+}
+
+/// How events are streamed while they happen (`--events`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EventFormat {
+    /// One JSON object per line on stdout.
+    Ndjson,
+}
+
+/// Where the events of a run go, as `--format` and `--events` ask.
+pub enum Sink {
+    Text(Printer),
+    /// Collected for the result document.
+    Json(Vec<Value>),
+    Ndjson,
+}
+
+impl Sink {
+    pub fn new(format: Format, events: Option<EventFormat>, verbosity: Verbosity) -> Sink {
+        match (events, format) {
+            (Some(EventFormat::Ndjson), _) => Sink::Ndjson,
+            (None, Format::Json) => Sink::Json(Vec::new()),
+            (None, Format::Text) => Sink::Text(Printer { verbosity }),
+        }
+    }
+
+    /// Announces that `command` starts on `root`.
+    pub fn started(&mut self, command: &str, root: &Path) {
+        if let Sink::Ndjson = self {
+            emit(json!({ "event": "started", "version": json::VERSION, "command": command, "root": root.to_string_lossy() }));
+        }
+    }
+
+    /// Streams what the scan found and how each entry was classified.
+    pub fn planned(&mut self, plan: &Plan) {
+        let Sink::Ndjson = self else {
+            return;
+        };
+        emit(json!({ "event": "scanned", "root": plan.root.to_string_lossy(), "dirs": plan.scanned_dirs.len() }));
+        for action in &plan.actions {
+            let mut classified = json::action(action);
+            classified["event"] = json!("classified");
+            emit(classified);
+        }
+        for failure in &plan.failures {
+            emit(json::event(&Event::Failed { path: &failure.path, error: &failure.error }));
+        }
+    }
+
+    /// Writes the outcome: the summary line, the result document or the summary event.
+    pub fn finish(&mut self, root: &Path, run_id: Option<&str>, summary: &Summary) {
+        match self {
+            Sink::Text(printer) => print_summary(summary, printer.verbosity),
+            Sink::Json(events) => println!("{:#}", json::result(root, run_id, summary, mem::take(events))),
+            Sink::Ndjson => emit(json!({ "event": "summary", "run_id": run_id, "summary": json::counts(summary) })),
+        }
+    }
+}
+
+impl Reporter for Sink {
+    fn report(&mut self, event: Event<'_>) {
+        match self {
+            Sink::Text(printer) => printer.report(event),
+            Sink::Json(events) => events.push(json::event(&event)),
+            Sink::Ndjson => emit(json::event(&event)),
+        }
+    }
+}
+
+impl Observer for Sink {
+    fn planned(&mut self, plan: &Plan) {
+        Sink::planned(self, plan);
+    }
+
+    fn finished(&mut self, root: &Path, run_id: Option<&str>, summary: &Summary) {
+        self.finish(root, run_id, summary);
+    }
+
+    fn failed(&mut self, failures: &[Failure]) {
+        for failure in failures {
+            match self {
+                Sink::Text(_) => eprintln!("tidyup: {}: {}", failure.path.display(), failure.error),
+                _ => self.report(Event::Failed { path: &failure.path, error: &failure.error }),
+            }
+        }
+    }
+
+    fn warn(&mut self, message: &str) {
+        eprintln!("tidyup: {}", message);
+    }
+}
+
+fn emit(event: Value) {
+    println!("{}", event);
+}
+
+pub fn print_plan(plan: &Plan, on_conflict: OnConflict) {
+    let create_dirs = plan.create_dirs();
+    println!("Directories to create ({}):", create_dirs.len());
+    for dir in create_dirs {
+        println!("  {}", dir.display());
+    }
+    let moves: Vec<_> = plan.moves().collect();
+    println!("Moves ({}):", moves.len());
+    for (source, destination, category, transfer) in moves {
+        let label = match transfer {
+            Transfer::Move => category.to_string(),
+            transfer => format!("{}, {}", category, transfer),
+        };
+        if destination.symlink_metadata().is_ok() {
+            println!("  {} -> {} [{}] (exists, on conflict: {})", source.display(), destination.display(), label, on_conflict);
+        } else {
+            println!("  {} -> {} [{}]", source.display(), destination.display(), label);
+        }
+    }
+    let trashes: Vec<_> = plan.trashes().collect();
+    if !trashes.is_empty() {
+        println!("To trash ({}):", trashes.len());
+        for (path, reason) in trashes {
+            println!("  {} ({})", path.display(), reason);
+        }
+    }
+    let links: Vec<_> = plan.links().collect();
+    if !links.is_empty() {
+        println!("To replace with hard links ({}):", links.len());
+        for (path, original) in links {
+            println!("  {} => {}", path.display(), original.display());
+        }
+    }
+    if !plan.duplicates.is_empty() {
+        println!("Duplicates ({}):", plan.duplicates.len());
+        print_duplicates(plan);
+    }
+    let skips: Vec<_> = plan.skips().collect();
+    println!("Skipped ({}):", skips.len());
+    for (path, reason) in skips {
+        println!("  {} ({})", path.display(), reason);
+    }
+    if !plan.failures.is_empty() {
+        println!("Errors ({}):", plan.failures.len());
+        for failure in &plan.failures {
+            println!("  {} ({})", failure.path.display(), failure.error);
+        }
+    }
+}
+
+pub fn print_duplicates(plan: &Plan) {
+    for duplicate in &plan.duplicates {
+        println!("  {} = {}", duplicate.path.display(), duplicate.original.display());
+    }
+}
+
+/// Prints the failures to stderr, followed by a one-line total.
+pub fn print_summary(summary: &Summary, verbosity: Verbosity) {
+    for failure in &summary.failures {
+        eprintln!("tidyup: {}: {}", failure.path.display(), failure.error);
+    }
+    if !summary.failures.is_empty() {
+        eprintln!(
+            "tidyup: {} file{} could not be processed",
+            summary.failures.len(),
+            if summary.failures.len() == 1 { "" } else { "s" }
+        );
+    }
+    if verbosity >= Verbosity::Normal {
+        let mut line = format!("Moved {} files", summary.moved);
+        if summary.copied > 0 {
+            line.push_str(&format!(", copied {}", summary.copied));
+        }
+        if summary.trashed > 0 {
+            line.push_str(&format!(", trashed {}", summary.trashed));
+        }
+        if summary.linked > 0 {
+            line.push_str(&format!(", linked {}", summary.linked));
+        }
+        if summary.unlinked > 0 {
+            line.push_str(&format!(", removed {} stale links", summary.unlinked));
+        }
+        println!("{}, skipped {}, failed {}", line, summary.skipped, summary.failures.len());
+    }
+}
+
+/// The steps of a [`Decision`], one per line.
+pub fn print_decision(decision: &Decision, on_conflict: OnConflict) {
+    println!("{}", decision.path.display());
+    if let [Action::Skip { reason: reason @ (SkipReason::Symlink | SkipReason::NotAFile), .. }] = decision.actions.as_slice() {
+        println!("  stays: {}", reason);
+        return;
+    }
+    println!("  file: {}, modified {} ago", rules::format_size(decision.size), rules::format_age(decision.age));
+    match &decision.reach {
+        Reach::Reached => println!("  scan: reached"),
+        Reach::Outside => println!("  scan: outside the directory, only reached when listed"),
+        Reach::PassedOver(SkipReason::Excluded(pattern)) => println!("  scan: not reached, excluded by `{}`", pattern),
+        Reach::PassedOver(SkipReason::CategoryDir) => println!("  scan: not reached, inside a category folder (see --include-tidied)"),
+        Reach::PassedOver(SkipReason::MaxDepth) => println!("  scan: not reached, deeper than --max-depth"),
+        Reach::PassedOver(reason) => println!("  scan: not reached, {}", reason),
+    }
+    match &decision.companion_of {
+        Some(primary) => println!("  companion of: {} (goes wherever it goes)", primary.display()),
+        None if !decision.companions.is_empty() => {
+            let companions: Vec<_> = decision.companions.iter().map(|companion| companion.display().to_string()).collect();
+            println!("  companions: {}", companions.join(", "));
+        }
+        None => {}
+    }
+
+    let extension = if decision.extension.is_empty() { "none" } else { &decision.extension };
+    match &decision.content {
+        Ok(Some(content)) => println!("  type: extension {}, content {}", extension, content),
+        Ok(None) => println!("  type: extension {}, content not recognised", extension),
+        Err(e) => println!("  type: extension {}, content cannot be read ({})", extension, e),
+    }
+
+    if !decision.rules.is_empty() {
+        match decision.match_mode {
+            MatchMode::First => println!("  rules (the first matching rule decides):"),
+            MatchMode::All => println!("  rules (every matching rule applies):"),
+        }
+        for step in &decision.rules {
+            let rule = step.rule;
+            let action = match &rule.destination {
+                Some(destination) if rule.action.places() => format!("{} to {}", rule.transfer, destination),
+                _ if rule.action == RuleAction::Trash => "trash".to_string(),
+                _ => "skip".to_string(),
+            };
+            let mark = if step.decides { "[x]" } else { "[ ]" };
+            let note = if step.trace.matched && !step.decides { ", matches but an earlier rule decided" } else { "" };
+            println!("    {} {} (priority {}): {}{}", mark, rule.name, rule.priority, action, note);
+            let conditions = if step.trace.label == "all" { &step.trace.children[..] } else { std::slice::from_ref(&step.trace) };
+            for condition in conditions {
+                print_trace(condition, 8);
+            }
+        }
+        let deciding: Vec<&str> = decision.deciding().map(|rule| rule.name.as_str()).collect();
+        if deciding.is_empty() {
+            println!("  decided by: category, no rule matched");
+        } else {
+            println!("  decided by: rule {}", deciding.join(", rule "));
+        }
+    }
+
+    match &decision.category {
+        Some(Ok(step)) => {
+            let detect = format!("{:?}", step.category.detect).to_lowercase();
+            let by = if step.by_content { "content" } else { "extension" };
+            println!("  category: {} (detect = {}), by {} {}", step.category.name, detect, by, step.kind);
+        }
+        Some(Err(reason)) => println!("  category: none ({})", reason),
+        None => {}
+    }
+    match &decision.filter {
+        Some(Some(reason)) => println!("  filters: {}", reason),
+        Some(None) => println!("  filters: passed"),
+        None => {}
+    }
+    if let Some(Ok(step)) = &decision.category {
+        println!("  folder: {}", step.folder);
+    }
+
+    if let Some(original) = &decision.duplicate_of {
+        println!("  duplicate: same content as {}", original.display());
+    }
+
+    for placement in &decision.placements {
+        println!("  destination: {}", placement.destination.display());
+        if placement.taken {
+            println!("  conflict: {} exists, --on-conflict {}", placement.destination.display(), on_conflict);
+        } else {
+            println!("  conflict: none");
+        }
+        match &placement.outcome {
+            Ok(Outcome::Moved(placed)) => println!("  result: {} to {}", placement.transfer, placed.display()),
+            Ok(Outcome::Replaced(placed)) => println!("  result: {} to {}, replacing it", placement.transfer, placed.display()),
+            Ok(Outcome::Skipped(reason)) => println!("  result: stays ({})", reason),
+            Err(e) => println!("  result: cannot compare with {}: {}", placement.destination.display(), e),
+        }
+    }
+    let is_self = |path: &Path| path.file_name() == decision.path.file_name();
+    for action in &decision.actions {
+        match action {
+            Action::Trash { reason, .. } => println!("  result: trash ({})", reason),
+            Action::Link { original, .. } => println!("  result: replaced by a hard link to {}", original.display()),
+            Action::Skip { path, reason } if is_self(path) => println!("  result: stays ({})", reason),
+            _ => {}
+        }
+    }
+}
+
+fn print_trace(trace: &Trace, indent: usize) {
+    let mark = if trace.matched { "[x]" } else { "[ ]" };
+    match &trace.actual {
+        Some(actual) => println!("{:indent$}{} {}  (is {})", "", mark, trace.label, actual, indent = indent),
+        None => println!("{:indent$}{} {}", "", mark, trace.label, indent = indent),
+    }
+    for child in &trace.children {
+        print_trace(child, indent + 4);
+    }
+}
+
+/// The categories, rules and bundles of `config`, as `tidyup config check` lists them.
+pub fn print_config(config: &Config) {
+    match &config.source {
+        Some(source) => println!("{}: ok", source.display()),
+        None => println!("<built-in config>: ok"),
+    }
+    print_categories(config);
+    for rule in &config.rules {
+        match &rule.destination {
+            Some(destination) if rule.transfer == Transfer::Move => {
+                println!("  rule {} (priority {}) -> {}", rule.name, rule.priority, destination)
+            }
+            Some(destination) => {
+                println!("  rule {} (priority {}) -> {} ({})", rule.name, rule.priority, destination, rule.transfer)
+            }
+            None if rule.action == RuleAction::Trash => println!("  rule {} (priority {}): trash", rule.name, rule.priority),
+            None => println!("  rule {} (priority {}): skip", rule.name, rule.priority),
+        }
+    }
+    for bundle in &config.bundles {
+        println!("  bundle {} ({})", bundle.name, bundle.extensions.join(", "));
+    }
+}
+
+pub fn print_categories(config: &Config) {
+    for category in &config.categories {
+        println!("  {} -> {} ({})", category.name, category.folder, category.extensions.join(", "));
+    }
+}
+
+pub fn print_stats(stats: &Stats) {
+    for (category, (files, bytes)) in &stats.by_category {
+        println!("{:<16} {:>6} files {:>10}", category, files, human_size(*bytes));
+    }
+    if !stats.by_reason.is_empty() {
+        println!("Left in place:");
+        for (reason, count) in &stats.by_reason {
+            println!("  {:>6}  {}", count, reason);
+        }
+    }
+}
+
+/// The groups, with the files named relative to `root` where they are below it.
+pub fn print_groups(groups: &Groups, root: &Path) {
+    let name = |file: &Path| match file.strip_prefix(root) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.display().to_string(),
+        _ => file.display().to_string(),
+    };
+    for (category, files) in &groups.groups {
+        let mut files: Vec<String> = files.iter().map(|file| name(file)).collect();
+        files.sort();
+        println!("{} ({}):", category, files.len());
+        for file in &files {
+            println!("  {}", file);
+        }
+    }
+    if !groups.ungrouped.is_empty() {
+        let mut ungrouped: Vec<(String, &str)> = groups.ungrouped.iter().map(|(file, reason)| (name(file), reason.as_str())).collect();
+        ungrouped.sort();
+        println!("Not grouped ({}):", ungrouped.len());
+        for (file, reason) in &ungrouped {
+            println!("  {} ({})", file, reason);
+        }
+    }
+}
+
+/// What an undo restored and removed; failures go to stderr.
+pub fn print_undo(report: &UndoReport, verbosity: Verbosity) {
+    if verbosity >= Verbosity::Normal {
+        println!("Undoing run {}", report.run_id);
+    }
+    if verbosity >= Verbosity::Verbose {
+        for path in &report.restored {
+            println!("  restored {}", path.display());
+        }
+        for path in &report.removed {
+            println!("  removed {}", path.display());
+        }
+        for dir in &report.removed_dirs {
+            println!("  removed {}", dir.display());
+        }
+    }
+    for (path, reason) in &report.failed {
+        eprintln!("tidyup: cannot restore {}: {}", path.display(), reason);
+    }
+    if !report.failed.is_empty() {
+        eprintln!(
+            "tidyup: {} of {} files could not be restored; run `tidyup undo {}` again after fixing them",
+            report.failed.len(),
+            report.failed.len() + report.restored.len() + report.removed.len(),
+            report.run_id
+        );
+    } else if verbosity >= Verbosity::Normal {
+        println!("Restored {} files", report.restored.len());
+        if !report.removed.is_empty() {
+            println!("Removed {} copies and links", report.removed.len());
+        }
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,1029 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::bundle;
+use crate::classify::{Classification, Classifier};
+use crate::config::{Bundle, Config};
+use crate::conflict::{self, OnConflict};
+use crate::dedup::{self, Duplicate, OnDuplicate};
+use crate::error::Error;
+use crate::explain::{CategoryStep, Decision, Placement, Reach, RuleStep};
+use crate::group::Groups;
+use crate::fsops::{self, Transfer};
+use crate::report::Failure;
+use crate::rules::{Facts, MatchMode, Rule, RuleAction};
+use crate::scan::{Exclude, Scanner};
+use crate::sniff;
+use crate::template::{self, Template, Vars};
+
+/// What a file renamed in place by `--fix-extensions` is listed under.
+pub const FIXED_EXTENSION: &str = "fixed extension";
+
+/// Everything a run would do, in the order it would do it.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub root: PathBuf,
+    pub actions: Vec<Action>,
+    /// Entries that could not be inspected while planning.
+    pub failures: Vec<Failure>,
+    pub scanned_dirs: Vec<PathBuf>,
+    /// Files whose content is already at their destination or elsewhere in the scan.
+    pub duplicates: Vec<Duplicate>,
+}
+
+#[derive(Debug)]
+pub enum Action {
+    /// `category` names the category or rule that chose the destination;
+    /// `transfer` says whether the file is moved, copied or linked there.
+    Move { source: PathBuf, destination: PathBuf, category: String, transfer: Transfer },
+    /// Files that share a stem and move together, primary first; either all
+    /// of them end up at their destinations or none do.
+    Bundle { members: Vec<(PathBuf, PathBuf)>, category: String, transfer: Transfer },
+    /// Move the file to the trash.
+    Trash { path: PathBuf, reason: TrashReason },
+    /// Replace the file with a hard link to `original`, which holds the same bytes.
+    Link { path: PathBuf, original: PathBuf },
+    Skip { path: PathBuf, reason: SkipReason },
+}
+
+#[derive(Debug)]
+pub enum TrashReason {
+    Duplicate(PathBuf),
+    Rule(String),
+}
+
+impl fmt::Display for TrashReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrashReason::Duplicate(original) => write!(f, "duplicate of {}", original.display()),
+            TrashReason::Rule(name) => write!(f, "matched rule `{}`", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SkipReason {
+    NotAFile,
+    Symlink,
+    SymlinkLoop,
+    CategoryDir,
+    MaxDepth,
+    Excluded(String),
+    AlreadyInPlace,
+    /// A copy or link rule already put the file there.
+    AlreadyPlaced(PathBuf),
+    NoExtension,
+    ContentMismatch { extension: String, detected: String },
+    NotSelected(String),
+    Ignored(String),
+    Unmapped(String),
+    Rule(String),
+    /// A companion file whose primary is not moved.
+    Companion(PathBuf),
+    Duplicate(PathBuf),
+    /// The destination template could not be expanded.
+    Destination(String),
+    /// Left out when the plan was reviewed.
+    Declined,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::NotAFile => write!(f, "not a regular file"),
+            SkipReason::Symlink => write!(f, "symbolic link"),
+            SkipReason::SymlinkLoop => write!(f, "directory already scanned (symlink loop or duplicate link)"),
+            SkipReason::CategoryDir => write!(f, "category folder"),
+            SkipReason::MaxDepth => write!(f, "directory (beyond --max-depth)"),
+            SkipReason::Excluded(pattern) => write!(f, "excluded by `{}`", pattern),
+            SkipReason::AlreadyInPlace => write!(f, "already in its category folder"),
+            SkipReason::AlreadyPlaced(destination) => write!(f, "already at {}", destination.display()),
+            SkipReason::NoExtension => write!(f, "no extension"),
+            SkipReason::ContentMismatch { extension, detected } => {
+                write!(f, "named `.{}` but the content is `{}`", extension, detected)
+            }
+            SkipReason::NotSelected(ext) => write!(f, "extension `{}` not in --extensions", ext),
+            SkipReason::Ignored(ext) => write!(f, "extension `{}` is ignored", ext),
+            SkipReason::Unmapped(ext) => write!(f, "no category for extension `{}`", ext),
+            SkipReason::Rule(name) => write!(f, "left in place by rule `{}`", name),
+            SkipReason::Companion(primary) => write!(f, "companion of {}, which stays", primary.display()),
+            SkipReason::Duplicate(original) => write!(f, "duplicate of {}", original.display()),
+            SkipReason::Destination(message) => write!(f, "cannot build the destination: {}", message),
+            SkipReason::Declined => write!(f, "declined"),
+        }
+    }
+}
+
+impl Action {
+    /// The path the action is about: the directory, the moved file or the skipped entry.
+    pub fn path(&self) -> &Path {
+        match self {
+            Action::Skip { path, .. } | Action::Trash { path, .. } | Action::Link { path, .. } => path,
+            Action::Move { source, .. } => source,
+            Action::Bundle { members, .. } => &members[0].0,
+        }
+    }
+
+    /// The skips that take the action's place when it is left out.
+    pub fn declined(self) -> Vec<Action> {
+        let paths = match self {
+            Action::Skip { .. } => return vec![self],
+            Action::Bundle { members, .. } => members.into_iter().map(|(source, _)| source).collect(),
+            action => vec![action.path().to_path_buf()],
+        };
+        paths.into_iter().map(|path| Action::Skip { path, reason: SkipReason::Declined }).collect()
+    }
+}
+
+impl Plan {
+    /// Every file that would be moved, copied or linked, including each member of a bundle.
+    pub fn moves(&self) -> impl Iterator<Item = (&Path, &Path, &str, Transfer)> {
+        self.actions.iter().flat_map(|action| match action {
+            Action::Move { source, destination, category, transfer } => {
+                vec![(source.as_path(), destination.as_path(), category.as_str(), *transfer)]
+            }
+            Action::Bundle { members, category, transfer } => members
+                .iter()
+                .map(|(source, destination)| (source.as_path(), destination.as_path(), category.as_str(), *transfer))
+                .collect(),
+            _ => Vec::new(),
+        })
+    }
+
+    pub fn trashes(&self) -> impl Iterator<Item = (&Path, &TrashReason)> {
+        self.actions.iter().filter_map(|action| match action {
+            Action::Trash { path, reason } => Some((path.as_path(), reason)),
+            _ => None,
+        })
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.actions.iter().filter_map(|action| match action {
+            Action::Link { path, original } => Some((path.as_path(), original.as_path())),
+            _ => None,
+        })
+    }
+
+    pub fn skips(&self) -> impl Iterator<Item = (&Path, &SkipReason)> {
+        self.actions.iter().filter_map(|action| match action {
+            Action::Skip { path, reason } => Some((path.as_path(), reason)),
+            _ => None,
+        })
+    }
+
+    /// The folders the moves need that do not exist yet, parents first. The
+    /// executor creates them right before the first file goes into them.
+    pub fn create_dirs(&self) -> Vec<PathBuf> {
+        let mut create_dirs = Vec::new();
+        for (_, destination, _, _) in self.moves() {
+            if let Some(parent) = destination.parent() {
+                create_dirs.extend(missing_dirs(&self.root, parent));
+            }
+        }
+        create_dirs.sort();
+        create_dirs.dedup();
+        create_dirs
+    }
+
+    /// Whether executing the plan would change anything.
+    pub fn is_empty(&self) -> bool {
+        self.actions.iter().all(|action| matches!(action, Action::Skip { .. }))
+    }
+}
+
+/// `dir` and those of its ancestors below `root` that do not exist, parents first.
+pub fn missing_dirs(root: &Path, dir: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|ancestor| *ancestor != root && !ancestor.as_os_str().is_empty() && !ancestor.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+/// Where a copy or link of `source` already is: at `destination` or, after
+/// an earlier name clash, at one of its numbered variants.
+fn already_placed(transfer: Transfer, source: &Path, destination: &Path) -> Option<PathBuf> {
+    if transfer == Transfer::Move {
+        return None;
+    }
+    let mut candidate = destination.to_path_buf();
+    for n in 1.. {
+        if fsops::is_transferred(transfer, source, &candidate) {
+            return Some(candidate);
+        }
+        if fs::symlink_metadata(&candidate).is_err() {
+            break;
+        }
+        candidate = conflict::suffixed(destination, &format!("({})", n));
+    }
+    None
+}
+
+/// Adds the empty folders at and below `dir` to `empty`, children before
+/// their parents, and returns whether `dir` itself is empty.
+fn collect_empty(dir: &Path, empty: &mut Vec<PathBuf>) -> bool {
+    if !fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir()) {
+        return false;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return false;
+    };
+    let mut is_empty = true;
+    for entry in entries {
+        let Ok(entry) = entry else {
+            is_empty = false;
+            continue;
+        };
+        // Visit every child, so that nested empty folders are found even next to files.
+        if !collect_empty(&entry.path(), empty) {
+            is_empty = false;
+        }
+    }
+    if is_empty {
+        empty.push(dir.to_path_buf());
+    }
+    is_empty
+}
+
+/// What to scan and which files to consider.
+#[derive(Debug, Clone)]
+pub struct PlanOptions {
+    /// 1 only looks at the immediate children of the root.
+    pub max_depth: usize,
+    pub exclude: Vec<String>,
+    pub follow_symlinks: bool,
+    /// Also descend into category folders.
+    pub include_tidied: bool,
+    /// Only move files of these types; empty means all.
+    pub extensions: Vec<String>,
+    pub ignore: Vec<String>,
+    pub fix_extensions: bool,
+    /// Look for files whose content is already there; `None` does not look.
+    pub dedup: Option<OnDuplicate>,
+    /// Where category folders and rule destinations go; the scanned directory if `None`.
+    pub target: Option<PathBuf>,
+    /// Replaces the folder of every category.
+    pub folder: Option<Template>,
+    /// Puts every file in place this way instead of as its rule says, e.g. as
+    /// a symlink to build a view. Nothing is trashed then.
+    pub transfer: Option<Transfer>,
+    /// Plans these files instead of scanning the root; relative ones are below it.
+    pub files: Option<Vec<PathBuf>>,
+}
+
+impl Default for PlanOptions {
+    fn default() -> PlanOptions {
+        PlanOptions {
+            max_depth: 1,
+            exclude: Vec::new(),
+            follow_symlinks: false,
+            include_tidied: false,
+            extensions: Vec::new(),
+            ignore: Vec::new(),
+            fix_extensions: false,
+            dedup: None,
+            target: None,
+            folder: None,
+            transfer: None,
+            files: None,
+        }
+    }
+}
+
+/// Turns a directory scan into a [`Plan`] without touching the filesystem.
+pub struct Planner<'a> {
+    root: PathBuf,
+    target: PathBuf,
+    classifier: Classifier<'a>,
+    rules: &'a [Rule],
+    match_mode: MatchMode,
+    bundles: &'a [Bundle],
+    scanner: Scanner,
+    extensions: Vec<String>,
+    ignore: Vec<String>,
+    dedup: Option<OnDuplicate>,
+    transfer: Option<Transfer>,
+    files: Option<Vec<PathBuf>>,
+}
+
+impl<'a> Planner<'a> {
+    pub fn new(config: &'a Config, root: impl Into<PathBuf>, options: PlanOptions) -> Result<Planner<'a>, Error> {
+        let root = root.into();
+        let target = options.target.unwrap_or_else(|| root.clone());
+        let classifier = Classifier::new(config, &target, options.fix_extensions).with_folder(options.folder);
+        let exclude = Exclude::new(&options.exclude).map_err(|e| Error::InvalidPattern(e.to_string()))?;
+        let mut planner = Planner {
+            root,
+            target,
+            classifier,
+            rules: &config.rules,
+            match_mode: config.match_mode,
+            bundles: &config.bundles,
+            scanner: Scanner { max_depth: options.max_depth.max(1), exclude, follow_symlinks: options.follow_symlinks, skip_dirs: Vec::new() },
+            extensions: options.extensions,
+            ignore: options.ignore,
+            dedup: options.dedup,
+            transfer: options.transfer,
+            files: options.files,
+        };
+        if !options.include_tidied {
+            planner.scanner.skip_dirs = planner.target_dirs();
+        }
+        // A view inside the scanned directory is never part of it.
+        if let (Ok(root), Ok(target)) = (fs::canonicalize(&planner.root), fs::canonicalize(&planner.target)) {
+            match target.strip_prefix(&root) {
+                Ok(inside) if !inside.as_os_str().is_empty() => planner.scanner.skip_dirs.push(planner.root.join(inside)),
+                _ => {}
+            }
+        }
+        Ok(planner)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where files are put: the root, or the view being built.
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// The category folders and rule destinations below the target. The
+    /// config only allows folders inside it, but anything that would still
+    /// lead out of it is dropped here too, as these are pruned.
+    pub fn target_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = self.classifier.category_dirs();
+        let prefixes = self.rules.iter().filter_map(|rule| rule.destination.as_ref()).map(Template::prefix);
+        dirs.extend(prefixes.filter(|prefix| !prefix.as_os_str().is_empty()).map(|prefix| self.target.join(prefix)));
+        let target = fsops::normalize(&self.target);
+        dirs.retain(|dir| fsops::normalize(dir).strip_prefix(&target).is_ok_and(template::is_relative_inside));
+        dirs
+    }
+
+    /// The category folders and rule destinations, and the folders inside
+    /// them, that hold nothing but empty folders, deepest first.
+    pub fn empty_dirs(&self) -> Vec<PathBuf> {
+        let mut targets = self.target_dirs();
+        targets.sort();
+        targets.dedup();
+        let mut empty = Vec::new();
+        for dir in targets {
+            if !empty.contains(&dir) {
+                collect_empty(&dir, &mut empty);
+            }
+        }
+        empty
+    }
+
+    /// The empty folders a run of `plan` would prune: those it moves nothing into.
+    pub fn prunable(&self, plan: &Plan) -> Vec<PathBuf> {
+        let mut empty = self.empty_dirs();
+        empty.retain(|dir| !plan.moves().any(|(_, destination, _, _)| destination.starts_with(dir)));
+        empty
+    }
+
+    /// Sorts the files of `plan` by category without moving them. Listed
+    /// files need not exist: those that do not are grouped by name alone.
+    pub fn group(&self, mut plan: Plan) -> Groups {
+        let listed = self.files.is_some();
+        let (missing, failures): (Vec<_>, Vec<_>) = std::mem::take(&mut plan.failures)
+            .into_iter()
+            .partition(|failure| listed && failure.error.kind() == io::ErrorKind::NotFound);
+
+        let mut groups = Groups { failures, ..Groups::default() };
+        for (source, _, category, _) in plan.moves() {
+            groups.groups.entry(category.to_string()).or_default().push(source.to_path_buf());
+        }
+        // Folders only matter if they were listed.
+        groups.ungrouped = plan
+            .skips()
+            .filter(|(_, reason)| listed || !matches!(reason, SkipReason::NotAFile | SkipReason::CategoryDir))
+            .map(|(file, reason)| (file.to_path_buf(), reason.to_string()))
+            .collect();
+        groups.ungrouped.extend(plan.trashes().map(|(file, reason)| (file.to_path_buf(), format!("to trash, {}", reason))));
+        groups
+            .ungrouped
+            .extend(plan.links().map(|(file, original)| (file.to_path_buf(), format!("duplicate of {}", original.display()))));
+
+        for file in missing.into_iter().map(|failure| failure.path) {
+            match self.classify(&file) {
+                Ok(classification) => match self.reject(&classification.kind) {
+                    Some(reason) => groups.ungrouped.push((file, reason.to_string())),
+                    None => groups.groups.entry(classification.category.name.clone()).or_default().push(file),
+                },
+                Err(reason) => groups.ungrouped.push((file, reason.to_string())),
+            }
+        }
+        groups
+    }
+
+    /// The rules matching `path`, in the order they apply: only the first in
+    /// `first` mode, all of them in `all` mode.
+    pub fn matching_rules(&self, path: &Path, now: SystemTime) -> Vec<&'a Rule> {
+        self.rules_for(path, now, None)
+    }
+
+    fn rules_for(&self, path: &Path, now: SystemTime, record: Option<&mut Decision<'a>>) -> Vec<&'a Rule> {
+        let Some(facts) = Facts::new(path, now) else {
+            return Vec::new();
+        };
+        let Some(decision) = record else {
+            let mut matching = self.rules.iter().filter(|rule| rule.when.matches(&facts));
+            return match self.match_mode {
+                MatchMode::First => matching.next().into_iter().collect(),
+                MatchMode::All => matching.collect(),
+            };
+        };
+        // Every rule is traced, the ones after the rule that decides too.
+        let mut matching = Vec::new();
+        for rule in self.rules {
+            let trace = rule.when.trace(&facts);
+            let decides = trace.matched && (self.match_mode == MatchMode::All || matching.is_empty());
+            if decides {
+                matching.push(rule);
+            }
+            decision.rules.push(RuleStep { rule, trace, decides });
+        }
+        matching
+    }
+
+    /// `path` relative to the root, if it is below it.
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        let root = fs::canonicalize(&self.root).ok()?;
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let path = fs::canonicalize(dir).ok()?.join(path.file_name()?);
+        path.strip_prefix(root).ok().map(Path::to_path_buf)
+    }
+
+    /// The actions the matching rules call for. Copies and links add up, while
+    /// a matching move, skip or trash rule ends the list.
+    fn rule_actions(&self, path: &Path, rules: &[&Rule], now: SystemTime) -> Vec<Action> {
+        let facts = Facts::new(path, now);
+        let mut actions = Vec::new();
+        for rule in rules {
+            match rule.action {
+                RuleAction::Skip => {
+                    actions.push(Action::Skip { path: path.to_path_buf(), reason: SkipReason::Rule(rule.name.clone()) });
+                    break;
+                }
+                RuleAction::Trash if self.transfer.is_some() => {
+                    actions.push(Action::Skip { path: path.to_path_buf(), reason: SkipReason::Rule(rule.name.clone()) });
+                    break;
+                }
+                RuleAction::Trash => {
+                    actions.push(Action::Trash { path: path.to_path_buf(), reason: TrashReason::Rule(rule.name.clone()) });
+                    break;
+                }
+                RuleAction::Move | RuleAction::Copy | RuleAction::Symlink | RuleAction::Hardlink => {
+                    let (Some(template), Some(name)) = (&rule.destination, path.file_name()) else {
+                        continue;
+                    };
+                    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+                    let vars = Vars {
+                        path,
+                        extension: &extension,
+                        category: &rule.name,
+                        captures: facts.as_ref().map(|facts| rule.when.captures(facts)).unwrap_or_default(),
+                    };
+                    let folder = match template.render(&vars) {
+                        Ok(folder) => folder,
+                        Err(message) => {
+                            actions.push(Action::Skip { path: path.to_path_buf(), reason: SkipReason::Destination(message) });
+                            break;
+                        }
+                    };
+                    let destination = self.target.join(folder).join(name);
+                    if destination == path {
+                        actions.push(Action::Skip { path: path.to_path_buf(), reason: SkipReason::AlreadyInPlace });
+                        break;
+                    }
+                    let transfer = self.transfer.unwrap_or(rule.transfer);
+                    if let Some(placed) = already_placed(transfer, path, &destination) {
+                        actions.push(Action::Skip { path: path.to_path_buf(), reason: SkipReason::AlreadyPlaced(placed) });
+                    } else {
+                        actions.push(Action::Move { source: path.to_path_buf(), destination, category: rule.name.clone(), transfer });
+                    }
+                    if rule.transfer == Transfer::Move {
+                        break;
+                    }
+                }
+            }
+        }
+        actions
+    }
+
+    /// Classifies one file the way [`Planner::plan`] would.
+    pub fn classify(&self, path: &Path) -> Result<Classification<'a>, SkipReason> {
+        self.classifier.classify(path)
+    }
+
+    /// The reason files of type `kind` are filtered out, if they are.
+    pub fn reject(&self, kind: &str) -> Option<SkipReason> {
+        if !self.extensions.is_empty() && !self.extensions.iter().any(|ext| ext == kind) {
+            Some(SkipReason::NotSelected(kind.to_string()))
+        } else if self.ignore.iter().any(|ext| ext == kind) {
+            Some(SkipReason::Ignored(kind.to_string()))
+        } else {
+            None
+        }
+    }
+
+    /// Decides what happens to one regular file: by the matching rules if
+    /// any, by its category otherwise.
+    pub fn plan_file(&self, path: &Path, now: SystemTime) -> Vec<Action> {
+        self.decide(path, now, None)
+    }
+
+    /// [`Planner::plan_file`], recording the steps into `record` if given.
+    fn decide(&self, path: &Path, now: SystemTime, mut record: Option<&mut Decision<'a>>) -> Vec<Action> {
+        let skip = |reason| vec![Action::Skip { path: path.to_path_buf(), reason }];
+
+        let rules = if self.rules.is_empty() { Vec::new() } else { self.rules_for(path, now, record.as_deref_mut()) };
+        if !rules.is_empty() {
+            let kind = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+            let rejected = self.reject(&kind);
+            if let Some(decision) = record {
+                decision.filter = Some(rejected.clone());
+            }
+            return match rejected {
+                Some(reason) => skip(reason),
+                None => self.rule_actions(path, &rules, now),
+            };
+        }
+
+        let classification = self.classifier.classify(path);
+        if let Some(decision) = record.as_deref_mut() {
+            decision.category = Some(match &classification {
+                Ok(classification) => Ok(CategoryStep {
+                    category: classification.category,
+                    kind: classification.kind.clone(),
+                    by_content: classification.kind != decision.extension,
+                    folder: self.classifier.folder(classification.category).to_string(),
+                }),
+                Err(reason) => Err(reason.clone()),
+            });
+        }
+        let transfer = self.transfer.unwrap_or_default();
+        let classification = match classification {
+            Ok(classification) => classification,
+            Err(reason @ (SkipReason::Unmapped(_) | SkipReason::NoExtension)) => {
+                // No category takes the content either: only the name is fixed.
+                return match self.classifier.fixed_name(path) {
+                    Some((kind, destination)) if self.reject(kind).is_none() => {
+                        vec![Action::Move { source: path.to_path_buf(), destination, category: FIXED_EXTENSION.to_string(), transfer }]
+                    }
+                    _ => skip(reason),
+                };
+            }
+            Err(reason) => return skip(reason),
+        };
+        let rejected = self.reject(&classification.kind);
+        if let Some(decision) = record {
+            decision.filter = Some(rejected.clone());
+        }
+        match rejected {
+            Some(reason) => skip(reason),
+            None if classification.destination == path => skip(SkipReason::AlreadyInPlace),
+            None => match already_placed(transfer, path, &classification.destination) {
+                Some(placed) => skip(SkipReason::AlreadyPlaced(placed)),
+                None => vec![Action::Move {
+                    source: path.to_path_buf(),
+                    destination: classification.destination,
+                    category: classification.category.name.clone(),
+                    transfer,
+                }],
+            },
+        }
+    }
+
+    /// Decides what happens to a bundle: the primary is planned like any
+    /// file and the companions follow it into the same folder.
+    pub fn plan_bundle(&self, members: &[PathBuf], now: SystemTime) -> Vec<Action> {
+        self.decide_bundle(members, now, None)
+    }
+
+    fn decide_bundle(&self, members: &[PathBuf], now: SystemTime, record: Option<&mut Decision<'a>>) -> Vec<Action> {
+        let Some((primary, companions)) = members.split_first() else {
+            return Vec::new();
+        };
+        let actions = self.decide(primary, now, record);
+        if !actions.iter().any(|action| matches!(action, Action::Move { .. })) {
+            let reason = || SkipReason::Companion(primary.clone());
+            let skips = companions.iter().map(|path| Action::Skip { path: path.clone(), reason: reason() });
+            return actions.into_iter().chain(skips).collect();
+        }
+        actions
+            .into_iter()
+            .map(|action| {
+                let Action::Move { source, destination, category, transfer } = action else {
+                    return action;
+                };
+                let folder = destination.parent().unwrap_or(&self.target).to_path_buf();
+                let mut members = vec![(source, destination)];
+                for companion in companions {
+                    let name = companion.file_name().unwrap_or_default();
+                    members.push((companion.clone(), folder.join(name)));
+                }
+                Action::Bundle { members, category, transfer }
+            })
+            .collect()
+    }
+
+    /// Plans `path` like a run would and records each step of the decision on
+    /// the way: whether a scan reaches it, every rule, the category, the
+    /// filters, any duplicate at the destination, and what `policy` does
+    /// where the destination is taken.
+    pub fn explain(&self, path: &Path, now: SystemTime, policy: OnConflict) -> io::Result<Decision<'a>> {
+        let metadata = fs::symlink_metadata(path)?;
+        let mut decision = Decision::new(path.to_path_buf(), self.match_mode);
+        decision.size = metadata.len();
+        decision.age = metadata.modified().ok().and_then(|mtime| now.duration_since(mtime).ok()).map_or(0, |age| age.as_secs());
+        decision.reach = match self.relative(path) {
+            None => Reach::Outside,
+            Some(relative) => self.scanner.passes_over(&self.root, &relative).map_or(Reach::Reached, Reach::PassedOver),
+        };
+        if !metadata.is_file() {
+            let reason = if metadata.is_symlink() { SkipReason::Symlink } else { SkipReason::NotAFile };
+            decision.actions.push(Action::Skip { path: path.to_path_buf(), reason });
+            return Ok(decision);
+        }
+
+        let is_self = |member: &Path| member.file_name() == path.file_name();
+        let bundle = self.bundle_of(path);
+        let decider = match bundle.as_deref() {
+            Some([primary, ..]) if !is_self(primary) => {
+                decision.companion_of = Some(primary.clone());
+                primary.as_path()
+            }
+            Some([_, companions @ ..]) => {
+                decision.companions = companions.to_vec();
+                path
+            }
+            _ => path,
+        };
+        decision.extension = decider.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+        decision.content = sniff::sniff(decider).map(|detected| detected.map(|detected| detected.ext)).map_err(|e| e.to_string());
+
+        let mut actions = match &bundle {
+            Some(members) => self.decide_bundle(members, now, Some(&mut decision)),
+            None => self.decide(path, now, Some(&mut decision)),
+        };
+        if let Some(policy) = self.dedup {
+            // Only the destination folder is searched: the rest of the scan is not known here.
+            let (moves, mut skips): (Vec<_>, Vec<_>) = actions.into_iter().partition(|action| !matches!(action, Action::Skip { .. }));
+            let mut plan = Plan::default();
+            actions = deduplicate(moves, policy, &mut plan, &mut skips);
+            actions.extend(skips);
+            decision.duplicate_of = plan.duplicates.into_iter().find(|duplicate| duplicate.path == path).map(|duplicate| duplicate.original);
+        }
+        for action in &actions {
+            let (members, transfer) = match action {
+                Action::Move { source, destination, transfer, .. } => (vec![(source, destination)], *transfer),
+                Action::Bundle { members, transfer, .. } => (members.iter().map(|(source, destination)| (source, destination)).collect(), *transfer),
+                _ => continue,
+            };
+            for (source, destination) in members.into_iter().filter(|(source, _)| is_self(source)) {
+                decision.placements.push(Placement {
+                    source: source.clone(),
+                    destination: destination.clone(),
+                    transfer,
+                    taken: fs::symlink_metadata(destination).is_ok(),
+                    outcome: conflict::predict(source, destination, policy),
+                });
+            }
+        }
+        decision.actions = actions;
+        Ok(decision)
+    }
+
+    /// The bundle `path` belongs to among the files next to it, primary first.
+    pub fn bundle_of(&self, path: &Path) -> Option<Vec<PathBuf>> {
+        if self.bundles.is_empty() {
+            return None;
+        }
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let files = fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .map(|entry| entry.path())
+            .collect();
+        let (bundles, _) = bundle::group(files, self.bundles);
+        let name = path.file_name()?;
+        bundles.into_iter().find(|members| members.iter().any(|member| member.file_name() == Some(name)))
+    }
+
+    /// Scans the root, or lists the given files, and decides what happens to
+    /// every entry. Only an unreadable root is an error.
+    pub fn plan(&self) -> io::Result<Plan> {
+        let mut plan = Plan { root: self.root.clone(), ..Plan::default() };
+
+        let mut skips = Vec::new();
+        let scanned = match &self.files {
+            Some(files) => self.scanner.list(&self.root, files, &mut skips, &mut plan.failures),
+            None => self.scanner.scan(&self.root, &mut skips, &mut plan.failures)?,
+        };
+        plan.scanned_dirs = scanned.dirs;
+
+        let now = SystemTime::now();
+        let (bundles, files) = bundle::group(scanned.files, self.bundles);
+        let mut moves = Vec::new();
+        let actions = files
+            .iter()
+            .flat_map(|file_path| self.plan_file(file_path, now))
+            .chain(bundles.iter().flat_map(|members| self.plan_bundle(members, now)));
+        for action in actions {
+            match action {
+                Action::Skip { .. } => skips.push(action),
+                _ => moves.push(action),
+            }
+        }
+
+        if let Some(policy) = self.dedup {
+            moves = deduplicate(moves, policy, &mut plan, &mut skips);
+        }
+
+        moves.sort_by(|a, b| a.path().cmp(b.path()));
+        skips.sort_by(|a, b| a.path().cmp(b.path()));
+        plan.actions.extend(moves);
+        plan.actions.extend(skips);
+        Ok(plan)
+    }
+}
+
+/// Looks for moves whose file is already at the destination or is moved by
+/// another action too, and turns them into what `policy` asks for.
+fn deduplicate(moves: Vec<Action>, policy: OnDuplicate, plan: &mut Plan, skips: &mut Vec<Action>) -> Vec<Action> {
+    let mut incoming = Vec::new();
+    let mut folders = HashSet::new();
+    for action in &moves {
+        match action {
+            Action::Move { source, destination, transfer, .. } => {
+                // Only a move leaves a single copy behind; copies and links are meant to add one.
+                if *transfer == Transfer::Move {
+                    incoming.push(source.clone());
+                }
+                folders.extend(destination.parent().map(Path::to_path_buf));
+            }
+            Action::Bundle { members, .. } => {
+                folders.extend(members.iter().filter_map(|(_, destination)| destination.parent()).map(Path::to_path_buf));
+            }
+            _ => {}
+        }
+    }
+    // The shortest name is the one to keep: `photo.png` rather than `photo (1).png`.
+    incoming.sort_by_key(|path| (path.file_name().map_or(0, |name| name.len()), path.clone()));
+
+    let mut existing: Vec<PathBuf> = Vec::new();
+    let mut folders: Vec<_> = folders.into_iter().collect();
+    folders.sort();
+    for folder in folders {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+            .map(|entry| entry.path())
+            .collect();
+        files.sort();
+        existing.extend(files);
+    }
+
+    plan.duplicates = dedup::find(&incoming, &existing, &mut plan.failures);
+    plan.duplicates.sort_by(|a, b| a.path.cmp(&b.path));
+    if policy == OnDuplicate::Report {
+        return moves;
+    }
+
+    let originals: HashMap<&Path, &Path> =
+        plan.duplicates.iter().map(|duplicate| (duplicate.path.as_path(), duplicate.original.as_path())).collect();
+    let mut kept = Vec::new();
+    for action in moves {
+        let original = match &action {
+            Action::Move { source, .. } => originals.get(source.as_path()).map(|original| (source.clone(), original.to_path_buf())),
+            _ => None,
+        };
+        match (original, policy) {
+            (Some((path, original)), OnDuplicate::Skip) => skips.push(Action::Skip { path, reason: SkipReason::Duplicate(original) }),
+            (Some((path, original)), OnDuplicate::Trash) => kept.push(Action::Trash { path, reason: TrashReason::Duplicate(original) }),
+            (Some((path, original)), OnDuplicate::Hardlink) => kept.push(Action::Link { path, original }),
+            _ => kept.push(action),
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn plan(config: &Config, root: &Path, options: PlanOptions) -> Plan {
+        Planner::new(config, root, options).unwrap().plan().unwrap()
+    }
+
+    fn skip<'p>(plan: &'p Plan, path: &Path) -> &'p SkipReason {
+        plan.skips().find(|(skipped, _)| *skipped == path).map(|(_, reason)| reason).unwrap()
+    }
+
+    #[test]
+    fn classifies_by_extension() {
+        let dir = TestDir::new();
+        let photo = dir.file("photo.JPG", b"x");
+        let script = dir.file("script.py", b"x");
+        let notes = dir.file("notes.txt", b"x");
+        let bare = dir.file("README", b"x");
+        dir.file("images/old.png", b"x");
+        let config = Config::parse(crate::config::DEFAULT_CONFIG, None).unwrap();
+
+        let plan = plan(&config, dir.path(), PlanOptions { max_depth: 2, ..PlanOptions::default() });
+        let mut moves: Vec<_> = plan.moves().map(|(source, destination, category, _)| (source, destination, category)).collect();
+        moves.sort();
+        assert_eq!(
+            moves,
+            [
+                (photo.as_path(), dir.path().join("images/photo.JPG").as_path(), "images"),
+                (script.as_path(), dir.path().join("python/script.py").as_path(), "python"),
+            ]
+        );
+        assert!(matches!(skip(&plan, &notes), SkipReason::Unmapped(ext) if ext == "txt"));
+        assert!(matches!(skip(&plan, &bare), SkipReason::NoExtension));
+        assert!(matches!(skip(&plan, &dir.path().join("images")), SkipReason::CategoryDir));
+    }
+
+    #[test]
+    fn filters_by_extension() {
+        let dir = TestDir::new();
+        let photo = dir.file("photo.jpg", b"x");
+        let script = dir.file("script.py", b"x");
+        let config = Config::parse(crate::config::DEFAULT_CONFIG, None).unwrap();
+
+        let options = PlanOptions { extensions: vec!["py".to_string()], ..PlanOptions::default() };
+        let plan = plan(&config, dir.path(), options);
+        assert_eq!(plan.moves().map(|(source, _, _, _)| source).collect::<Vec<_>>(), [script.as_path()]);
+        assert!(matches!(skip(&plan, &photo), SkipReason::NotSelected(ext) if ext == "jpg"));
+    }
+
+    #[test]
+    fn fixes_extensions_by_content() {
+        let dir = TestDir::new();
+        let download = dir.file("download.bin", b"\x89PNG\r\n\x1a\n rest");
+        let photo = dir.file("photo.jpg", b"\x89PNG\r\n\x1a\n rest");
+        let report = dir.file("report.bin", b"%PDF-1.7");
+        let notes = dir.file("notes.bin", b"plain");
+        let config = Config::parse(
+            r#"
+            [[category]]
+            name = "images"
+            extensions = ["png", "jpg"]
+            "#,
+            None,
+        )
+        .unwrap();
+
+        let fixed = plan(&config, dir.path(), PlanOptions { fix_extensions: true, ..PlanOptions::default() });
+        let mut moves: Vec<_> = fixed.moves().map(|(source, destination, category, _)| (source, destination, category)).collect();
+        moves.sort();
+        assert_eq!(
+            moves,
+            [
+                (download.as_path(), dir.path().join("images/download.png").as_path(), "images"),
+                (photo.as_path(), dir.path().join("images/photo.png").as_path(), "images"),
+                (report.as_path(), dir.path().join("report.pdf").as_path(), FIXED_EXTENSION),
+            ]
+        );
+        assert!(matches!(skip(&fixed, &notes), SkipReason::Unmapped(ext) if ext == "bin"));
+
+        let plan = plan(&config, dir.path(), PlanOptions::default());
+        assert!(matches!(skip(&plan, &download), SkipReason::Unmapped(ext) if ext == "bin"));
+    }
+
+    #[test]
+    fn rules_come_before_categories() {
+        let dir = TestDir::new();
+        let invoice = dir.file("invoice-2024.jpg", b"x");
+        let kept = dir.file("keep.jpg", b"x");
+        let config = Config::parse(
+            r#"
+            [[category]]
+            name = "images"
+            extensions = ["jpg"]
+
+            [[rule]]
+            name = "invoices"
+            when = { name = "invoice-*" }
+            destination = "invoices"
+
+            [[rule]]
+            name = "keep"
+            when = { name = "keep.*" }
+            action = "skip"
+            "#,
+            None,
+        )
+        .unwrap();
+
+        let plan = plan(&config, dir.path(), PlanOptions::default());
+        let moves: Vec<_> = plan.moves().map(|(source, destination, category, _)| (source, destination, category)).collect();
+        assert_eq!(moves, [(invoice.as_path(), dir.path().join("invoices/invoice-2024.jpg").as_path(), "invoices")]);
+        assert!(matches!(skip(&plan, &kept), SkipReason::Rule(name) if name == "keep"));
+    }
+
+    #[test]
+    fn prunes_only_category_folders() {
+        let dir = TestDir::new();
+        fs::create_dir_all(dir.path().join("images/2024")).unwrap();
+        fs::create_dir_all(dir.path().join("python/keep")).unwrap();
+        dir.file("python/keep/a.py", b"x");
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        let config = Config::parse(crate::config::DEFAULT_CONFIG, None).unwrap();
+
+        let planner = Planner::new(&config, dir.path(), PlanOptions::default()).unwrap();
+        let mut empty = planner.empty_dirs();
+        empty.sort();
+        assert_eq!(empty, [dir.path().join("images"), dir.path().join("images/2024")]);
+        assert!(planner.target_dirs().iter().all(|target| target.starts_with(dir.path())));
+
+        let planner = Planner::new(&config, dir.path().join("python/.."), PlanOptions::default()).unwrap();
+        assert_eq!(planner.target_dirs().len(), config.categories.len());
+    }
+
+    #[test]
+    fn explains_reach_rules_and_conflicts() {
+        let dir = TestDir::new();
+        let invoice = dir.file("invoice-1.jpg", b"new");
+        dir.file("invoices/invoice-1.jpg", b"old");
+        let deep = dir.file("a/b/deep.jpg", b"x");
+        let excluded = dir.file("drafts/draft.jpg", b"x");
+        let elsewhere = TestDir::new();
+        let outside = elsewhere.file("far.jpg", b"x");
+        let config = Config::parse(
+            r#"
+            [[category]]
+            name = "images"
+            extensions = ["jpg"]
+
+            [[rule]]
+            name = "invoices"
+            when = { name = "invoice-*" }
+            destination = "invoices"
+
+            [[rule]]
+            name = "keep"
+            when = { name = "keep.*" }
+            action = "skip"
+            "#,
+            None,
+        )
+        .unwrap();
+        let options = PlanOptions { max_depth: 2, exclude: vec!["drafts".to_string()], ..PlanOptions::default() };
+        let planner = Planner::new(&config, dir.path(), options).unwrap();
+        let now = SystemTime::now();
+
+        let decision = planner.explain(&invoice, now, OnConflict::Rename).unwrap();
+        assert!(matches!(decision.reach, Reach::Reached));
+        let rules: Vec<_> = decision.rules.iter().map(|step| (step.rule.name.as_str(), step.trace.matched, step.decides)).collect();
+        assert_eq!(rules, [("invoices", true, true), ("keep", false, false)]);
+        assert!(decision.category.is_none());
+        let [placement] = decision.placements.as_slice() else { panic!("{:?}", decision.placements) };
+        let destination = dir.path().join("invoices/invoice-1.jpg");
+        assert_eq!(placement.destination, destination);
+        assert!(placement.taken);
+        assert!(matches!(&placement.outcome, Ok(conflict::Outcome::Moved(placed)) if *placed != destination));
+
+        let decision = planner.explain(&deep, now, OnConflict::Rename).unwrap();
+        assert!(matches!(decision.reach, Reach::PassedOver(SkipReason::MaxDepth)));
+        assert!(matches!(&decision.category, Some(Ok(step)) if step.category.name == "images" && !step.by_content));
+        assert!(!decision.placements[0].taken);
+        let decision = planner.explain(&excluded, now, OnConflict::Rename).unwrap();
+        assert!(matches!(decision.reach, Reach::PassedOver(SkipReason::Excluded(pattern)) if pattern == "drafts"));
+        let decision = planner.explain(&outside, now, OnConflict::Rename).unwrap();
+        assert!(matches!(decision.reach, Reach::Outside));
+
+        let decision = planner.explain(&dir.path().join("a"), now, OnConflict::Rename).unwrap();
+        assert!(matches!(decision.actions.as_slice(), [Action::Skip { reason: SkipReason::NotAFile, .. }]));
+        assert!(decision.rules.is_empty() && decision.placements.is_empty());
+    }
+
+    #[test]
+    fn explains_duplicates_at_the_destination() {
+        let dir = TestDir::new();
+        let original = dir.file("images/photo.jpg", b"same");
+        let copy = dir.file("photo (1).jpg", b"same");
+        let config = Config::parse(crate::config::DEFAULT_CONFIG, None).unwrap();
+        let options = PlanOptions { dedup: Some(OnDuplicate::Skip), ..PlanOptions::default() };
+        let planner = Planner::new(&config, dir.path(), options).unwrap();
+
+        let decision = planner.explain(&copy, SystemTime::now(), OnConflict::Rename).unwrap();
+        assert_eq!(decision.duplicate_of.as_deref(), Some(original.as_path()));
+        assert!(decision.placements.is_empty());
+        assert!(matches!(decision.actions.as_slice(), [Action::Skip { reason: SkipReason::Duplicate(of), .. }] if *of == original));
+    }
+}
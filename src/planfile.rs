@@ -0,0 +1,329 @@
+//! Plan files: a [`Plan`] written out to be reviewed, edited and applied later.
+//!
+//! The file is JSON Lines, a header and then one action per line, so that an
+//! action can be edited or deleted without touching the others; blank lines
+//! and lines starting with `#` are ignored. Every source carries a
+//! [`Fingerprint`], and [`load`] refuses the actions whose source changed.
+//! Destinations must stay below the header's root, and relative ones are
+//! taken to be below it.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{self as stdpath, Path, PathBuf};
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::fsops::Transfer;
+use crate::plan::{Action, Plan, TrashReason};
+use crate::report::Failure;
+use crate::template;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    #[serde(rename = "type")]
+    kind: String,
+    root: PathBuf,
+    created: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Entry {
+    Move { source: PathBuf, destination: PathBuf, category: String, transfer: String, fingerprint: Fingerprint },
+    Bundle { members: Vec<Member>, category: String, transfer: String },
+    /// Trashed because of `rule`, or as a duplicate of `original`.
+    Trash {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rule: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        original: Option<PathBuf>,
+        fingerprint: Fingerprint,
+    },
+    Link { path: PathBuf, original: PathBuf, fingerprint: Fingerprint },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Member {
+    source: PathBuf,
+    destination: PathBuf,
+    fingerprint: Fingerprint,
+}
+
+/// What a source looked like when the plan was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub inode: u64,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> io::Result<Fingerprint> {
+        let metadata = fs::symlink_metadata(path)?;
+        Ok(Fingerprint { size: metadata.len(), mtime: metadata.mtime(), mtime_nsec: metadata.mtime_nsec(), inode: metadata.ino() })
+    }
+
+    /// Why `path` no longer matches, if it does not.
+    fn check(&self, path: &Path) -> Option<io::Error> {
+        let current = match Fingerprint::of(path) {
+            Ok(current) => current,
+            Err(e) => return Some(e),
+        };
+        let mut changed = Vec::new();
+        if current.size != self.size {
+            changed.push("size");
+        }
+        if (current.mtime, current.mtime_nsec) != (self.mtime, self.mtime_nsec) {
+            changed.push("mtime");
+        }
+        if current.inode != self.inode {
+            changed.push("inode");
+        }
+        if changed.is_empty() {
+            None
+        } else {
+            Some(io::Error::other(format!("changed since it was planned ({}), not applied", changed.join(", "))))
+        }
+    }
+}
+
+/// Writes the actions of `plan` that change something to `path` and returns
+/// how many there were. Paths are written absolute.
+pub fn save(plan: &Plan, path: &Path) -> io::Result<usize> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let header = Header {
+        version: VERSION,
+        kind: "plan".to_string(),
+        root: stdpath::absolute(&plan.root)?,
+        created: Local::now().to_rfc3339(),
+    };
+    writeln!(out, "{}", serde_json::to_string(&header)?)?;
+
+    let fingerprint = |path: &Path| {
+        Fingerprint::of(path).map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))
+    };
+    let mut count = 0;
+    for action in &plan.actions {
+        let entry = match action {
+            Action::Move { source, destination, category, transfer } => Entry::Move {
+                source: stdpath::absolute(source)?,
+                destination: stdpath::absolute(destination)?,
+                category: category.clone(),
+                transfer: transfer.name().to_string(),
+                fingerprint: fingerprint(source)?,
+            },
+            Action::Bundle { members, category, transfer } => Entry::Bundle {
+                members: members
+                    .iter()
+                    .map(|(source, destination)| {
+                        Ok(Member {
+                            source: stdpath::absolute(source)?,
+                            destination: stdpath::absolute(destination)?,
+                            fingerprint: fingerprint(source)?,
+                        })
+                    })
+                    .collect::<io::Result<_>>()?,
+                category: category.clone(),
+                transfer: transfer.name().to_string(),
+            },
+            Action::Trash { path, reason } => {
+                let (rule, original) = match reason {
+                    TrashReason::Rule(rule) => (Some(rule.clone()), None),
+                    TrashReason::Duplicate(original) => (None, Some(stdpath::absolute(original)?)),
+                };
+                Entry::Trash { path: stdpath::absolute(path)?, rule, original, fingerprint: fingerprint(path)? }
+            }
+            Action::Link { path, original } => Entry::Link {
+                path: stdpath::absolute(path)?,
+                original: stdpath::absolute(original)?,
+                fingerprint: fingerprint(path)?,
+            },
+            Action::Skip { .. } => continue,
+        };
+        writeln!(out, "{}", serde_json::to_string(&entry)?)?;
+        count += 1;
+    }
+    out.flush()?;
+    Ok(count)
+}
+
+/// Reads a plan file back. Actions whose source changed since the plan was
+/// made end up in the plan's failures instead of its actions.
+pub fn load(path: &Path) -> Result<Plan, Error> {
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("cannot read {}: {}", path.display(), e)))?;
+    let invalid = |number: usize, message: String| Error::InvalidPlan(format!("{}:{}: {}", path.display(), number + 1, message));
+
+    let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    let header: Header = match lines.next() {
+        Some((number, line)) => serde_json::from_str(line).map_err(|e| invalid(number, e.to_string()))?,
+        None => return Err(Error::InvalidPlan(format!("{}: empty plan file", path.display()))),
+    };
+    if header.kind != "plan" || header.version != VERSION {
+        return Err(Error::InvalidPlan(format!(
+            "{}: not a version {} tidyup plan (type `{}`, version {})",
+            path.display(),
+            VERSION,
+            header.kind,
+            header.version
+        )));
+    }
+
+    let mut plan = Plan { root: header.root, ..Plan::default() };
+    for (number, line) in lines {
+        let entry: Entry = serde_json::from_str(line).map_err(|e| invalid(number, e.to_string()))?;
+        let transfer = |name: &str| name.parse::<Transfer>().map_err(|e| invalid(number, e));
+        let inside = |destination| inside(&plan.root, destination).map_err(|e| invalid(number, e));
+        let (action, fingerprints) = match entry {
+            Entry::Move { source, destination, category, transfer: name, fingerprint } => {
+                let checks = vec![(source.clone(), fingerprint)];
+                (Action::Move { source, destination: inside(destination)?, category, transfer: transfer(&name)? }, checks)
+            }
+            Entry::Bundle { members, category, transfer: name } => {
+                if members.is_empty() {
+                    return Err(invalid(number, "a bundle needs at least one member".to_string()));
+                }
+                let checks = members.iter().map(|member| (member.source.clone(), member.fingerprint)).collect();
+                let members = members
+                    .into_iter()
+                    .map(|member| Ok((member.source, inside(member.destination)?)))
+                    .collect::<Result<_, Error>>()?;
+                (Action::Bundle { members, category, transfer: transfer(&name)? }, checks)
+            }
+            Entry::Trash { path, rule, original, fingerprint } => {
+                let reason = match (rule, original) {
+                    (Some(rule), _) => TrashReason::Rule(rule),
+                    (None, Some(original)) => TrashReason::Duplicate(original),
+                    (None, None) => return Err(invalid(number, "trash needs a `rule` or an `original`".to_string())),
+                };
+                (Action::Trash { path: path.clone(), reason }, vec![(path, fingerprint)])
+            }
+            Entry::Link { path, original, fingerprint } => {
+                (Action::Link { path: path.clone(), original }, vec![(path, fingerprint)])
+            }
+        };
+        let changed: Vec<Failure> = fingerprints
+            .into_iter()
+            .filter_map(|(path, fingerprint)| fingerprint.check(&path).map(|error| Failure { path, error }))
+            .collect();
+        if changed.is_empty() {
+            plan.actions.push(action);
+        } else {
+            plan.failures.extend(changed);
+        }
+    }
+    Ok(plan)
+}
+
+/// `destination` as a path below `root`, which is where a relative one is
+/// taken to be; a plan cannot put files anywhere else.
+fn inside(root: &Path, destination: PathBuf) -> Result<PathBuf, String> {
+    let relative = destination.strip_prefix(root).unwrap_or(&destination);
+    if !template::is_relative_inside(relative) {
+        return Err(format!("destination {} is outside {}", destination.display(), root.display()));
+    }
+    Ok(root.join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+
+    fn invalid(dir: &TestDir, text: &str) -> String {
+        let path = dir.file("plan.jsonl", text.as_bytes());
+        match load(&path) {
+            Err(Error::InvalidPlan(message)) => message,
+            other => panic!("expected an invalid plan, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn loads_what_it_saved() {
+        let dir = TestDir::new();
+        let source = dir.file("a.jpg", b"x");
+        let destination = dir.path().join("images/a.jpg");
+        let plan = Plan {
+            root: dir.path().to_path_buf(),
+            actions: vec![Action::Move {
+                source: source.clone(),
+                destination: destination.clone(),
+                category: "images".to_string(),
+                transfer: Transfer::Move,
+            }],
+            ..Plan::default()
+        };
+        let path = dir.path().join("plan.jsonl");
+        assert_eq!(save(&plan, &path).unwrap(), 1);
+
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded.root, dir.path());
+        let moves: Vec<_> = loaded.moves().map(|(source, destination, _, _)| (source, destination)).collect();
+        assert_eq!(moves, [(source.as_path(), destination.as_path())]);
+        assert!(loaded.failures.is_empty());
+
+        fs::write(&source, b"changed").unwrap();
+        let loaded = load(&path).unwrap();
+        assert!(loaded.actions.is_empty());
+        assert_eq!(loaded.failures.len(), 1);
+    }
+
+    #[test]
+    fn rejects_bundles_without_members() {
+        let dir = TestDir::new();
+        let message = invalid(
+            &dir,
+            "{\"version\":1,\"type\":\"plan\",\"root\":\"/tmp\",\"created\":\"\"}\n\
+             {\"action\":\"bundle\",\"members\":[],\"category\":\"images\",\"transfer\":\"move\"}\n",
+        );
+        assert!(message.ends_with("plan.jsonl:2: a bundle needs at least one member"), "{}", message);
+    }
+
+    #[test]
+    fn keeps_destinations_inside_the_root() {
+        let dir = TestDir::new();
+        let header = "{\"version\":1,\"type\":\"plan\",\"root\":\"/tmp/root\",\"created\":\"\"}\n";
+        let fingerprint = "\"fingerprint\":{\"size\":0,\"mtime\":0,\"mtime_nsec\":0,\"inode\":0}";
+        let to = |destination: &str| {
+            format!("{}{{\"action\":\"move\",\"source\":\"/tmp/root/a\",\"destination\":\"{}\",\"category\":\"x\",\"transfer\":\"move\",{}}}\n", header, destination, fingerprint)
+        };
+        assert!(invalid(&dir, &to("/etc/a")).ends_with("plan.jsonl:2: destination /etc/a is outside /tmp/root"));
+        assert!(invalid(&dir, &to("/tmp/root/../a")).contains("plan.jsonl:2: destination /tmp/root/../a is outside"));
+        assert!(invalid(&dir, &to("../a")).contains("plan.jsonl:2: destination ../a is outside"));
+        assert!(invalid(&dir, &to("/tmp/root")).contains("plan.jsonl:2:"));
+        let bundle = format!(
+            "{}{{\"action\":\"bundle\",\"members\":[{{\"source\":\"/tmp/root/a\",\"destination\":\"/tmp/b\",{}}}],\"category\":\"x\",\"transfer\":\"move\"}}\n",
+            header, fingerprint
+        );
+        assert!(invalid(&dir, &bundle).contains("plan.jsonl:2: destination /tmp/b is outside"));
+
+        let source = dir.file("a.jpg", b"x");
+        let action = Action::Move { source, destination: dir.path().join("images/a.jpg"), category: "x".to_string(), transfer: Transfer::Move };
+        let path = dir.path().join("relative.jsonl");
+        save(&Plan { root: dir.path().to_path_buf(), actions: vec![action], ..Plan::default() }, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap().replace(&format!("{}/images", dir.path().display()), "images");
+        fs::write(&path, text).unwrap();
+        let loaded = load(&path).unwrap();
+        let destinations: Vec<_> = loaded.moves().map(|(_, destination, _, _)| destination).collect();
+        assert_eq!(destinations, [dir.path().join("images/a.jpg")]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let dir = TestDir::new();
+        assert!(invalid(&dir, "# nothing\n\n").ends_with("empty plan file"));
+        assert!(invalid(&dir, "{\"version\":2,\"type\":\"plan\",\"root\":\"/tmp\",\"created\":\"\"}\n").contains("not a version 1"));
+        assert!(invalid(&dir, "not json\n").contains("plan.jsonl:1:"));
+        let trash = "{\"version\":1,\"type\":\"plan\",\"root\":\"/tmp\",\"created\":\"\"}\n\
+                     # a comment\n\
+                     {\"action\":\"trash\",\"path\":\"/tmp/a\",\"fingerprint\":{\"size\":0,\"mtime\":0,\"mtime_nsec\":0,\"inode\":0}}\n";
+        assert!(invalid(&dir, trash).contains("plan.jsonl:3: trash needs a `rule` or an `original`"));
+    }
+}
@@ -0,0 +1,55 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::fsops::Transfer;
+use crate::plan::SkipReason;
+
+/// A file that could not be processed; the run carries on without it.
+#[derive(Debug)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub moved: usize,
+    pub skipped: usize,
+    pub copied: usize,
+    pub trashed: usize,
+    pub linked: usize,
+    /// Stale links removed from a view.
+    pub unlinked: usize,
+    pub failures: Vec<Failure>,
+}
+
+/// What happened to one entry while a plan was executed.
+#[derive(Debug)]
+pub enum Event<'a> {
+    CreatedDir(&'a Path),
+    RemovedDir(&'a Path),
+    Moved { source: &'a Path, destination: &'a Path },
+    /// Copied or linked; the source stays where it is.
+    Copied { source: &'a Path, destination: &'a Path, transfer: Transfer },
+    Trashed { path: &'a Path, trashed: &'a Path },
+    /// Replaced by a hard link to `original`.
+    Linked { path: &'a Path, original: &'a Path },
+    /// A stale link removed from a view.
+    Unlinked(&'a Path),
+    /// Left in place by the conflict policy.
+    Kept { path: &'a Path, reason: &'a str },
+    /// Left in place by the plan.
+    Skipped { path: &'a Path, reason: &'a SkipReason },
+    Failed { path: &'a Path, error: &'a io::Error },
+}
+
+/// Receives [`Event`]s as an [`Executor`](crate::Executor) works through a plan.
+pub trait Reporter {
+    fn report(&mut self, event: Event<'_>);
+}
+
+impl<F: FnMut(Event<'_>)> Reporter for F {
+    fn report(&mut self, event: Event<'_>) {
+        self(event)
+    }
+}
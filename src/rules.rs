@@ -0,0 +1,480 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::SystemTime;
+
+use globset::GlobMatcher;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::exif::{self, Exif};
+use crate::fsops::Transfer;
+use crate::template::Template;
+
+/// A declarative rule from the config: files matching `when` get `action`.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    /// Higher priorities are evaluated first; ties keep config order.
+    pub priority: i64,
+    pub when: Condition,
+    pub action: RuleAction,
+    /// Folder relative to the target directory; required for the actions that place files.
+    pub destination: Option<Template>,
+    /// How `move`, `copy`, `symlink` and `hardlink` put files there.
+    pub transfer: Transfer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    #[default]
+    Move,
+    /// Leave the original in place and put a copy at the destination.
+    Copy,
+    Symlink,
+    Hardlink,
+    /// Leave the file where it is.
+    Skip,
+    /// Move the file to the trash.
+    Trash,
+}
+
+impl RuleAction {
+    /// Whether the action puts the file, or a copy or link of it, at a destination.
+    pub fn places(self) -> bool {
+        matches!(self, RuleAction::Move | RuleAction::Copy | RuleAction::Symlink | RuleAction::Hardlink)
+    }
+}
+
+/// Whether the first matching rule decides, or every matching rule applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    #[default]
+    First,
+    All,
+}
+
+#[derive(Debug, Clone)]
+pub enum Condition {
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+    /// Glob against the file name.
+    Name(GlobMatcher),
+    /// Regex against the file name.
+    Regex(Regex),
+    Extension(Vec<String>),
+    Size(Range),
+    /// Seconds since the last modification.
+    Modified(Range),
+    /// Seconds since the last status change.
+    Changed(Range),
+    Uid(u32),
+    Gid(u32),
+    /// Exactly these permission bits.
+    Mode(u32),
+    ModeAll(u32),
+    ModeAny(u32),
+    Hidden(bool),
+    /// Glob against the EXIF camera make, ignoring case.
+    Make(GlobMatcher),
+    Model(GlobMatcher),
+    /// Seconds since the EXIF capture time, or since the last modification without one.
+    Taken(Range),
+    Orientation(u16),
+    /// Whether the EXIF data has a GPS position.
+    Gps(bool),
+}
+
+/// What conditions are checked against, gathered once per file.
+pub struct Facts<'a> {
+    pub path: &'a Path,
+    /// The file name, with anything that is not UTF-8 replaced by U+FFFD.
+    pub name: Cow<'a, str>,
+    pub extension: String,
+    pub metadata: fs::Metadata,
+    pub now: SystemTime,
+    exif: OnceCell<Option<Exif>>,
+}
+
+impl<'a> Facts<'a> {
+    pub fn new(path: &'a Path, now: SystemTime) -> Option<Facts<'a>> {
+        let name = path.file_name()?.to_string_lossy();
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+        Some(Facts { path, name, extension, metadata, now, exif: OnceCell::new() })
+    }
+
+    /// The EXIF data, read on first use.
+    pub fn exif(&self) -> Option<&Exif> {
+        self.exif.get_or_init(|| exif::read(self.path).ok().flatten()).as_ref()
+    }
+
+    fn age(&self, seconds: i64) -> u64 {
+        let now = self.now.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        now.saturating_sub(seconds).max(0) as u64
+    }
+}
+
+impl Condition {
+    pub fn matches(&self, facts: &Facts) -> bool {
+        match self {
+            Condition::All(conditions) => conditions.iter().all(|c| c.matches(facts)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.matches(facts)),
+            Condition::Not(condition) => !condition.matches(facts),
+            Condition::Name(glob) => glob.is_match(facts.name.as_ref()),
+            Condition::Regex(regex) => regex.is_match(&facts.name),
+            Condition::Extension(extensions) => extensions.contains(&facts.extension),
+            Condition::Size(range) => range.contains(facts.metadata.len()),
+            Condition::Modified(range) => range.contains(facts.age(facts.metadata.mtime())),
+            Condition::Changed(range) => range.contains(facts.age(facts.metadata.ctime())),
+            Condition::Uid(uid) => facts.metadata.uid() == *uid,
+            Condition::Gid(gid) => facts.metadata.gid() == *gid,
+            Condition::Mode(mode) => facts.metadata.mode() & 0o7777 == *mode,
+            Condition::ModeAll(bits) => facts.metadata.mode() & bits == *bits,
+            Condition::ModeAny(bits) => facts.metadata.mode() & bits != 0,
+            Condition::Hidden(hidden) => facts.name.starts_with('.') == *hidden,
+            Condition::Make(glob) => facts.exif().and_then(|exif| exif.make.as_deref()).is_some_and(|make| glob.is_match(make)),
+            Condition::Model(glob) => facts.exif().and_then(|exif| exif.model.as_deref()).is_some_and(|model| glob.is_match(model)),
+            Condition::Taken(range) => {
+                let taken = facts.exif().and_then(Exif::taken_at).map(|taken| taken.timestamp());
+                range.contains(facts.age(taken.unwrap_or(facts.metadata.mtime())))
+            }
+            Condition::Orientation(orientation) => facts.exif().and_then(|exif| exif.orientation) == Some(*orientation),
+            Condition::Gps(gps) => facts.exif().is_some_and(|exif| exif.gps.is_some()) == *gps,
+        }
+    }
+}
+
+/// How a condition went for one file, for `tidyup explain`.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    /// The condition as it would be written in the config, e.g. `size = ">=1M"`.
+    pub label: String,
+    /// What the file has, for conditions on a single property.
+    pub actual: Option<String>,
+    pub matched: bool,
+    /// The conditions inside `all`, `any` and `not`.
+    pub children: Vec<Trace>,
+}
+
+impl Condition {
+    /// Like [`Condition::matches`], but evaluates every condition, even
+    /// those that cannot change the result, and records how each went.
+    pub fn trace(&self, facts: &Facts) -> Trace {
+        let group = |label: &str, children: Vec<Trace>, matched: bool| Trace { label: label.to_string(), actual: None, matched, children };
+        let leaf = |label: String, actual: String| Trace { label, actual: Some(actual), matched: self.matches(facts), children: Vec::new() };
+        let mode = facts.metadata.mode() & 0o7777;
+        let exif = || facts.exif();
+        match self {
+            Condition::All(conditions) => {
+                let children: Vec<Trace> = conditions.iter().map(|c| c.trace(facts)).collect();
+                let matched = children.iter().all(|child| child.matched);
+                group("all", children, matched)
+            }
+            Condition::Any(conditions) => {
+                let children: Vec<Trace> = conditions.iter().map(|c| c.trace(facts)).collect();
+                let matched = children.iter().any(|child| child.matched);
+                group("any", children, matched)
+            }
+            Condition::Not(condition) => {
+                let child = condition.trace(facts);
+                let matched = !child.matched;
+                group("not", vec![child], matched)
+            }
+            Condition::Name(glob) => leaf(format!("name = \"{}\"", glob.glob()), facts.name.to_string()),
+            Condition::Regex(regex) => leaf(format!("regex = \"{}\"", regex.as_str()), facts.name.to_string()),
+            Condition::Extension(extensions) => {
+                let actual = if facts.extension.is_empty() { "none".to_string() } else { facts.extension.clone() };
+                leaf(format!("extension = [{}]", extensions.join(", ")), actual)
+            }
+            Condition::Size(range) => leaf(format!("size = \"{}\"", range.describe(format_size)), format_size(facts.metadata.len())),
+            Condition::Modified(range) => {
+                leaf(format!("mtime = \"{}\"", range.describe(format_age)), format!("{} ago", format_age(facts.age(facts.metadata.mtime()))))
+            }
+            Condition::Changed(range) => {
+                leaf(format!("ctime = \"{}\"", range.describe(format_age)), format!("{} ago", format_age(facts.age(facts.metadata.ctime()))))
+            }
+            Condition::Uid(uid) => leaf(format!("uid = {}", uid), facts.metadata.uid().to_string()),
+            Condition::Gid(gid) => leaf(format!("gid = {}", gid), facts.metadata.gid().to_string()),
+            Condition::Mode(bits) => leaf(format!("mode = \"{:04o}\"", bits), format!("{:04o}", mode)),
+            Condition::ModeAll(bits) => leaf(format!("mode_all = \"{:04o}\"", bits), format!("{:04o}", mode)),
+            Condition::ModeAny(bits) => leaf(format!("mode_any = \"{:04o}\"", bits), format!("{:04o}", mode)),
+            Condition::Hidden(hidden) => leaf(format!("hidden = {}", hidden), facts.name.starts_with('.').to_string()),
+            Condition::Make(glob) => {
+                let make = exif().and_then(|exif| exif.make.clone()).unwrap_or_else(|| "none".to_string());
+                leaf(format!("make = \"{}\"", glob.glob()), make)
+            }
+            Condition::Model(glob) => {
+                let model = exif().and_then(|exif| exif.model.clone()).unwrap_or_else(|| "none".to_string());
+                leaf(format!("model = \"{}\"", glob.glob()), model)
+            }
+            Condition::Taken(range) => {
+                let actual = match exif().and_then(Exif::taken_at) {
+                    Some(taken) => format!("{} ago", format_age(facts.age(taken.timestamp()))),
+                    None => format!("no capture time, modified {} ago", format_age(facts.age(facts.metadata.mtime()))),
+                };
+                leaf(format!("taken = \"{}\"", range.describe(format_age)), actual)
+            }
+            Condition::Orientation(orientation) => {
+                let actual = exif().and_then(|exif| exif.orientation).map_or("none".to_string(), |o| o.to_string());
+                leaf(format!("orientation = {}", orientation), actual)
+            }
+            Condition::Gps(gps) => leaf(format!("gps = {}", gps), exif().is_some_and(|exif| exif.gps.is_some()).to_string()),
+        }
+    }
+}
+
+impl Condition {
+    /// The first `regex` condition, unless it sits below a `not`.
+    fn regex(&self) -> Option<&Regex> {
+        match self {
+            Condition::Regex(regex) => Some(regex),
+            Condition::All(conditions) | Condition::Any(conditions) => conditions.iter().find_map(Condition::regex),
+            _ => None,
+        }
+    }
+
+    pub fn has_regex(&self) -> bool {
+        self.regex().is_some()
+    }
+
+    /// The first `regex` condition that took part in matching `facts`: one
+    /// below an `any` only counts if its branch matched.
+    fn matched_regex(&self, facts: &Facts) -> Option<&Regex> {
+        match self {
+            Condition::Regex(regex) if regex.is_match(&facts.name) => Some(regex),
+            Condition::All(conditions) | Condition::Any(conditions) => {
+                conditions.iter().filter(|condition| condition.matches(facts)).find_map(|condition| condition.matched_regex(facts))
+            }
+            _ => None,
+        }
+    }
+
+    /// The capture groups of the first `regex` condition that matched
+    /// `facts`, keyed by number and, for named groups, by name.
+    pub fn captures(&self, facts: &Facts) -> HashMap<String, String> {
+        let mut captures = HashMap::new();
+        let Some(regex) = self.matched_regex(facts) else {
+            return captures;
+        };
+        if let Some(found) = regex.captures(&facts.name) {
+            for (index, group) in regex.capture_names().enumerate() {
+                let Some(value) = found.get(index) else {
+                    continue;
+                };
+                captures.insert(index.to_string(), value.as_str().to_string());
+                if let Some(group) = group {
+                    captures.insert(group.to_string(), value.as_str().to_string());
+                }
+            }
+        }
+        captures
+    }
+}
+
+/// A half-open range `[min, max)` of sizes or ages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Range {
+    pub fn contains(&self, value: u64) -> bool {
+        self.min <= value && value < self.max
+    }
+
+    /// Parses `>N`, `>=N`, `<N`, `<=N`, `A..B`, `A..`, `..B` or an exact `N`,
+    /// with `unit` turning each number into a u64.
+    pub fn parse(text: &str, unit: fn(&str) -> Result<u64, String>) -> Result<Range, String> {
+        let text = text.trim();
+        let range = if let Some(rest) = text.strip_prefix(">=") {
+            Range { min: unit(rest)?, max: u64::MAX }
+        } else if let Some(rest) = text.strip_prefix('>') {
+            Range { min: unit(rest)?.saturating_add(1), max: u64::MAX }
+        } else if let Some(rest) = text.strip_prefix("<=") {
+            Range { min: 0, max: unit(rest)?.saturating_add(1) }
+        } else if let Some(rest) = text.strip_prefix('<') {
+            Range { min: 0, max: unit(rest)? }
+        } else if let Some((min, max)) = text.split_once("..") {
+            Range {
+                min: if min.trim().is_empty() { 0 } else { unit(min)? },
+                max: if max.trim().is_empty() { u64::MAX } else { unit(max)? },
+            }
+        } else {
+            let value = unit(text)?;
+            Range { min: value, max: value.saturating_add(1) }
+        };
+        if range.min >= range.max {
+            return Err(format!("range `{}` is empty", text));
+        }
+        Ok(range)
+    }
+
+    /// The range written back the way [`Range::parse`] reads it, with
+    /// `unit` turning each bound into text.
+    pub fn describe(&self, unit: fn(u64) -> String) -> String {
+        let exact = |value: u64| !unit(value).contains('.');
+        match (self.min, self.max) {
+            (min, u64::MAX) if min > 0 && !exact(min) && exact(min - 1) => format!(">{}", unit(min - 1)),
+            (min, u64::MAX) => format!(">={}", unit(min)),
+            (0, max) if !exact(max) && exact(max - 1) => format!("<={}", unit(max - 1)),
+            (0, max) => format!("<{}", unit(max)),
+            (min, max) if max == min + 1 => unit(min),
+            (min, max) => format!("{}..{}", unit(min), unit(max)),
+        }
+    }
+}
+
+/// `1536` -> `1.5K`, in the units of [`parse_size`].
+pub fn format_size(bytes: u64) -> String {
+    scaled(bytes, &[(1 << 40, "T"), (1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K"), (1, "B")])
+}
+
+/// `86400` -> `1d`, in the units of [`parse_age`].
+pub fn format_age(seconds: u64) -> String {
+    let day = 24 * 60 * 60;
+    scaled(seconds, &[(365 * day, "y"), (7 * day, "w"), (day, "d"), (60 * 60, "h"), (60, "m"), (1, "s")])
+}
+
+/// `value` in the largest of `units` that divides it evenly, or with one
+/// decimal in the largest it reaches.
+fn scaled(value: u64, units: &[(u64, &str)]) -> String {
+    let smallest = units[units.len() - 1];
+    if let Some((factor, unit)) = units.iter().copied().find(|&(factor, _)| factor > 1 && value >= factor && value.is_multiple_of(factor)) {
+        return format!("{}{}", value / factor, unit);
+    }
+    match units.iter().copied().find(|&(factor, _)| value >= factor) {
+        Some((factor, unit)) if factor > 1 => format!("{:.1}{}", value as f64 / factor as f64, unit),
+        _ => format!("{}{}", value, smallest.1),
+    }
+}
+
+/// Parses `512`, `10K`, `1.5MiB`, `2G`; units are powers of 1024.
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(text);
+    let factor: u64 = match unit.to_ascii_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return Err(format!("invalid size unit `{}` (expected B, K, M, G or T)", unit)),
+    };
+    scale(text, number, factor)
+}
+
+/// Parses an age in seconds from `90s`, `30m`, `12h`, `7d`, `2w` or `1y` (365 days).
+pub fn parse_age(text: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(text);
+    let factor: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Err(format!("invalid age `{}` (expected a number followed by s, m, h, d, w or y)", text.trim())),
+    };
+    scale(text, number, factor)
+}
+
+/// Parses permission bits written in octal, with or without a `0o` prefix.
+pub fn parse_mode(text: &str) -> Result<u32, String> {
+    let digits = text.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid mode `{}` (expected octal permission bits such as 0644)", text.trim())),
+    }
+}
+
+fn split_unit(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    (number.trim(), unit)
+}
+
+fn scale(text: &str, number: &str, factor: u64) -> Result<u64, String> {
+    let value: f64 = number.parse().map_err(|_| format!("invalid number in `{}`", text.trim()))?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(format!("invalid number in `{}`", text.trim()));
+    }
+    Ok((value * factor as f64).min(u64::MAX as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use globset::Glob;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn parses_ranges() {
+        let range = |text| Range::parse(text, parse_size);
+        assert_eq!(range(">10K"), Ok(Range { min: 10 * 1024 + 1, max: u64::MAX }));
+        assert_eq!(range(">=10K"), Ok(Range { min: 10 * 1024, max: u64::MAX }));
+        assert_eq!(range("<1M"), Ok(Range { min: 0, max: 1 << 20 }));
+        assert_eq!(range("<=1M"), Ok(Range { min: 0, max: (1 << 20) + 1 }));
+        assert_eq!(range("1K..2K"), Ok(Range { min: 1024, max: 2048 }));
+        assert_eq!(range("1K.."), Ok(Range { min: 1024, max: u64::MAX }));
+        assert_eq!(range("..2K"), Ok(Range { min: 0, max: 2048 }));
+        assert_eq!(range(" 512 "), Ok(Range { min: 512, max: 513 }));
+        assert_eq!(Range::parse(">7d", parse_age), Ok(Range { min: 7 * 86400 + 1, max: u64::MAX }));
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        assert!(Range::parse("2K..1K", parse_size).is_err());
+        assert!(Range::parse("<0", parse_size).is_err());
+        assert!(Range::parse(">10X", parse_size).is_err());
+        assert!(Range::parse("7", parse_age).is_err());
+    }
+
+    #[test]
+    fn matches_names_that_are_not_utf8() {
+        let dir = TestDir::new();
+        let path = dir.path().join(OsStr::from_bytes(b"caf\xe9.txt"));
+        fs::write(&path, b"x").unwrap();
+        let facts = Facts::new(&path, SystemTime::now()).unwrap();
+        assert_eq!(facts.name, "caf\u{fffd}.txt");
+        assert!(Condition::Name(Glob::new("caf*.txt").unwrap().compile_matcher()).matches(&facts));
+        assert!(Condition::Extension(vec!["txt".to_string()]).matches(&facts));
+    }
+
+    #[test]
+    fn captures_from_the_regex_that_matched() {
+        let dir = TestDir::new();
+        let path = dir.file("IMG_2024.jpg", b"x");
+        let facts = Facts::new(&path, SystemTime::now()).unwrap();
+        let regex = |pattern| Condition::Regex(Regex::new(pattern).unwrap());
+        let any = Condition::Any(vec![regex(r"^scan-(?P<year>\d+)"), regex(r"^IMG_(?P<year>\d+)")]);
+        assert!(any.matches(&facts));
+        assert_eq!(any.captures(&facts).get("year").map(String::as_str), Some("2024"));
+
+        let size = Condition::Size(Range { min: 1 << 20, max: u64::MAX });
+        let branches = Condition::Any(vec![Condition::All(vec![size, regex(r"^(IMG)")]), regex(r"_(\d+)")]);
+        assert_eq!(branches.captures(&facts).get("1").map(String::as_str), Some("2024"));
+        assert!(Condition::Not(Box::new(regex(r"^(x)"))).captures(&facts).is_empty());
+    }
+
+    #[test]
+    fn describes_ranges_the_way_they_parse() {
+        for text in [">10K", ">=1.5M", "<1M", "<=2G", "1K..2K", "512B"] {
+            let range = Range::parse(text, parse_size).unwrap();
+            assert_eq!(Range::parse(&range.describe(format_size), parse_size), Ok(range), "{}", text);
+        }
+        for text in [">7d", "<=2w", "1h..2d"] {
+            let range = Range::parse(text, parse_age).unwrap();
+            assert_eq!(Range::parse(&range.describe(format_age), parse_age), Ok(range), "{}", text);
+        }
+    }
+}
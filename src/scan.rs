@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::plan::{Action, SkipReason};
+use crate::report::Failure;
+
+pub struct Scanner {
+    /// 1 only looks at the immediate children of the root.
+    pub max_depth: usize,
+    pub exclude: Exclude,
+    pub follow_symlinks: bool,
+    /// Directories that are never descended into, e.g. category folders.
+    pub skip_dirs: Vec<PathBuf>,
+}
+
+#[derive(Default)]
+pub struct Exclude {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl Exclude {
+    pub fn new(patterns: &[String]) -> Result<Exclude, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern)?);
+        }
+        Ok(Exclude { patterns: patterns.to_vec(), set: builder.build()? })
+    }
+
+    /// Matches `relative` (to the scan root) and its file name, so that
+    /// `node_modules` excludes the folder at any depth.
+    fn matching(&self, relative: &Path) -> Option<&str> {
+        let mut matches = self.set.matches(relative);
+        if matches.is_empty() {
+            if let Some(name) = relative.file_name() {
+                matches = self.set.matches(name);
+            }
+        }
+        matches.first().map(|&i| self.patterns[i].as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Scanned {
+    pub files: Vec<PathBuf>,
+    /// Every directory that was listed, starting with the root.
+    pub dirs: Vec<PathBuf>,
+}
+
+impl Scanner {
+    /// Lists the regular files below `root`, recording everything else it
+    /// passes over as `Action::Skip` in `skips`. Only an unreadable `root` is an error;
+    /// problems further down end up in `failures`.
+    pub fn scan(&self, root: &Path, skips: &mut Vec<Action>, failures: &mut Vec<Failure>) -> io::Result<Scanned> {
+        let mut scanned = Scanned::default();
+        let mut visited = HashSet::new();
+        let root_metadata = fs::metadata(root)?;
+        visited.insert((root_metadata.dev(), root_metadata.ino()));
+
+        let mut pending = vec![(root.to_path_buf(), 1)];
+        while let Some((dir, depth)) = pending.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(error) if dir == root => return Err(error),
+                Err(error) => {
+                    failures.push(Failure { path: dir, error });
+                    continue;
+                }
+            };
+            scanned.dirs.push(dir.clone());
+            for entry in entries {
+                let (path, metadata) = match entry.and_then(|entry| Ok((entry.path(), entry.metadata()?))) {
+                    Ok(found) => found,
+                    Err(error) => {
+                        failures.push(Failure { path: dir.clone(), error });
+                        continue;
+                    }
+                };
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+
+                if let Some(pattern) = self.exclude.matching(relative) {
+                    skips.push(Action::Skip { path, reason: SkipReason::Excluded(pattern.to_string()) });
+                    continue;
+                }
+
+                if metadata.is_file() {
+                    scanned.files.push(path);
+                    continue;
+                }
+
+                let target = if metadata.is_symlink() && self.follow_symlinks {
+                    match fs::metadata(&path) {
+                        Ok(target) if target.is_dir() => target,
+                        _ => {
+                            skips.push(Action::Skip { path, reason: SkipReason::Symlink });
+                            continue;
+                        }
+                    }
+                } else if metadata.is_symlink() {
+                    skips.push(Action::Skip { path, reason: SkipReason::Symlink });
+                    continue;
+                } else {
+                    metadata
+                };
+
+                if !target.is_dir() || self.max_depth == 1 {
+                    skips.push(Action::Skip { path, reason: SkipReason::NotAFile });
+                } else if self.skip_dirs.contains(&path) {
+                    skips.push(Action::Skip { path, reason: SkipReason::CategoryDir });
+                } else if depth >= self.max_depth {
+                    skips.push(Action::Skip { path, reason: SkipReason::MaxDepth });
+                } else if !visited.insert((target.dev(), target.ino())) {
+                    skips.push(Action::Skip { path, reason: SkipReason::SymlinkLoop });
+                } else {
+                    pending.push((path, depth + 1));
+                }
+            }
+        }
+        Ok(scanned)
+    }
+
+    /// Why [`Scanner::scan`] of `root` would never reach the file at
+    /// `relative` below it, if it would not.
+    pub fn passes_over(&self, root: &Path, relative: &Path) -> Option<SkipReason> {
+        let depth = relative.components().count();
+        for prefix in relative.ancestors().collect::<Vec<_>>().into_iter().rev().skip(1) {
+            if let Some(pattern) = self.exclude.matching(prefix) {
+                return Some(SkipReason::Excluded(pattern.to_string()));
+            }
+            if prefix != relative && self.skip_dirs.contains(&root.join(prefix)) {
+                return Some(SkipReason::CategoryDir);
+            }
+        }
+        (depth > self.max_depth).then_some(SkipReason::MaxDepth)
+    }
+
+    /// Like [`Scanner::scan`], but for an explicit list of `paths` instead of
+    /// the entries below `root`; relative ones are taken to be below `root`.
+    /// Directories in the list are not descended into.
+    pub fn list(&self, root: &Path, paths: &[PathBuf], skips: &mut Vec<Action>, failures: &mut Vec<Failure>) -> Scanned {
+        let mut scanned = Scanned::default();
+        let mut seen = HashSet::new();
+        for path in paths {
+            let path = root.join(path.strip_prefix(".").unwrap_or(path));
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(error) => {
+                    failures.push(Failure { path, error });
+                    continue;
+                }
+            };
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if let Some(pattern) = self.exclude.matching(relative) {
+                skips.push(Action::Skip { path, reason: SkipReason::Excluded(pattern.to_string()) });
+            } else if metadata.is_file() {
+                scanned.files.push(path);
+            } else if metadata.is_symlink() {
+                skips.push(Action::Skip { path, reason: SkipReason::Symlink });
+            } else {
+                skips.push(Action::Skip { path, reason: SkipReason::NotAFile });
+            }
+        }
+        scanned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::TestDir;
+    use std::os::unix::fs::symlink;
+
+    fn scanner(max_depth: usize, exclude: &[&str], follow_symlinks: bool) -> Scanner {
+        let exclude: Vec<String> = exclude.iter().map(|pattern| pattern.to_string()).collect();
+        Scanner { max_depth, exclude: Exclude::new(&exclude).unwrap(), follow_symlinks, skip_dirs: Vec::new() }
+    }
+
+    /// The files found below `root`, relative to it, and the reasons the rest was skipped.
+    fn scan(scanner: &Scanner, root: &Path) -> (Vec<PathBuf>, Vec<(PathBuf, SkipReason)>) {
+        let (mut skips, mut failures) = (Vec::new(), Vec::new());
+        let scanned = scanner.scan(root, &mut skips, &mut failures).unwrap();
+        assert!(failures.is_empty());
+        let mut files: Vec<PathBuf> = scanned.files.iter().map(|file| file.strip_prefix(root).unwrap().to_path_buf()).collect();
+        files.sort();
+        let mut skips: Vec<_> = skips
+            .into_iter()
+            .map(|skip| match skip {
+                Action::Skip { path, reason } => (path.strip_prefix(root).unwrap().to_path_buf(), reason),
+                action => panic!("not a skip: {:?}", action),
+            })
+            .collect();
+        skips.sort_by(|a, b| a.0.cmp(&b.0));
+        (files, skips)
+    }
+
+    fn tree() -> TestDir {
+        let dir = TestDir::new();
+        dir.file("a.txt", b"x");
+        dir.file("sub/b.txt", b"x");
+        dir.file("sub/deeper/c.txt", b"x");
+        dir.file("sub/node_modules/d.js", b"x");
+        dir.file("e.tmp", b"x");
+        dir
+    }
+
+    #[test]
+    fn stops_at_the_depth_limit() {
+        let dir = tree();
+        let (files, skips) = scan(&scanner(1, &[], false), dir.path());
+        assert_eq!(files, [Path::new("a.txt"), Path::new("e.tmp")]);
+        assert!(matches!(skips.as_slice(), [(sub, SkipReason::NotAFile)] if sub == Path::new("sub")));
+
+        let (files, skips) = scan(&scanner(2, &[], false), dir.path());
+        assert_eq!(files, [Path::new("a.txt"), Path::new("e.tmp"), Path::new("sub/b.txt")]);
+        assert!(skips.iter().all(|(_, reason)| matches!(reason, SkipReason::MaxDepth)));
+        assert_eq!(skips.len(), 2);
+
+        let (files, _) = scan(&scanner(usize::MAX, &[], false), dir.path());
+        assert_eq!(files.len(), 5);
+    }
+
+    #[test]
+    fn excludes_by_name_and_by_path() {
+        let dir = tree();
+        let (files, skips) = scan(&scanner(10, &["node_modules", "*.tmp", "sub/deeper"], false), dir.path());
+        assert_eq!(files, [Path::new("a.txt"), Path::new("sub/b.txt")]);
+        let excluded: Vec<_> = skips
+            .iter()
+            .map(|(path, reason)| match reason {
+                SkipReason::Excluded(pattern) => (path.as_path(), pattern.as_str()),
+                reason => panic!("{} skipped: {}", path.display(), reason),
+            })
+            .collect();
+        assert_eq!(
+            excluded,
+            [(Path::new("e.tmp"), "*.tmp"), (Path::new("sub/deeper"), "sub/deeper"), (Path::new("sub/node_modules"), "node_modules")]
+        );
+    }
+
+    #[test]
+    fn follows_symlinks_only_when_asked_and_only_once() {
+        let dir = TestDir::new();
+        dir.file("sub/a.txt", b"x");
+        symlink(dir.path(), dir.path().join("sub/loop")).unwrap();
+        symlink(dir.path().join("sub/a.txt"), dir.path().join("link.txt")).unwrap();
+
+        let (files, skips) = scan(&scanner(10, &[], false), dir.path());
+        assert_eq!(files, [Path::new("sub/a.txt")]);
+        assert!(matches!(skips.as_slice(), [(_, SkipReason::Symlink), (_, SkipReason::Symlink)]));
+
+        let (files, skips) = scan(&scanner(10, &[], true), dir.path());
+        assert_eq!(files, [Path::new("sub/a.txt")]);
+        assert!(matches!(
+            skips.as_slice(),
+            [(link, SkipReason::Symlink), (looped, SkipReason::SymlinkLoop)]
+                if link == Path::new("link.txt") && looped == Path::new("sub/loop")
+        ));
+    }
+
+    #[test]
+    fn never_enters_category_folders() {
+        let dir = tree();
+        let mut scanner = scanner(10, &[], false);
+        scanner.skip_dirs.push(dir.path().join("sub"));
+        let (files, skips) = scan(&scanner, dir.path());
+        assert_eq!(files, [Path::new("a.txt"), Path::new("e.tmp")]);
+        assert!(matches!(skips.as_slice(), [(_, SkipReason::CategoryDir)]));
+    }
+
+    #[test]
+    fn lists_only_the_given_entries() {
+        let dir = tree();
+        let paths = ["a.txt", "./a.txt", "sub", "e.tmp", "missing.txt"].map(PathBuf::from);
+        let absolute = dir.path().join("sub/deeper/c.txt");
+        let paths: Vec<PathBuf> = paths.into_iter().chain([absolute.clone()]).collect();
+        let (mut skips, mut failures) = (Vec::new(), Vec::new());
+
+        let scanned = scanner(1, &["*.tmp"], false).list(dir.path(), &paths, &mut skips, &mut failures);
+        assert_eq!(scanned.files, [dir.path().join("a.txt"), absolute]);
+        assert!(matches!(
+            skips.as_slice(),
+            [Action::Skip { reason: SkipReason::NotAFile, .. }, Action::Skip { reason: SkipReason::Excluded(_), .. }]
+        ));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].path, dir.path().join("missing.txt"));
+    }
+}
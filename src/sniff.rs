@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+const HEADER_SIZE: usize = 8192;
+
+/// A file type recognised from its leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detected {
+    /// Canonical extension, also used as the key into the category mapping.
+    pub ext: &'static str,
+    /// Other extensions that name the same format.
+    pub aliases: &'static [&'static str],
+    /// Whether `--fix-extensions` may rename a file to `ext`. Off for formats
+    /// that conventionally carry no extension or many unrelated ones.
+    pub fixable: bool,
+}
+
+impl Detected {
+    const fn new(ext: &'static str, aliases: &'static [&'static str]) -> Detected {
+        Detected { ext, aliases, fixable: true }
+    }
+
+    const fn unfixable(ext: &'static str, aliases: &'static [&'static str]) -> Detected {
+        Detected { ext, aliases, fixable: false }
+    }
+
+    pub fn matches_extension(&self, ext: &str) -> bool {
+        self.ext == ext || self.aliases.contains(&ext)
+    }
+}
+
+const PNG: Detected = Detected::new("png", &[]);
+const JPEG: Detected = Detected::new("jpg", &["jpeg", "jpe", "jfif"]);
+const GIF: Detected = Detected::new("gif", &[]);
+const WEBP: Detected = Detected::new("webp", &[]);
+const BMP: Detected = Detected::new("bmp", &["dib"]);
+const TIFF: Detected = Detected::unfixable("tif", &["tiff", "dng", "cr2", "nef", "arw", "pef", "srw", "3fr", "erf", "nrw"]);
+const ICO: Detected = Detected::new("ico", &["cur"]);
+const HEIC: Detected = Detected::new("heic", &["heif", "hif"]);
+const AVIF: Detected = Detected::new("avif", &[]);
+const PDF: Detected = Detected::new("pdf", &["ai"]);
+const ZIP: Detected = Detected::unfixable(
+    "zip",
+    &["jar", "apk", "aar", "cbz", "xpi", "whl", "nupkg", "ipa", "kmz", "3mf", "vsix", "crx", "ora", "sketch"],
+);
+const DOCX: Detected = Detected::new("docx", &["docm", "dotx"]);
+const XLSX: Detected = Detected::new("xlsx", &["xlsm", "xltx"]);
+const PPTX: Detected = Detected::new("pptx", &["pptm", "potx", "ppsx"]);
+const ODT: Detected = Detected::new("odt", &[]);
+const ODS: Detected = Detected::new("ods", &[]);
+const ODP: Detected = Detected::new("odp", &[]);
+const EPUB: Detected = Detected::new("epub", &[]);
+const GZIP: Detected = Detected::new("gz", &["tgz", "gzip"]);
+const BZIP2: Detected = Detected::new("bz2", &["tbz", "tbz2"]);
+const XZ: Detected = Detected::new("xz", &["txz"]);
+const ZSTD: Detected = Detected::new("zst", &["tzst"]);
+const SEVEN_ZIP: Detected = Detected::new("7z", &[]);
+const RAR: Detected = Detected::new("rar", &["cbr"]);
+const TAR: Detected = Detected::new("tar", &[]);
+const ELF: Detected = Detected::unfixable("elf", &["so", "o", "ko", "bin", "out", "axf", "prx", "mod"]);
+const MP3: Detected = Detected::new("mp3", &[]);
+const FLAC: Detected = Detected::new("flac", &[]);
+const OGG: Detected = Detected::unfixable("ogg", &["oga", "ogv", "opus", "spx"]);
+const WAV: Detected = Detected::new("wav", &["wave"]);
+const AVI: Detected = Detected::new("avi", &[]);
+const MP4: Detected = Detected::new("mp4", &["m4v", "m4p"]);
+const M4A: Detected = Detected::new("m4a", &["m4b"]);
+const MOV: Detected = Detected::new("mov", &["qt"]);
+const MKV: Detected = Detected::unfixable("mkv", &["webm", "mka", "mk3d"]);
+const SQLITE: Detected = Detected::unfixable("sqlite", &["db", "sqlite3", "db3"]);
+const SHELL: Detected = Detected::unfixable("sh", &["bash", "zsh", "ksh"]);
+const PYTHON: Detected = Detected::unfixable("py", &["pyw"]);
+const PERL: Detected = Detected::unfixable("pl", &["pm"]);
+const RUBY: Detected = Detected::unfixable("rb", &[]);
+const NODE: Detected = Detected::unfixable("js", &["mjs", "cjs"]);
+
+/// Reads the start of `path` and identifies its format, if known.
+pub fn sniff(path: &Path) -> io::Result<Option<Detected>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    File::open(path)?.take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+    Ok(detect(&header))
+}
+
+pub fn detect(b: &[u8]) -> Option<Detected> {
+    let at = |offset: usize, magic: &[u8]| b.len() >= offset + magic.len() && &b[offset..offset + magic.len()] == magic;
+
+    if at(0, b"\x89PNG\r\n\x1a\n") {
+        Some(PNG)
+    } else if at(0, b"\xff\xd8\xff") {
+        Some(JPEG)
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Some(GIF)
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        Some(WEBP)
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Some(WAV)
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        Some(AVI)
+    } else if at(0, b"BM") && b.len() >= 26 && at(6, b"\0\0\0\0") {
+        Some(BMP)
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        Some(TIFF)
+    } else if at(0, b"\0\0\x01\0") && b.len() >= 6 && b[4] > 0 {
+        Some(ICO)
+    } else if at(0, b"%PDF-") {
+        Some(PDF)
+    } else if at(0, b"PK\x03\x04") {
+        Some(detect_zip(b))
+    } else if at(0, b"\x1f\x8b") {
+        Some(GZIP)
+    } else if at(0, b"BZh") {
+        Some(BZIP2)
+    } else if at(0, b"\xfd7zXZ\0") {
+        Some(XZ)
+    } else if at(0, b"\x28\xb5\x2f\xfd") {
+        Some(ZSTD)
+    } else if at(0, b"7z\xbc\xaf\x27\x1c") {
+        Some(SEVEN_ZIP)
+    } else if at(0, b"Rar!\x1a\x07") {
+        Some(RAR)
+    } else if at(257, b"ustar") {
+        Some(TAR)
+    } else if at(0, b"\x7fELF") {
+        Some(ELF)
+    } else if at(0, b"ID3") || (b.len() >= 2 && b[0] == 0xff && b[1] & 0xe0 == 0xe0 && b[1] & 0x06 != 0) {
+        Some(MP3)
+    } else if at(0, b"fLaC") {
+        Some(FLAC)
+    } else if at(0, b"OggS") {
+        Some(OGG)
+    } else if at(4, b"ftyp") {
+        detect_ftyp(b)
+    } else if at(0, b"\x1a\x45\xdf\xa3") {
+        Some(MKV)
+    } else if at(0, b"SQLite format 3\0") {
+        Some(SQLITE)
+    } else if at(0, b"#!") {
+        detect_shebang(b)
+    } else {
+        None
+    }
+}
+
+/// Tells OOXML, OpenDocument and EPUB apart from a plain zip by the
+/// names in the first local headers.
+fn detect_zip(b: &[u8]) -> Detected {
+    let contains = |needle: &[u8]| b.windows(needle.len()).any(|w| w == needle);
+
+    if contains(b"mimetypeapplication/epub+zip") {
+        EPUB
+    } else if contains(b"mimetypeapplication/vnd.oasis.opendocument.text") {
+        ODT
+    } else if contains(b"mimetypeapplication/vnd.oasis.opendocument.spreadsheet") {
+        ODS
+    } else if contains(b"mimetypeapplication/vnd.oasis.opendocument.presentation") {
+        ODP
+    } else if contains(b"word/") {
+        DOCX
+    } else if contains(b"xl/") {
+        XLSX
+    } else if contains(b"ppt/") {
+        PPTX
+    } else {
+        ZIP
+    }
+}
+
+/// ISO base media files: MP4, QuickTime, HEIF and AVIF share the `ftyp` box.
+fn detect_ftyp(b: &[u8]) -> Option<Detected> {
+    let brand = b.get(8..12)?;
+    match brand {
+        b"avif" | b"avis" => Some(AVIF),
+        b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1" => Some(HEIC),
+        b"qt  " => Some(MOV),
+        b"M4A " | b"M4B " => Some(M4A),
+        _ => Some(MP4),
+    }
+}
+
+fn detect_shebang(b: &[u8]) -> Option<Detected> {
+    let line = b.split(|&c| c == b'\n').next()?;
+    let line = String::from_utf8_lossy(&line[2..]);
+    let mut words = line.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|word| !word.starts_with('-'))?;
+    }
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    match interpreter {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" | "ash" => Some(SHELL),
+        "python" | "pypy" => Some(PYTHON),
+        "perl" => Some(PERL),
+        "ruby" => Some(RUBY),
+        "node" | "nodejs" | "deno" | "bun" => Some(NODE),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ext(b: &[u8]) -> Option<&'static str> {
+        detect(b).map(|detected| detected.ext)
+    }
+
+    #[test]
+    fn detects_by_magic() {
+        assert_eq!(ext(b"\x89PNG\r\n\x1a\n rest"), Some("png"));
+        assert_eq!(ext(b"\xff\xd8\xff\xe0"), Some("jpg"));
+        assert_eq!(ext(b"GIF89a"), Some("gif"));
+        assert_eq!(ext(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(ext(b"%PDF-1.7"), Some("pdf"));
+        assert_eq!(ext(b"\x1f\x8b\x08"), Some("gz"));
+        assert_eq!(ext(b"\0\0\0\x18ftypheic"), Some("heic"));
+        assert_eq!(ext(b"#!/usr/bin/env python3\n"), Some("py"));
+    }
+
+    #[test]
+    fn tells_zip_containers_apart() {
+        let mut docx = b"PK\x03\x04".to_vec();
+        docx.extend([0; 26]);
+        docx.extend(b"word/document.xml");
+        assert_eq!(ext(&docx), Some("docx"));
+        assert_eq!(ext(b"PK\x03\x04"), Some("zip"));
+    }
+
+    #[test]
+    fn leaves_unknown_and_short_content_alone() {
+        assert_eq!(ext(b""), None);
+        assert_eq!(ext(b"\x89PN"), None);
+        assert_eq!(ext(b"hello, world"), None);
+        assert!(!detect(b"\xff\xd8\xff").unwrap().matches_extension("png"));
+    }
+}
@@ -0,0 +1,30 @@
+//! What a plan would do, in numbers, as `tidyup stats` shows it.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::plan::Plan;
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// The files each category would receive, and their total size.
+    pub by_category: BTreeMap<String, (usize, u64)>,
+    /// How many files would be left in place, by reason.
+    pub by_reason: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    pub fn of(plan: &Plan) -> Stats {
+        let mut stats = Stats::default();
+        for (source, _, category, _) in plan.moves() {
+            let size = fs::symlink_metadata(source).map(|m| m.len()).unwrap_or(0);
+            let entry = stats.by_category.entry(category.to_string()).or_default();
+            entry.0 += 1;
+            entry.1 += size;
+        }
+        for (_, reason) in plan.skips() {
+            *stats.by_reason.entry(reason.to_string()).or_default() += 1;
+        }
+        stats
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, TimeZone};
+
+use crate::exif::{self, Exif};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A destination folder such as `images/{mtime:%Y}/{mtime:%m}`, relative to the target directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placeholder {
+    Category,
+    Ext,
+    Stem,
+    Mtime(String),
+    Ctime(String),
+    /// `empty`, `tiny`, `small`, `medium` or `large`.
+    Size,
+    Owner,
+    Group,
+    /// A capture group of the rule's `regex` condition, by number or name.
+    Regex(String),
+    /// The EXIF capture date, or the mtime without one.
+    Taken(String),
+    Make,
+    Model,
+    /// Make and model, e.g. `Apple iPhone 12`.
+    Camera,
+    Orientation,
+    /// `latitude,longitude` rounded to two decimals.
+    Gps,
+}
+
+/// What placeholders are filled in from.
+pub struct Vars<'a> {
+    pub path: &'a Path,
+    /// The extension the file was classified by.
+    pub extension: &'a str,
+    pub category: &'a str,
+    pub captures: HashMap<String, String>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed `{{` in `{}`", source)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(Placeholder::parse(&name)?));
+                }
+                '}' => return Err(format!("unmatched `}}` in `{}` (write `}}}}` for a literal brace)", source)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        let template = Template { source: source.to_string(), parts };
+        if !is_relative_inside(Path::new(&template.skeleton())) {
+            return Err(format!("`{}` must be a relative path inside the target directory", source));
+        }
+        Ok(template)
+    }
+
+    /// A template without placeholders, e.g. a category folder named after the category.
+    pub fn literal(path: &str) -> Template {
+        Template { source: path.to_string(), parts: vec![Part::Literal(path.to_string())] }
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = &Placeholder> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Placeholder(placeholder) => Some(placeholder),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// The leading folders that are the same for every file, e.g. `images`
+    /// for `images/{mtime:%Y}`. Empty when the template starts with a placeholder.
+    pub fn prefix(&self) -> PathBuf {
+        let mut prefix = PathBuf::new();
+        let mut literal = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => literal.push_str(text),
+                Part::Placeholder(_) => {
+                    // Only the components that ended before the placeholder are fixed.
+                    if let Some((fixed, _)) = literal.rsplit_once('/') {
+                        prefix.push(fixed);
+                    }
+                    return prefix;
+                }
+            }
+        }
+        prefix.push(literal);
+        prefix
+    }
+
+    /// Expands the placeholders. Values never contain `/` and are never `.`
+    /// or `..`, so the result stays below the target directory.
+    pub fn render(&self, vars: &Vars) -> Result<PathBuf, String> {
+        let mut metadata = None;
+        let mut exif = None;
+        let mut rendered = String::new();
+        for part in &self.parts {
+            let placeholder = match part {
+                Part::Literal(text) => {
+                    rendered.push_str(text);
+                    continue;
+                }
+                Part::Placeholder(placeholder) => placeholder,
+            };
+            let value = match placeholder {
+                Placeholder::Category => vars.category.to_string(),
+                Placeholder::Ext if vars.extension.is_empty() => "noext".to_string(),
+                Placeholder::Ext => vars.extension.to_string(),
+                Placeholder::Stem => vars.path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+                Placeholder::Regex(group) => match vars.captures.get(group) {
+                    Some(value) => value.clone(),
+                    None => return Err(format!("the rule's regex has no capture group `{}` for this file", group)),
+                },
+                _ if placeholder.uses_exif() => {
+                    let exif = exif.get_or_insert_with(|| exif::read(vars.path).ok().flatten());
+                    match (placeholder, exif.as_ref()) {
+                        (Placeholder::Taken(format), Some(exif)) if exif.taken.is_some() => match exif.taken_at() {
+                            Some(taken) => taken.format(format).to_string(),
+                            None => "unknown".to_string(),
+                        },
+                        (Placeholder::Taken(format), _) => {
+                            // No capture date: fall back to the mtime like a file manager would.
+                            let metadata = fs::symlink_metadata(vars.path).map_err(|e| e.to_string())?;
+                            format_time(metadata.mtime(), format)
+                        }
+                        (_, exif) => exif_value(placeholder, exif).unwrap_or_else(|| "unknown".to_string()),
+                    }
+                }
+                _ => {
+                    if metadata.is_none() {
+                        metadata = Some(fs::symlink_metadata(vars.path).map_err(|e| e.to_string())?);
+                    }
+                    metadata_value(placeholder, metadata.as_ref().unwrap())
+                }
+            };
+            rendered.push_str(&sanitize(&value));
+        }
+
+        let path = PathBuf::from(rendered);
+        if !is_relative_inside(&path) {
+            return Err(format!("`{}` expands to `{}`, which is outside the target directory", self.source, path.display()));
+        }
+        Ok(path)
+    }
+
+    /// The template with every placeholder replaced by a plain name, to check its shape.
+    fn skeleton(&self) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.as_str(),
+                Part::Placeholder(_) => "x",
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Placeholder {
+    fn uses_exif(&self) -> bool {
+        matches!(
+            self,
+            Placeholder::Taken(_) | Placeholder::Make | Placeholder::Model | Placeholder::Camera | Placeholder::Orientation | Placeholder::Gps
+        )
+    }
+
+    fn parse(text: &str) -> Result<Placeholder, String> {
+        let (name, argument) = match text.split_once(':') {
+            Some((name, argument)) => (name.trim(), Some(argument)),
+            None => (text.trim(), None),
+        };
+        let date_format = |argument: Option<&str>| {
+            let format = argument.unwrap_or(DEFAULT_DATE_FORMAT);
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                return Err(format!("invalid date format `{}`", format));
+            }
+            Ok(format.to_string())
+        };
+        let placeholder = match name {
+            "category" => Placeholder::Category,
+            "ext" => Placeholder::Ext,
+            "stem" => Placeholder::Stem,
+            "mtime" => return Ok(Placeholder::Mtime(date_format(argument)?)),
+            "taken" => return Ok(Placeholder::Taken(date_format(argument)?)),
+            "make" => Placeholder::Make,
+            "model" => Placeholder::Model,
+            "camera" => Placeholder::Camera,
+            "orientation" => Placeholder::Orientation,
+            "gps" => Placeholder::Gps,
+            "ctime" => return Ok(Placeholder::Ctime(date_format(argument)?)),
+            "size" => Placeholder::Size,
+            "owner" => Placeholder::Owner,
+            "group" => Placeholder::Group,
+            _ => match name.strip_prefix("regex.") {
+                Some(group) if !group.is_empty() => Placeholder::Regex(group.to_string()),
+                _ => {
+                    return Err(format!(
+                        "unknown placeholder `{{{}}}` (expected category, ext, stem, mtime, ctime, taken, size, owner, group, make, model, camera, orientation, gps or regex.N)",
+                        text
+                    ))
+                }
+            },
+        };
+        match argument {
+            Some(_) => Err(format!("placeholder `{{{}}}` takes no format", name)),
+            None => Ok(placeholder),
+        }
+    }
+}
+
+fn metadata_value(placeholder: &Placeholder, metadata: &fs::Metadata) -> String {
+    match placeholder {
+        Placeholder::Mtime(format) => format_time(metadata.mtime(), format),
+        Placeholder::Ctime(format) => format_time(metadata.ctime(), format),
+        Placeholder::Size => size_bucket(metadata.len()).to_string(),
+        Placeholder::Owner => user_name(metadata.uid()).unwrap_or_else(|| metadata.uid().to_string()),
+        Placeholder::Group => group_name(metadata.gid()).unwrap_or_else(|| metadata.gid().to_string()),
+        _ => String::new(),
+    }
+}
+
+fn exif_value(placeholder: &Placeholder, exif: Option<&Exif>) -> Option<String> {
+    let exif = exif?;
+    match placeholder {
+        Placeholder::Make => exif.make.clone(),
+        Placeholder::Model => exif.model.clone(),
+        Placeholder::Camera => exif.camera(),
+        Placeholder::Orientation => exif.orientation.map(|orientation| orientation.to_string()),
+        Placeholder::Gps => exif.gps.map(|(latitude, longitude)| format!("{:.2},{:.2}", latitude, longitude)),
+        _ => None,
+    }
+}
+
+fn format_time(seconds: i64, format: &str) -> String {
+    match Local.timestamp_opt(seconds, 0).earliest() {
+        Some(time) => time.format(format).to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+fn size_bucket(size: u64) -> &'static str {
+    match size {
+        0 => "empty",
+        s if s < 100 << 10 => "tiny",
+        s if s < 10 << 20 => "small",
+        s if s < 1 << 30 => "medium",
+        _ => "large",
+    }
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe { libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_string_lossy().into_owned())
+}
+
+fn group_name(gid: u32) -> Option<String> {
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let status = unsafe { libc::getgrgid_r(gid, &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(group.gr_name) }.to_string_lossy().into_owned())
+}
+
+/// Makes a placeholder value safe to use inside one path component.
+fn sanitize(value: &str) -> String {
+    let value: String = value.chars().map(|c| if c == '/' || c == '\0' { '_' } else { c }).collect();
+    match value.trim() {
+        "" | "." | ".." => "_".to_string(),
+        _ => value,
+    }
+}
+
+pub fn is_relative_inside(path: &Path) -> bool {
+    !path.as_os_str().is_empty() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(path: &Path) -> Vars<'_> {
+        Vars { path, extension: "jpg", category: "images", captures: HashMap::new() }
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let template = Template::parse("{category}/{ext}/{stem}").unwrap();
+        assert_eq!(template.render(&vars(Path::new("/tmp/IMG_1.jpg"))).unwrap(), Path::new("images/jpg/IMG_1"));
+        assert_eq!(template.prefix(), Path::new(""));
+        assert_eq!(Template::parse("media/{category}").unwrap().prefix(), Path::new("media"));
+    }
+
+    #[test]
+    fn escapes_braces() {
+        let template = Template::parse("a{{b}}").unwrap();
+        assert_eq!(template.render(&vars(Path::new("x.jpg"))).unwrap(), Path::new("a{b}"));
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(Template::parse("{category").is_err());
+        assert!(Template::parse("category}").is_err());
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("{mtime:%Q}").is_err());
+        assert!(Template::parse("{stem:%Y}").is_err());
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_target() {
+        assert!(Template::parse("..").is_err());
+        assert!(Template::parse("../images").is_err());
+        assert!(Template::parse("images/../..").is_err());
+        assert!(Template::parse("/images").is_err());
+        assert!(Template::parse("").is_err());
+        assert!(Template::parse("images/{ext}").is_ok());
+    }
+
+    #[test]
+    fn values_stay_inside_the_target() {
+        let template = Template::parse("{regex.1}").unwrap();
+        let mut vars = vars(Path::new("x.jpg"));
+        vars.captures.insert("1".to_string(), "..".to_string());
+        assert_eq!(template.render(&vars).unwrap(), Path::new("_"));
+        vars.captures.insert("1".to_string(), "../../etc".to_string());
+        assert_eq!(template.render(&vars).unwrap(), Path::new(".._.._etc"));
+        vars.captures.clear();
+        assert!(template.render(&vars).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{self as stdpath, Path, PathBuf};
+
+use chrono::Local;
+
+use crate::fsops::Mover;
+use crate::xdg;
+
+const MAX_SUFFIX: u32 = 10_000;
+
+/// Where a file went when it was put in the trash.
+#[derive(Debug)]
+pub struct Trashed {
+    pub file: PathBuf,
+    /// The `.trashinfo` file that lets a file manager restore it.
+    pub info: PathBuf,
+}
+
+/// Moves `path` into the trash, following the freedesktop.org trash
+/// specification, so that it can be restored from the desktop.
+///
+/// Files on the filesystem of the home trash (`$XDG_DATA_HOME/Trash`) go
+/// there. Files on other filesystems go to the trash at the top of their
+/// mount, `.Trash/$uid` or `.Trash-$uid`, so they are not copied across
+/// devices; the home trash is used when neither can be set up.
+pub fn trash(mover: &Mover, path: &Path) -> io::Result<Trashed> {
+    let path = stdpath::absolute(path)?;
+    let home = xdg::data_home()
+        .map(|dir| dir.join("Trash"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "cannot determine the trash; set XDG_DATA_HOME or HOME"))?;
+
+    let device = fs::symlink_metadata(&path)?.dev();
+    if device_of(&home) != Some(device) {
+        let top = mount_point(&path, device);
+        if let Some(trash_dir) = top_trash(&top) {
+            // Top directory trashes store paths relative to the mount.
+            let relative = path.strip_prefix(&top).unwrap_or(&path);
+            match put(mover, &trash_dir, &path, relative) {
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+                result => return result,
+            }
+        }
+    }
+    put(mover, &home, &path, &path)
+}
+
+/// Moves `path` into `trash_dir`, recording `recorded` as where it came from.
+fn put(mover: &Mover, trash_dir: &Path, path: &Path, recorded: &Path) -> io::Result<Trashed> {
+    let (files, info) = (trash_dir.join("files"), trash_dir.join("info"));
+    DirBuilder::new().recursive(true).mode(0o700).create(&files)?;
+    DirBuilder::new().recursive(true).mode(0o700).create(&info)?;
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("cannot trash {}", path.display())))?;
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(recorded),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    for n in 1..=MAX_SUFFIX {
+        let mut entry = name.to_os_string();
+        if n > 1 {
+            entry.push(format!(".{}", n));
+        }
+        let mut info_name = entry.clone();
+        info_name.push(".trashinfo");
+        let trashed = Trashed { file: files.join(&entry), info: info.join(info_name) };
+
+        // Creating the info file claims the name.
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&trashed.info) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let moved = file.write_all(contents.as_bytes()).and_then(|()| mover.move_noreplace(path, &trashed.file));
+        match moved {
+            Ok(()) => return Ok(trashed),
+            Err(e) => {
+                let _ = fs::remove_file(&trashed.info);
+                if e.kind() == io::ErrorKind::AlreadyExists {
+                    continue;
+                }
+                return Err(e);
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("no free name in the trash for {}", path.display())))
+}
+
+/// The trash at the top of a mount: `$top/.Trash/$uid` when the
+/// administrator set up a sticky, non-symlinked `.Trash`, `$top/.Trash-$uid` otherwise.
+fn top_trash(top: &Path) -> Option<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+    let shared = top.join(".Trash");
+    if let Ok(metadata) = fs::symlink_metadata(&shared) {
+        if metadata.is_dir() && metadata.mode() & libc::S_ISVTX != 0 {
+            let dir = shared.join(uid.to_string());
+            if ensure_own_dir(&dir, uid) {
+                return Some(dir);
+            }
+        }
+    }
+    let dir = top.join(format!(".Trash-{}", uid));
+    ensure_own_dir(&dir, uid).then_some(dir)
+}
+
+/// Creates `dir` if needed and checks that it is a real folder owned by `uid`.
+fn ensure_own_dir(dir: &Path, uid: u32) -> bool {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(_) => return false,
+    }
+    fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir() && metadata.uid() == uid)
+}
+
+/// The topmost folder above `path` that is still on `device`.
+fn mount_point(path: &Path, device: u64) -> PathBuf {
+    let mut top = path.parent().unwrap_or(path);
+    while let Some(parent) = top.parent() {
+        if device_of(parent) != Some(device) {
+            break;
+        }
+        top = parent;
+    }
+    top.to_path_buf()
+}
+
+/// The device `path` is on, or would be on once created.
+fn device_of(path: &Path) -> Option<u64> {
+    path.ancestors().find_map(|ancestor| fs::metadata(ancestor).ok()).map(|metadata| metadata.dev())
+}
+
+/// Escapes a path for the `Path=` key the way URLs are escaped, keeping `/`.
+fn percent_encode(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdir::{self, TestDir};
+
+    #[test]
+    fn trashes_into_the_home_trash_with_an_info_file() {
+        let home = testdir::xdg_home().join("data/Trash");
+        let dir = TestDir::new();
+        let path = dir.file("trash me 100%.txt", b"x");
+
+        let trashed = trash(&Mover::default(), &path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(trashed.file, home.join("files/trash me 100%.txt"));
+        assert_eq!(trashed.info, home.join("info/trash me 100%.txt.trashinfo"));
+        assert_eq!(fs::read(&trashed.file).unwrap(), b"x");
+
+        let info = fs::read_to_string(&trashed.info).unwrap();
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[0], "[Trash Info]");
+        assert_eq!(lines[1], format!("Path={}/trash%20me%20100%25.txt", dir.path().display()));
+        assert!(lines[2].starts_with("DeletionDate=") && lines[2].len() == "DeletionDate=2024-01-02T03:04:05".len());
+    }
+
+    #[test]
+    fn never_replaces_trashed_files() {
+        let dir = TestDir::new();
+        let first = trash(&Mover::default(), &dir.file("twice.txt", b"1")).unwrap();
+        let second = trash(&Mover::default(), &dir.file("twice.txt", b"2")).unwrap();
+        assert_eq!(second.file, first.file.with_file_name("twice.txt.2"));
+        assert_eq!(second.info, first.info.with_file_name("twice.txt.2.trashinfo"));
+        assert_eq!(fs::read(&first.file).unwrap(), b"1");
+        assert_eq!(fs::read(&second.file).unwrap(), b"2");
+    }
+
+    #[test]
+    fn percent_encodes_all_but_safe_bytes() {
+        assert_eq!(percent_encode(Path::new("/a/b-c_d.e~f")), "/a/b-c_d.e~f");
+        assert_eq!(percent_encode(Path::new("/x y/é#")), "/x%20y/%C3%A9%23");
+    }
+}
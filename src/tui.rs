@@ -0,0 +1,333 @@
+//! `--review`: the plan as a full-screen list, grouped by destination folder,
+//! in which entries can be switched off or sent elsewhere before it is applied.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use tidyup::fsops::Transfer;
+use tidyup::template;
+use tidyup::{Action, Plan};
+
+const KEYS: &str = "up/down move  space toggle  e change destination  a apply  q quit";
+
+/// Shows `plan` for review on the terminal and returns it as accepted, with
+/// the entries switched off turned into skips, or `None` if the user quit.
+pub fn review(plan: Plan) -> io::Result<Option<Plan>> {
+    let mut skips = Vec::new();
+    let mut items = Vec::new();
+    for action in plan.actions {
+        match action {
+            Action::Skip { .. } => skips.push(action),
+            action => items.push(Item { action, selected: true }),
+        }
+    }
+    let mut review = Review { root: plan.root.clone(), items, cursor: 0, scroll: 0, editing: None, message: String::new() };
+    review.sort();
+    review.cursor = 0;
+
+    let accepted = {
+        let _screen = Screen::enter()?;
+        review.run()?
+    };
+    if !accepted {
+        return Ok(None);
+    }
+
+    let mut actions = Vec::new();
+    for item in review.items {
+        if item.selected {
+            actions.push(item.action);
+        } else {
+            skips.extend(item.action.declined());
+        }
+    }
+    actions.extend(skips);
+    Ok(Some(Plan { actions, ..plan }))
+}
+
+struct Item {
+    action: Action,
+    selected: bool,
+}
+
+impl Item {
+    /// The heading the item is listed under.
+    fn group(&self, root: &Path) -> String {
+        match (&self.action, destination(&self.action)) {
+            (_, Some(destination)) => {
+                let folder = destination.parent().unwrap_or(root);
+                let folder = folder.strip_prefix(root).unwrap_or(folder);
+                format!("{}/", folder.display())
+            }
+            (Action::Trash { .. }, _) => "(trash)".to_string(),
+            _ => "(hard links)".to_string(),
+        }
+    }
+
+    fn line(&self, root: &Path) -> String {
+        let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).display().to_string();
+        let mark = if self.selected { "[x]" } else { "[ ]" };
+        match &self.action {
+            Action::Move { source, destination, transfer, .. } => {
+                format!("{} {}  <- {}{}", mark, name(destination), relative(source), transfer_note(*transfer))
+            }
+            Action::Bundle { members, transfer, .. } => format!(
+                "{} {} +{}  <- {}{}",
+                mark,
+                name(&members[0].1),
+                members.len() - 1,
+                relative(&members[0].0),
+                transfer_note(*transfer)
+            ),
+            Action::Trash { path, reason } => format!("{} {}  ({})", mark, relative(path), reason),
+            Action::Link { path, original } => format!("{} {}  => {}", mark, relative(path), relative(original)),
+            Action::Skip { path, .. } => relative(path),
+        }
+    }
+}
+
+fn destination(action: &Action) -> Option<&Path> {
+    match action {
+        Action::Move { destination, .. } => Some(destination),
+        Action::Bundle { members, .. } => Some(&members[0].1),
+        _ => None,
+    }
+}
+
+fn name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+fn transfer_note(transfer: Transfer) -> String {
+    match transfer {
+        Transfer::Move => String::new(),
+        transfer => format!(" ({})", transfer),
+    }
+}
+
+enum Row {
+    Group(String, usize),
+    Item(usize),
+}
+
+struct Review {
+    root: PathBuf,
+    items: Vec<Item>,
+    /// Index into `items`, which are kept in display order.
+    cursor: usize,
+    /// The first row on screen.
+    scroll: usize,
+    /// The destination being typed, while changing one.
+    editing: Option<String>,
+    message: String,
+}
+
+impl Review {
+    /// Puts the items in display order, keeping the cursor on its item.
+    fn sort(&mut self) {
+        let root = &self.root;
+        let mut keyed: Vec<(String, Item)> = self.items.drain(..).map(|item| (item.group(root), item)).collect();
+        let current = keyed.get(self.cursor).map(|(_, item)| item.action.path().to_path_buf());
+        keyed.sort_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.action.path().cmp(y.action.path())));
+        self.cursor = current
+            .and_then(|current| keyed.iter().position(|(_, item)| item.action.path() == current))
+            .unwrap_or(0);
+        self.items = keyed.into_iter().map(|(_, item)| item).collect();
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        let mut current: Option<String> = None;
+        for (index, item) in self.items.iter().enumerate() {
+            let group = item.group(&self.root);
+            if current.as_ref() != Some(&group) {
+                let count = self.items[index..].iter().take_while(|other| other.group(&self.root) == group).count();
+                rows.push(Row::Group(group.clone(), count));
+                current = Some(group);
+            }
+            rows.push(Row::Item(index));
+        }
+        rows
+    }
+
+    /// Handles keys until the user applies (`true`) or quits (`false`).
+    fn run(&mut self) -> io::Result<bool> {
+        if self.items.is_empty() {
+            self.message = "Nothing to do.".to_string();
+        }
+        loop {
+            self.draw()?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+                return Ok(false);
+            }
+            if self.editing.is_some() {
+                self.edit(key);
+                continue;
+            }
+            self.message.clear();
+            let page = terminal::size()?.1.saturating_sub(3).max(1) as usize;
+            let last = self.items.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => self.cursor = (self.cursor + 1).min(last),
+                KeyCode::PageUp => self.cursor = self.cursor.saturating_sub(page),
+                KeyCode::PageDown => self.cursor = (self.cursor + page).min(last),
+                KeyCode::Home => self.cursor = 0,
+                KeyCode::End => self.cursor = last,
+                KeyCode::Char(' ') => {
+                    if let Some(item) = self.items.get_mut(self.cursor) {
+                        item.selected = !item.selected;
+                    }
+                }
+                KeyCode::Char('e') | KeyCode::Enter => {
+                    if let Some(item) = self.items.get(self.cursor) {
+                        match destination(&item.action) {
+                            Some(destination) => {
+                                let relative = destination.strip_prefix(&self.root).unwrap_or(destination);
+                                self.editing = Some(relative.display().to_string());
+                            }
+                            None => self.message = "Only moved, copied or linked files have a destination.".to_string(),
+                        }
+                    }
+                }
+                KeyCode::Char('a') => return Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                _ => self.message = KEYS.to_string(),
+            }
+        }
+    }
+
+    fn edit(&mut self, key: KeyEvent) {
+        let Some(buffer) = self.editing.as_mut() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Char(c) => buffer.push(c),
+            KeyCode::Backspace => {
+                buffer.pop();
+            }
+            KeyCode::Esc => self.editing = None,
+            KeyCode::Enter => {
+                let typed = buffer.trim().to_string();
+                self.editing = None;
+                if typed.is_empty() || typed.ends_with('/') {
+                    self.message = "The destination must name a file.".to_string();
+                    return;
+                }
+                if !template::is_relative_inside(Path::new(&typed)) {
+                    self.message = "The destination must be a relative path inside the directory.".to_string();
+                    return;
+                }
+                let destination = self.root.join(typed);
+                if let Some(item) = self.items.get_mut(self.cursor) {
+                    redirect(&mut item.action, destination);
+                    item.selected = true;
+                }
+                self.sort();
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (width as usize, height as usize);
+        let rows = self.rows();
+        let visible = height.saturating_sub(2).max(1);
+        let cursor_row = rows.iter().position(|row| matches!(row, Row::Item(index) if *index == self.cursor)).unwrap_or(0);
+        if cursor_row < self.scroll {
+            // Keep the group heading in view when moving up to its first item.
+            self.scroll = cursor_row.saturating_sub(1);
+        } else if cursor_row >= self.scroll + visible {
+            self.scroll = cursor_row + 1 - visible;
+        }
+
+        let mut out = io::stderr();
+        queue!(out, Clear(ClearType::All), MoveTo(0, 0))?;
+        let selected = self.items.iter().filter(|item| item.selected).count();
+        let title = format!("tidyup review: {} of {} selected   {}", selected, self.items.len(), KEYS);
+        queue!(out, SetAttribute(Attribute::Bold), Print(clip(&title, width)), SetAttribute(Attribute::Reset))?;
+
+        for (line, row) in rows.iter().skip(self.scroll).take(visible).enumerate() {
+            queue!(out, MoveTo(0, line as u16 + 1))?;
+            match row {
+                Row::Group(group, count) => {
+                    let text = format!("{} ({})", group, count);
+                    queue!(out, SetAttribute(Attribute::Bold), Print(clip(&text, width)), SetAttribute(Attribute::Reset))?;
+                }
+                Row::Item(index) => {
+                    let text = format!("  {}", self.items[*index].line(&self.root));
+                    if *index == self.cursor {
+                        queue!(out, SetAttribute(Attribute::Reverse), Print(clip(&text, width)), SetAttribute(Attribute::Reset))?;
+                    } else {
+                        queue!(out, Print(clip(&text, width)))?;
+                    }
+                }
+            }
+        }
+
+        queue!(out, MoveTo(0, height.saturating_sub(1) as u16))?;
+        match &self.editing {
+            Some(buffer) => queue!(out, Print(clip(&format!("Destination: {}", buffer), width)))?,
+            None => queue!(out, Print(clip(&self.message, width)))?,
+        }
+        out.flush()
+    }
+}
+
+/// Sends a move, or a bundle with its companions, to `destination`.
+fn redirect(action: &mut Action, destination: PathBuf) {
+    match action {
+        Action::Move { destination: current, .. } => *current = destination,
+        Action::Bundle { members, .. } => {
+            // Companions keep sharing the primary's stem if it is renamed.
+            let folder = destination.parent().map(Path::to_path_buf).unwrap_or_default();
+            let old_stem = members[0].1.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let new_stem = destination.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            for (_, target) in members.iter_mut().skip(1) {
+                let name = target.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                let name = match name.strip_prefix(&old_stem) {
+                    Some(rest) => format!("{}{}", new_stem, rest),
+                    None => name,
+                };
+                *target = folder.join(name);
+            }
+            members[0].1 = destination;
+        }
+        _ => {}
+    }
+}
+
+fn clip(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Raw mode on the alternate screen, for as long as it lives.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stderr(), EnterAlternateScreen, Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,195 @@
+//! Views: parallel trees of symlinks into a directory that stays untouched.
+//!
+//! A view is planned like a run with [`Transfer::Symlink`](crate::fsops::Transfer)
+//! and a separate target; regenerating it only adds the missing links and
+//! removes the [`stale`] ones. The links a view made are listed in its
+//! [`MANIFEST`], so that links the user put there are never touched.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{self as stdpath, Path, PathBuf};
+
+use crate::conflict::OnConflict;
+use crate::error::Error;
+use crate::execute::Executor;
+use crate::fsops::{self, Mover};
+use crate::plan::{Plan, SkipReason};
+use crate::report::{Event, Failure, Reporter, Summary};
+
+/// The file in the top folder of a view that lists its links, one path
+/// relative to that folder per line.
+pub const MANIFEST: &str = ".tidyup-view";
+
+/// Brings the view at `into` up to date with `plan`: removes the `stale`
+/// links, makes the missing ones and lists them all in the manifest.
+/// Returns the summary and the id of the run, if one was recorded.
+pub fn update(plan: Plan, into: &Path, stale: &[PathBuf], reporter: &mut dyn Reporter) -> Result<(Summary, Option<String>), Error> {
+    let (unlinked, failures) = remove(stale, into, reporter);
+    let mut links: Vec<PathBuf> = wanted(&plan).into_iter().collect();
+    let executor = Executor { mover: Mover::default(), on_conflict: OnConflict::Rename };
+    // Links renamed on a clash end up somewhere else than planned.
+    let (mut summary, run_id) = executor.run(plan, &mut |event: Event<'_>| {
+        if let Event::Copied { destination, .. } = event {
+            links.push(destination.to_path_buf());
+        }
+        reporter.report(event);
+    })?;
+    summary.unlinked = unlinked;
+    summary.failures.extend(failures);
+    if let Err(error) = record(into, links) {
+        let path = into.join(MANIFEST);
+        let error = io::Error::new(error.kind(), format!("cannot write the view manifest: {}", error));
+        reporter.report(Event::Failed { path: &path, error: &error });
+        summary.failures.push(Failure { path, error });
+    }
+    Ok((summary, run_id))
+}
+
+/// The links `plan` wants below its target: the planned ones and those already in place.
+pub fn wanted(plan: &Plan) -> HashSet<PathBuf> {
+    let mut wanted: HashSet<PathBuf> = plan.moves().map(|(_, destination, _, _)| destination.to_path_buf()).collect();
+    wanted.extend(plan.skips().filter_map(|(_, reason)| match reason {
+        SkipReason::AlreadyPlaced(placed) => Some(placed.clone()),
+        _ => None,
+    }));
+    wanted
+}
+
+/// The links of the view at `into` that `plan` no longer accounts for:
+/// those listed in the manifest that point into the plan's root, at files
+/// that are gone, now belong elsewhere or were not selected.
+pub fn stale(plan: &Plan, into: &Path) -> io::Result<Vec<PathBuf>> {
+    let wanted = wanted(plan);
+    let roots = [fs::canonicalize(&plan.root)?, fsops::normalize(&stdpath::absolute(&plan.root)?)];
+
+    let mut stale = Vec::new();
+    for link in manifest(into)? {
+        if wanted.contains(&link) || !fs::symlink_metadata(&link).is_ok_and(|metadata| metadata.is_symlink()) {
+            continue;
+        }
+        let target = fs::read_link(&link)?;
+        let dir = stdpath::absolute(&link)?.parent().map(Path::to_path_buf).unwrap_or_default();
+        let target = fsops::normalize(&dir.join(target));
+        if roots.iter().any(|root| target.starts_with(root)) {
+            stale.push(link);
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+/// The links listed in the manifest of the view at `into`.
+pub fn manifest(into: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    let text = match fs::read_to_string(into.join(MANIFEST)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(text.lines().filter(|line| !line.is_empty()).map(|line| into.join(line)).collect())
+}
+
+/// Adds `links` to the manifest of the view at `into`, and drops the
+/// entries that are no longer symlinks.
+pub fn record(into: &Path, links: impl IntoIterator<Item = PathBuf>) -> io::Result<()> {
+    let mut listed = manifest(into)?;
+    listed.extend(links);
+    let mut text = String::new();
+    for link in listed {
+        let Ok(relative) = link.strip_prefix(into) else {
+            continue;
+        };
+        if fs::symlink_metadata(&link).is_ok_and(|metadata| metadata.is_symlink()) {
+            if let Some(line) = relative.to_str().filter(|line| !line.contains('\n')) {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    if text.is_empty() && !into.exists() {
+        return Ok(());
+    }
+    let mut file = fs::File::create(into.join(MANIFEST))?;
+    file.write_all(text.as_bytes())
+}
+
+/// Removes the `links`, then the folders below `into` they leave empty.
+pub fn remove(links: &[PathBuf], into: &Path, reporter: &mut dyn Reporter) -> (usize, Vec<Failure>) {
+    let mut removed = 0;
+    let mut failures = Vec::new();
+    for link in links {
+        if let Err(error) = fs::remove_file(link) {
+            reporter.report(Event::Failed { path: link, error: &error });
+            failures.push(Failure { path: link.clone(), error });
+            continue;
+        }
+        removed += 1;
+        reporter.report(Event::Unlinked(link));
+        for dir in link.ancestors().skip(1).take_while(|dir| *dir != into && dir.starts_with(into)) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            reporter.report(Event::RemovedDir(dir));
+        }
+    }
+    (removed, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsops::Transfer;
+    use crate::plan::Action;
+    use crate::testdir::TestDir;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn removes_only_its_own_links_the_plan_no_longer_wants() {
+        let dir = TestDir::new();
+        let (root, into) = (dir.path().join("downloads"), dir.path().join("view"));
+        let kept = dir.file("downloads/a.jpg", b"x");
+        let moved_on = dir.file("downloads/b.jpg", b"x");
+        let elsewhere = dir.file("elsewhere.txt", b"x");
+        dir.file("view/notes.txt", b"x");
+        fs::create_dir_all(into.join("images")).unwrap();
+        fs::create_dir_all(into.join("old/2023")).unwrap();
+        let wanted = into.join("images/a.jpg");
+        symlink(&kept, &wanted).unwrap();
+        symlink(&moved_on, into.join("images/b.jpg")).unwrap();
+        symlink(root.join("gone.jpg"), into.join("old/2023/gone.jpg")).unwrap();
+        symlink(&elsewhere, into.join("mine.txt")).unwrap();
+        let users = into.join("images/users.jpg");
+        symlink(&moved_on, &users).unwrap();
+        let made = ["images/a.jpg", "images/b.jpg", "old/2023/gone.jpg", "mine.txt"].map(|link| into.join(link));
+        record(&into, made).unwrap();
+
+        let destination = wanted.clone();
+        let transfer = Transfer::Symlink { absolute: true };
+        let actions = vec![Action::Move { source: kept, destination, category: "images".to_string(), transfer }];
+        let plan = Plan { root, actions, ..Plan::default() };
+        let links = stale(&plan, &into).unwrap();
+        assert_eq!(links, [into.join("images/b.jpg"), into.join("old/2023/gone.jpg")]);
+
+        let mut removed_dirs = Vec::new();
+        let (removed, failures) = remove(&links, &into, &mut |event: Event| {
+            if let Event::RemovedDir(dir) = event {
+                removed_dirs.push(dir.to_path_buf());
+            }
+        });
+        assert_eq!((removed, failures.len()), (2, 0));
+        assert_eq!(removed_dirs, [into.join("old/2023"), into.join("old")]);
+        assert!(wanted.is_symlink() && users.is_symlink());
+        assert!(into.join("mine.txt").is_symlink() && into.join("notes.txt").is_file());
+        assert!(stale(&plan, &into).unwrap().is_empty());
+
+        record(&into, []).unwrap();
+        assert_eq!(manifest(&into).unwrap(), BTreeSet::from([wanted, into.join("mine.txt")]));
+    }
+
+    #[test]
+    fn a_missing_view_has_nothing_stale() {
+        let dir = TestDir::new();
+        let plan = Plan { root: dir.path().to_path_buf(), ..Plan::default() };
+        assert!(stale(&plan, &dir.path().join("view")).unwrap().is_empty());
+    }
+}
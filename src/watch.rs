@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+use crate::execute::Executor;
+use crate::fsops;
+use crate::plan::{Action, Plan, Planner};
+use crate::report::{self, Failure, Reporter, Summary};
+
+/// Suffixes browsers and download managers use for files still being written.
+const PARTIAL_SUFFIXES: &[&str] = &["part", "crdownload", "download", "partial", "opdownload"];
+
+const EVENT_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_ATTRIB
+    | libc::IN_MODIFY;
+
+pub struct WatchOptions {
+    pub settle: Duration,
+    pub debounce: Duration,
+    pub poll: bool,
+    pub poll_interval: Duration,
+}
+
+pub struct Watcher<'a> {
+    pub options: WatchOptions,
+    pub planner: &'a Planner<'a>,
+    pub executor: Executor,
+}
+
+/// Receives what a [`Watcher`] does, pass by pass, besides the events.
+pub trait Observer: Reporter {
+    /// A pass is about to carry out `plan`.
+    fn planned(&mut self, plan: &Plan);
+    /// A pass that changed something or failed somewhere is done.
+    fn finished(&mut self, root: &Path, run_id: Option<&str>, summary: &Summary);
+    /// A pass found nothing to do but could not inspect these entries.
+    fn failed(&mut self, failures: &[Failure]);
+    /// Something the watch gets by without, such as inotify.
+    fn warn(&mut self, message: &str);
+}
+
+/// Size and mtime of a candidate, and since when they have been unchanged.
+struct Seen {
+    size: u64,
+    mtime: (i64, i64),
+    since: Instant,
+}
+
+/// What the watcher remembers between passes.
+#[derive(Default)]
+struct Memory {
+    seen: HashMap<PathBuf, Seen>,
+    /// Sources the watcher moved, copied or linked itself, and until when
+    /// the events about them are its own.
+    own_moves: HashMap<PathBuf, Instant>,
+    /// Sources the conflict policy left in place, with their size and mtime
+    /// then; they are not tried again until they change.
+    kept: HashMap<PathBuf, (u64, (i64, i64))>,
+}
+
+impl Watcher<'_> {
+    /// Tidies the root now and then every time something changes, until
+    /// killed. A pass that fails is reported and tried again after the poll
+    /// interval; only failing to wait for events stops the watch.
+    pub fn run(&self, observer: &mut dyn Observer) -> Result<(), Error> {
+        let mut inotify = if self.options.poll {
+            None
+        } else {
+            match Inotify::new() {
+                Ok(inotify) => Some(inotify),
+                Err(e) => {
+                    observer.warn(&format!("inotify unavailable ({}), polling every {:?}", e, self.options.poll_interval));
+                    None
+                }
+            }
+        };
+
+        let mut memory = Memory::default();
+        let mut next_pass = Some(Instant::now());
+
+        loop {
+            let now = Instant::now();
+            if next_pass.is_some_and(|at| at <= now) {
+                // A failed pass, such as a journal that cannot be written,
+                // is tried again later rather than ending the watch.
+                let pass = match self.pass(&mut memory, observer) {
+                    Ok(pass) => pass,
+                    Err(e) => {
+                        let retry = self.options.poll_interval;
+                        observer.warn(&format!("cannot tidy {} ({}), trying again in {:?}", self.planner.root().display(), e, retry));
+                        next_pass = Some(Instant::now() + retry);
+                        continue;
+                    }
+                };
+                next_pass = pass.recheck_at;
+
+                if let Some(watcher) = &mut inotify {
+                    for dir in &pass.scanned_dirs {
+                        if let Err(e) = watcher.add(dir) {
+                            observer.warn(&format!("cannot watch {} ({}), falling back to polling", dir.display(), e));
+                            inotify = None;
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let timeout = next_pass.map(|at| at.saturating_duration_since(now));
+            match &mut inotify {
+                Some(watcher) => {
+                    let events = watcher.wait(timeout)?;
+                    let now = Instant::now();
+                    memory.own_moves.retain(|_, until| *until > now);
+                    let relevant = events.iter().filter(|event| !self.is_own(event, &mut memory.own_moves)).count();
+                    if relevant > 0 {
+                        let debounced = Instant::now() + self.options.debounce;
+                        next_pass = Some(next_pass.map_or(debounced, |at| at.max(debounced)));
+                    }
+                }
+                None => {
+                    let interval = self.options.poll_interval;
+                    thread::sleep(timeout.map_or(interval, |t| t.min(interval)));
+                    next_pass = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    fn is_own(&self, event: &Event, own_moves: &mut HashMap<PathBuf, Instant>) -> bool {
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            return false;
+        }
+        if own_moves.contains_key(&event.path) {
+            if event.mask & (libc::IN_MOVED_FROM | libc::IN_DELETE) != 0 {
+                own_moves.remove(&event.path);
+            }
+            return true;
+        }
+        // Category folders and rule destinations we create, or writes into them when they sit below a watched folder.
+        self.planner.target_dirs().iter().any(|dir| event.path.starts_with(dir))
+    }
+
+    fn pass(&self, memory: &mut Memory, observer: &mut dyn Observer) -> Result<Pass, Error> {
+        let root = self.planner.root();
+        let mut plan = self
+            .planner
+            .plan()
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot scan {}: {}", root.display(), e)))?;
+
+        let now = Instant::now();
+        let mut recheck_at: Option<Instant> = None;
+        let mut recheck = |at: Instant| recheck_at = Some(recheck_at.map_or(at, |current| current.min(at)));
+
+        memory.kept.retain(|path, stamp| stamp_of(path) == Some(*stamp));
+        plan.actions.retain(|action| match action {
+            Action::Move { .. } | Action::Bundle { .. } => !memory.kept.contains_key(action.path()),
+            _ => true,
+        });
+
+        let seen = &mut memory.seen;
+        let candidates: HashSet<PathBuf> = plan.moves().map(|(source, _, _, _)| source.to_path_buf()).collect();
+        seen.retain(|path, _| candidates.contains(path));
+
+        // Only moves of settled files are carried out; skips are not worth reporting on every pass.
+        let mut settled = |source: &PathBuf| {
+            if is_downloading(source) {
+                recheck(now + self.options.settle);
+                return false;
+            }
+            let Some((size, mtime)) = stamp_of(source) else {
+                return false;
+            };
+            match seen.get(source) {
+                Some(entry) if entry.size == size && entry.mtime == mtime => {
+                    let ready_at = entry.since + self.options.settle;
+                    if ready_at <= now {
+                        true
+                    } else {
+                        recheck(ready_at);
+                        false
+                    }
+                }
+                _ => {
+                    seen.insert(source.clone(), Seen { size, mtime, since: now });
+                    recheck(now + self.options.settle);
+                    false
+                }
+            }
+        };
+        plan.actions.retain(|action| match action {
+            Action::Move { source, .. } => settled(source),
+            // Look at every member, so that each one's clock starts on this pass.
+            Action::Bundle { members, .. } => members.iter().filter(|(source, _)| settled(source)).count() == members.len(),
+            Action::Trash { path, .. } | Action::Link { path, .. } => settled(path),
+            Action::Skip { .. } => false,
+        });
+
+        if plan.is_empty() {
+            observer.failed(&plan.failures);
+            return Ok(Pass { recheck_at, scanned_dirs: plan.scanned_dirs });
+        }
+        observer.planned(&plan);
+        let scanned_dirs = std::mem::take(&mut plan.scanned_dirs);
+
+        let sources: Vec<PathBuf> = plan
+            .actions
+            .iter()
+            .flat_map(|action| match action {
+                Action::Bundle { members, .. } => members.iter().map(|(source, _)| source.clone()).collect(),
+                action => vec![action.path().to_path_buf()],
+            })
+            .collect();
+        for source in &sources {
+            memory.seen.remove(source);
+        }
+        let mut kept = Vec::new();
+        let (summary, run_id) = self.executor.run(plan, &mut |event: report::Event<'_>| {
+            if let report::Event::Kept { path, .. } = event {
+                kept.push(path.to_path_buf());
+            }
+            observer.report(event);
+        })?;
+
+        // Copies and links leave the source in place, so no event ever ends
+        // their entry; they expire once the events of this pass are in.
+        let until = Instant::now() + self.options.settle + self.options.debounce;
+        memory.own_moves.extend(sources.into_iter().map(|source| (source, until)));
+        for path in kept {
+            if let Some(stamp) = stamp_of(&path) {
+                memory.kept.insert(path, stamp);
+            }
+        }
+        if summary.moved + summary.copied + summary.linked + summary.trashed > 0 || !summary.failures.is_empty() {
+            observer.finished(root, run_id.as_deref(), &summary);
+        }
+        Ok(Pass { recheck_at, scanned_dirs })
+    }
+}
+
+struct Pass {
+    recheck_at: Option<Instant>,
+    scanned_dirs: Vec<PathBuf>,
+}
+
+/// The size and mtime of `path`, to tell whether it changed.
+fn stamp_of(path: &Path) -> Option<(u64, (i64, i64))> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    Some((metadata.len(), (metadata.mtime(), metadata.mtime_nsec())))
+}
+
+/// A file is still arriving while a `NAME.part`-style sibling exists.
+fn is_downloading(path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return false;
+    };
+    PARTIAL_SUFFIXES.iter().any(|suffix| {
+        let mut sibling = name.to_os_string();
+        sibling.push(".");
+        sibling.push(suffix);
+        path.with_file_name(sibling).exists()
+    })
+}
+
+struct Event {
+    path: PathBuf,
+    mask: u32,
+}
+
+struct Inotify {
+    fd: OwnedFd,
+    watches: HashMap<i32, PathBuf>,
+}
+
+impl Inotify {
+    fn new() -> io::Result<Inotify> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify { fd: unsafe { OwnedFd::from_raw_fd(fd) }, watches: HashMap::new() })
+    }
+
+    fn add(&mut self, dir: &Path) -> io::Result<()> {
+        if self.watches.values().any(|watched| watched == dir) {
+            return Ok(());
+        }
+        let path = fsops::cstring(dir)?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), EVENT_MASK | libc::IN_ONLYDIR) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches.insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    /// Blocks until events arrive or `timeout` passes, and returns what was read.
+    fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<Event>> {
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        let mut pollfd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let ready = unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            return if e.kind() == io::ErrorKind::Interrupted { Ok(Vec::new()) } else { Err(e) };
+        }
+
+        let mut events = Vec::new();
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let n = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::Interrupted {
+                    break;
+                }
+                return Err(e);
+            }
+            if n == 0 {
+                break;
+            }
+            self.parse(&buffer[..n as usize], &mut events);
+        }
+        Ok(events)
+    }
+
+    fn parse(&mut self, mut buffer: &[u8], events: &mut Vec<Event>) {
+        const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+        while buffer.len() >= HEADER {
+            let event: libc::inotify_event = unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::inotify_event) };
+            let end = HEADER + event.len as usize;
+            let name = &buffer[HEADER..end.min(buffer.len())];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            buffer = &buffer[end.min(buffer.len())..];
+
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                continue;
+            }
+            let dir = self.watches.get(&event.wd).cloned().unwrap_or_default();
+            let path = if name.is_empty() { dir } else { dir.join(OsStr::from_bytes(name)) };
+            events.push(Event { path, mask: event.mask });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, DEFAULT_CONFIG};
+    use crate::plan::PlanOptions;
+    use crate::testdir::TestDir;
+
+    /// Counts what a pass tells its observer.
+    #[derive(Default)]
+    struct Recorder {
+        planned: usize,
+        run_ids: Vec<Option<String>>,
+    }
+
+    impl Reporter for Recorder {
+        fn report(&mut self, _: report::Event<'_>) {}
+    }
+
+    impl Observer for Recorder {
+        fn planned(&mut self, _: &Plan) {
+            self.planned += 1;
+        }
+
+        fn finished(&mut self, _: &Path, run_id: Option<&str>, _: &Summary) {
+            self.run_ids.push(run_id.map(str::to_string));
+        }
+
+        fn failed(&mut self, _: &[Failure]) {}
+
+        fn warn(&mut self, _: &str) {}
+    }
+
+    fn with_watcher(root: &Path, settle: Duration, test: impl FnOnce(&Watcher)) {
+        let config = Config::parse(DEFAULT_CONFIG, None).unwrap();
+        let planner = Planner::new(&config, root, PlanOptions::default()).unwrap();
+        let watcher = Watcher {
+            options: WatchOptions { settle, debounce: Duration::ZERO, poll: true, poll_interval: Duration::ZERO },
+            planner: &planner,
+            executor: Executor::default(),
+        };
+        test(&watcher);
+    }
+
+    #[test]
+    fn waits_while_a_partial_download_exists() {
+        let dir = TestDir::new();
+        let zip = dir.file("a.zip", b"x");
+        assert!(!is_downloading(&zip));
+        dir.file("a.zip.crdownload", b"");
+        assert!(is_downloading(&zip));
+    }
+
+    #[test]
+    fn moves_nothing_before_files_settle() {
+        let dir = TestDir::new();
+        let photo = dir.file("photo.jpg", b"x");
+        let started = Instant::now();
+        with_watcher(dir.path(), Duration::from_secs(3600), |watcher| {
+            let (mut memory, mut recorder) = (Memory::default(), Recorder::default());
+            let pass = watcher.pass(&mut memory, &mut recorder).unwrap();
+            assert!(pass.recheck_at.unwrap() >= started + watcher.options.settle);
+            let since = memory.seen[&photo].since;
+
+            let pass = watcher.pass(&mut memory, &mut recorder).unwrap();
+            assert!(pass.recheck_at.is_some());
+            assert_eq!(memory.seen[&photo].since, since);
+
+            fs::write(&photo, b"grown").unwrap();
+            watcher.pass(&mut memory, &mut recorder).unwrap();
+            assert!(memory.seen[&photo].since > since);
+
+            fs::remove_file(&photo).unwrap();
+            let pass = watcher.pass(&mut memory, &mut recorder).unwrap();
+            assert!(pass.recheck_at.is_none());
+            assert!(memory.seen.is_empty() && memory.own_moves.is_empty());
+            assert_eq!(recorder.planned, 0);
+        });
+        assert!(photo.with_file_name("images").read_dir().is_err());
+    }
+
+    #[test]
+    fn records_back_to_back_passes_as_separate_runs() {
+        crate::testdir::xdg_home();
+        let dir = TestDir::new();
+        with_watcher(dir.path(), Duration::ZERO, |watcher| {
+            let (mut memory, mut recorder) = (Memory::default(), Recorder::default());
+            for name in ["a.jpg", "b.jpg", "c.jpg"] {
+                let photo = dir.file(name, b"x");
+                // The first pass sees the file, the second moves it.
+                watcher.pass(&mut memory, &mut recorder).unwrap();
+                watcher.pass(&mut memory, &mut recorder).unwrap();
+                assert!(!photo.exists());
+            }
+            let run_ids: HashSet<_> = recorder.run_ids.iter().map(|run_id| run_id.as_deref().unwrap()).collect();
+            assert_eq!((recorder.run_ids.len(), run_ids.len()), (3, 3));
+        });
+    }
+
+    #[test]
+    fn ignores_its_own_moves() {
+        let dir = TestDir::new();
+        with_watcher(dir.path(), Duration::ZERO, |watcher| {
+            let source = dir.path().join("photo.jpg");
+            let event = |path: &Path, mask| Event { path: path.to_path_buf(), mask };
+            let mut own_moves = HashMap::from([(source.clone(), Instant::now() + Duration::from_secs(3600))]);
+
+            assert!(watcher.is_own(&event(&source, libc::IN_CLOSE_WRITE), &mut own_moves));
+            assert_eq!(own_moves.len(), 1);
+            assert!(watcher.is_own(&event(&source, libc::IN_MOVED_FROM), &mut own_moves));
+            assert!(own_moves.is_empty());
+            assert!(!watcher.is_own(&event(&source, libc::IN_MOVED_FROM), &mut own_moves));
+
+            let moved = dir.path().join("images/photo.jpg");
+            assert!(watcher.is_own(&event(&moved, libc::IN_MOVED_TO), &mut own_moves));
+            assert!(!watcher.is_own(&event(&moved, libc::IN_Q_OVERFLOW), &mut own_moves));
+        });
+    }
+}